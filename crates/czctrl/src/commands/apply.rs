@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, Result};
 use clap::Parser;
@@ -6,6 +6,7 @@ use libcz::{state::State, vruntime::DVRuntime, ControlZone};
 
 use crate::{
     commands::{create::create_inner, start::start_inner},
+    config::DEFAULT_WAIT_TIMEOUT,
     GloablOpts,
 };
 
@@ -17,6 +18,10 @@ pub struct Apply {
     #[arg(short, long)]
     wait: bool,
 
+    /// Seconds to wait for Vm Boot
+    #[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT)]
+    wait_timeout: u64,
+
     /// asign static ip and using bridge network
     #[arg(short, long)]
     ip: Option<String>,
@@ -28,18 +33,27 @@ pub struct Apply {
 
 pub fn apply(args: Apply, global_opts: &GloablOpts) -> Result<()> {
     let mut new_cz = ControlZone::new_from_config(&args.file)?;
+    let wait = args.wait.then(|| Duration::from_secs(args.wait_timeout));
     let vruntime: DVRuntime = global_opts.vruntime.into();
     match new_cz.state {
         State::Pending => {
-            create_inner(&mut new_cz)?;
-            start_inner(&mut new_cz, args.wait, &vruntime)
+            create_inner(&mut new_cz, global_opts.lock_wait())?;
+            let _lock = new_cz.lock(global_opts.lock_wait())?;
+            start_inner(&mut new_cz, wait, global_opts.lock_wait(), &vruntime)
         }
         _ => {
             let full_config = PathBuf::from(&new_cz.meta.full_config);
             let mut curr_cz = libcz::ControlZone::new_from_full_config(&full_config)
                 .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
 
-            update_innner(&mut curr_cz, new_cz, args.wait, &vruntime)
+            let _lock = curr_cz.lock(global_opts.lock_wait())?;
+            update_innner(
+                &mut curr_cz,
+                new_cz,
+                wait,
+                global_opts.lock_wait(),
+                &vruntime,
+            )
         }
     }
 }
@@ -6,7 +6,7 @@ use log::info;
 
 use crate::GloablOpts;
 
-use libcz::ControlZone;
+use libcz::{lock::LockWait, ControlZone};
 
 #[derive(Parser, Debug)]
 pub struct Create {
@@ -24,10 +24,13 @@ pub fn create(args: Create, global_opts: &GloablOpts) -> Result<()> {
         return Ok(());
     }
 
-    create_inner(&mut cz)
+    create_inner(&mut cz, global_opts.lock_wait())
 }
 
-pub fn create_inner(cz: &mut ControlZone) -> Result<()> {
+pub fn create_inner(cz: &mut ControlZone, lock_wait: LockWait) -> Result<()> {
+    // allocated cpus are taken once recorded in config by create
+    let _root_lock = cz.lock_root(lock_wait)?;
+
     // valid check
     if cz.test_exists().is_some() {
         bail!(
@@ -36,6 +39,11 @@ pub fn create_inner(cz: &mut ControlZone) -> Result<()> {
         )
     }
 
+    cz.allocate_cpus(None)?;
+    if let Err(e) = cz.validate_resource(&cz.resource) {
+        bail!("invalid resource of {}: {e}", cz.meta.name)
+    }
+
     // create control zone
     if let Err(e) = cz.create() {
         bail!("create control zone failed: {e}")
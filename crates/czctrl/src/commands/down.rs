@@ -17,6 +17,7 @@ pub struct Down {
 
 pub fn down(args: Down, global_opts: &GloablOpts) -> Result<()> {
     let mut cz = ControlZone::new_from_config(&args.file)?;
+    let _lock = cz.lock(global_opts.lock_wait())?;
     let vruntime: DVRuntime = global_opts.vruntime.into();
     remove_inner(&mut cz, true, &vruntime)
 }
@@ -0,0 +1,53 @@
+use std::{io, path::PathBuf, process, time::Duration};
+
+use anyhow::{anyhow, bail, Ok, Result};
+use clap::Parser;
+use libcz::{default_workdir, state::State, ControlZone, CHANNEL_TIMEOUT, CZ_CONFIG};
+use log::debug;
+
+use crate::GloablOpts;
+
+#[derive(Parser, Debug)]
+pub struct Exec {
+    /// Control Zone Config
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Name of Control Zone
+    control_zone: String,
+
+    /// Command to Run in Control Zone
+    #[arg(last = true, required = true)]
+    cmd: Vec<String>,
+}
+
+pub fn exec(args: Exec, global_opts: &GloablOpts) -> Result<()> {
+    let full_config = match args.config {
+        Some(path) => path,
+        None => default_workdir(&args.control_zone).join(CZ_CONFIG),
+    };
+
+    let cz = ControlZone::new_from_full_config(&full_config)
+        .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+
+    if cz.state != State::Running {
+        bail!("control zone need to start fisrt")
+    }
+
+    if global_opts.dry_run {
+        return Ok(());
+    }
+
+    let Some(mut client) = cz.channel(Duration::from_secs(CHANNEL_TIMEOUT)) else {
+        bail!("control channel of {} unavailable", cz.meta.name)
+    };
+
+    // command may run for long without output
+    client.set_timeout(Duration::ZERO)?;
+    debug!("exec {:?} in {}", args.cmd, cz.meta.name);
+    let code = client.exec(args.cmd, &mut io::stdout(), &mut io::stderr())?;
+    if code != 0 {
+        process::exit(code);
+    }
+    Ok(())
+}
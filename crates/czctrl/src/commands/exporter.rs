@@ -0,0 +1,418 @@
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Ok, Result};
+use clap::Parser;
+use libcz::{state::State, ControlZone, POD_APPLY_DIR, POD_DIR, RESCTRL_ROOT};
+use libutil::resctrl::read_mon_data;
+use libvm::{
+    stats::{domain_stats, StatsGroup},
+    Libvirt,
+};
+use log::{debug, info, warn};
+
+use crate::{
+    config::{DEFAULT_EXPORTER_LISTEN, DEFAUL_LIBVIRT_URI},
+    vruntime::VRuntimeType,
+    GloablOpts,
+};
+
+use super::list::all_control_zones;
+
+const METRICS_PATH: &str = "/metrics";
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+/// a client idle for so long is dropped, as requests are served one by one
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+const STATES: [State; 8] = [
+    State::Pending,
+    State::Created,
+    State::Running,
+    State::Paused,
+    State::Stopped,
+    State::Killed,
+    State::Zombied,
+    State::Error,
+];
+
+/// balloon stats reported by libvirt in KiB
+const BALLOON_KIB_STATS: [&str; 9] = [
+    "current",
+    "maximum",
+    "swap_in",
+    "swap_out",
+    "unused",
+    "available",
+    "usable",
+    "rss",
+    "disk_caches",
+];
+
+#[derive(Parser, Debug)]
+pub struct Exporter {
+    /// Address to serve metrics on
+    #[arg(short, long, default_value = DEFAULT_EXPORTER_LISTEN)]
+    listen: String,
+}
+
+pub fn exporter(args: Exporter, global_opts: &GloablOpts) -> Result<()> {
+    let listener = TcpListener::bind(&args.listen)?;
+    info!("serving metrics on http://{}{METRICS_PATH}", args.listen);
+
+    // domain stats are only available from libvirt
+    let libvirt_uri = (global_opts.vruntime == VRuntimeType::Libvirt).then_some(DEFAUL_LIBVIRT_URI);
+    serve(listener, &global_opts.root_dir(), libvirt_uri)
+}
+
+/// serve requests one by one, metrics are collected on each scrape
+fn serve(listener: TcpListener, root_dir: &Path, libvirt_uri: Option<&str>) -> Result<()> {
+    for stream in listener.incoming() {
+        let result = stream
+            .map_err(anyhow::Error::from)
+            .and_then(|stream| handle(stream, root_dir, libvirt_uri));
+        if let Err(e) = result {
+            warn!("serve metrics failed: {e}");
+        }
+    }
+    Ok(())
+}
+
+fn handle(mut stream: TcpStream, root_dir: &Path, libvirt_uri: Option<&str>) -> Result<()> {
+    stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+    stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // drain headers, body of GET is ignored
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+    debug!("metrics request: {}", request.trim_end());
+
+    let mut parts = request.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some(METRICS_PATH)) => match render(root_dir, libvirt_uri) {
+            Result::Ok(body) => ("200 OK", CONTENT_TYPE, body),
+            Err(e) => {
+                warn!("render metrics failed: {e}");
+                (
+                    "500 Internal Server Error",
+                    "text/plain",
+                    format!("render metrics failed: {e}\n"),
+                )
+            }
+        },
+        (Some("GET"), _) => ("404 Not Found", "text/plain", String::from("not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("method not allowed\n"),
+        ),
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    Ok(())
+}
+
+/// samples of one metric family, kept together as openmetrics requires
+struct Family {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    samples: Vec<String>,
+}
+
+impl Family {
+    fn sample(&mut self, suffix: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{key}=\"{}\"", escape(value)))
+            .collect();
+        self.samples.push(format!(
+            "{}{suffix}{{{}}} {value}\n",
+            self.name,
+            labels.join(",")
+        ));
+    }
+}
+
+#[derive(Default)]
+struct Metrics {
+    families: Vec<Family>,
+}
+
+impl Metrics {
+    fn family(
+        &mut self,
+        name: &'static str,
+        kind: &'static str,
+        help: &'static str,
+    ) -> &mut Family {
+        let i = match self.families.iter().position(|f| f.name == name) {
+            Some(i) => i,
+            None => {
+                self.families.push(Family {
+                    name,
+                    kind,
+                    help,
+                    samples: vec![],
+                });
+                self.families.len() - 1
+            }
+        };
+        &mut self.families[i]
+    }
+
+    fn gauge(&mut self, name: &'static str, help: &'static str) -> &mut Family {
+        self.family(name, "gauge", help)
+    }
+
+    fn counter(&mut self, name: &'static str, help: &'static str) -> &mut Family {
+        self.family(name, "counter", help)
+    }
+
+    fn render(&self) -> String {
+        let mut text = String::new();
+        for family in self.families.iter().filter(|f| !f.samples.is_empty()) {
+            text.push_str(&format!("# TYPE {} {}\n", family.name, family.kind));
+            text.push_str(&format!("# HELP {} {}\n", family.name, family.help));
+            family.samples.iter().for_each(|s| text.push_str(s));
+        }
+        text.push_str("# EOF\n");
+        text
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// metrics of all control zones under root dir, libvirt is connected
+/// once for a scrape
+fn render(root_dir: &Path, libvirt_uri: Option<&str>) -> Result<String> {
+    let zones = all_control_zones(&root_dir.to_path_buf())?;
+    let libvirt = libvirt_uri.and_then(|url| match Libvirt::new(url) {
+        Result::Ok(libvirt) => Some(libvirt),
+        Err(e) => {
+            warn!("connect libvirt failed, domain stats skipped: {e}");
+            None
+        }
+    });
+
+    let mut metrics = Metrics::default();
+    for cz in zones {
+        collect(&mut metrics, &cz, libvirt.as_ref());
+    }
+    Ok(metrics.render())
+}
+
+fn collect(metrics: &mut Metrics, cz: &ControlZone, libvirt: Option<&Libvirt>) {
+    let zone = cz.meta.name.as_str();
+
+    let family = metrics.family("controlzone_state", "stateset", "State of Control Zone.");
+    for state in STATES {
+        let value = if state == cz.state { 1.0 } else { 0.0 };
+        family.sample(
+            "",
+            &[("zone", zone), ("controlzone_state", &state.to_string())],
+            value,
+        );
+    }
+
+    metrics
+        .gauge("controlzone_vcpus", "Number of vCPUs.")
+        .sample("", &[("zone", zone)], cz.resource.cpus.len() as f64);
+    metrics
+        .gauge("controlzone_memory_bytes", "Memory of Control Zone.")
+        .sample(
+            "",
+            &[("zone", zone)],
+            cz.resource.memory as f64 * 1024.0 * 1024.0,
+        );
+    metrics
+        .gauge("controlzone_pods", "Number of applied pods.")
+        .sample("", &[("zone", zone)], pod_count(cz) as f64);
+
+    if !matches!(cz.state, State::Running | State::Paused) {
+        return;
+    }
+
+    if let Some(libvirt) = libvirt {
+        match domain_stats(libvirt, zone, &[StatsGroup::Perf, StatsGroup::Balloon]) {
+            Result::Ok(Some(stats)) => collect_domain_stats(metrics, zone, &stats),
+            Result::Ok(None) => debug!("no domain of {zone}"),
+            Err(e) => warn!("get domain stats of {zone} failed: {e}"),
+        }
+    }
+
+    if let Some(group) = resctrl_group(zone) {
+        match read_mon_data(&group) {
+            Result::Ok(samples) => {
+                for sample in samples {
+                    let domain = sample.domain.to_string();
+                    let labels = [("zone", zone), ("domain", domain.as_str())];
+                    if let Some(value) = sample.llc_occupancy {
+                        metrics
+                            .gauge("controlzone_llc_occupancy_bytes", "L3 cache occupancy.")
+                            .sample("", &labels, value as f64);
+                    }
+                    if let Some(value) = sample.mbm_total_bytes {
+                        metrics
+                            .counter("controlzone_mbm_total_bytes", "Total memory bandwidth.")
+                            .sample("_total", &labels, value as f64);
+                    }
+                    if let Some(value) = sample.mbm_local_bytes {
+                        metrics
+                            .counter("controlzone_mbm_local_bytes", "Local memory bandwidth.")
+                            .sample("_total", &labels, value as f64);
+                    }
+                }
+            }
+            Err(e) => warn!("read resctrl data of {zone} failed: {e}"),
+        }
+    }
+}
+
+fn collect_domain_stats(
+    metrics: &mut Metrics,
+    zone: &str,
+    stats: &std::collections::BTreeMap<String, f64>,
+) {
+    for (field, value) in stats {
+        if let Some(event) = field.strip_prefix("perf.") {
+            metrics
+                .counter("controlzone_perf_events", "Perf event counters.")
+                .sample("_total", &[("zone", zone), ("event", event)], *value);
+        } else if let Some(stat) = field.strip_prefix("balloon.") {
+            if BALLOON_KIB_STATS.contains(&stat) {
+                metrics
+                    .gauge("controlzone_balloon_bytes", "Balloon memory stats.")
+                    .sample("", &[("zone", zone), ("stat", stat)], *value * 1024.0);
+            }
+        }
+    }
+}
+
+/// resctrl control group of zone, or monitor group created by observe
+fn resctrl_group(zone: &str) -> Option<PathBuf> {
+    let ctrl_group = PathBuf::from(RESCTRL_ROOT).join(zone);
+    let mon_group = PathBuf::from(RESCTRL_ROOT).join("mon_groups").join(zone);
+    [ctrl_group, mon_group]
+        .into_iter()
+        .find(|group| group.is_dir())
+}
+
+fn pod_count(cz: &ControlZone) -> usize {
+    let apply_dir = PathBuf::from(&cz.meta.share_folder)
+        .join(POD_DIR)
+        .join(POD_APPLY_DIR);
+    fs::read_dir(apply_dir)
+        .map(|entries| entries.filter_map(|entry| entry.ok()).count())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        thread,
+    };
+
+    use libcz::{ControlZone, POD_APPLY_DIR, POD_DIR};
+
+    use super::{serve, CONTENT_TYPE};
+
+    fn get(addr: &str, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: {addr}\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_exporter() {
+        let root = std::env::temp_dir().join("czctrl_test_exporter");
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+        fs::create_dir_all(&root).unwrap();
+        let rootfs = root.join("rootfs.img");
+        fs::write(&rootfs, "rootfs").unwrap();
+        let config = root.join("exporter01.yaml");
+        fs::write(
+            &config,
+            format!(
+                "meta:
+  name: exporter01
+  workdir: {}
+os:
+  kernel: /boot/vmlinuz
+  rootfs: {}
+  kcmdline: console=ttyS0
+resource:
+  cpuset: \"0-1\"
+  memory: 512
+",
+                root.join("exporter01").display(),
+                rootfs.display()
+            ),
+        )
+        .unwrap();
+
+        let mut cz = ControlZone::new_from_config(&config).unwrap();
+        cz.create().unwrap();
+        // apply dir is created by czdaemon in guest
+        let apply_dir = PathBuf::from(&cz.meta.share_folder)
+            .join(POD_DIR)
+            .join(POD_APPLY_DIR);
+        fs::create_dir_all(&apply_dir).unwrap();
+        fs::write(apply_dir.join("nginx.yaml"), "kind: Pod").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let server_root = root.clone();
+        thread::spawn(move || serve(listener, &server_root, None));
+
+        let response = get(&addr, "/metrics");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK"));
+        assert!(head.contains(CONTENT_TYPE));
+        assert!(body.contains("# TYPE controlzone_state stateset\n"));
+        assert!(body
+            .contains("controlzone_state{zone=\"exporter01\",controlzone_state=\"Created\"} 1\n"));
+        assert!(body
+            .contains("controlzone_state{zone=\"exporter01\",controlzone_state=\"Running\"} 0\n"));
+        assert!(body.contains("controlzone_vcpus{zone=\"exporter01\"} 2\n"));
+        assert!(body.contains("controlzone_memory_bytes{zone=\"exporter01\"} 536870912\n"));
+        assert!(body.contains("controlzone_pods{zone=\"exporter01\"} 1\n"));
+        assert!(body.ends_with("# EOF\n"));
+
+        assert!(get(&addr, "/").starts_with("HTTP/1.1 404"));
+
+        // failed scrape is answered rather than dropped
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let broken_addr = listener.local_addr().unwrap().to_string();
+        let broken_root = root.join("missing");
+        thread::spawn(move || serve(listener, &broken_root, None));
+        assert!(get(&broken_addr, "/metrics").starts_with("HTTP/1.1 500"));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -1,11 +1,14 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{anyhow, bail, Ok, Result};
 use clap::Parser;
 
-use crate::GloablOpts;
+use crate::{
+    config::DEFAUL_LIBVIRT_URI, snapshot::print_snapshots, vruntime::VRuntimeType, GloablOpts,
+};
 
-use libcz::{default_workdir, ControlZone, CZ_CONFIG};
+use libcz::{default_workdir, ControlZone, CHANNEL_QUERY_TIMEOUT, CZ_CONFIG};
+use libvm::{domain::DomainDef, live_domain};
 
 #[derive(Parser, Debug)]
 pub struct Inspect {
@@ -13,11 +16,15 @@ pub struct Inspect {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
+    /// Diff Running Domain against Config
+    #[arg(short, long)]
+    runtime: bool,
+
     /// Name of Control Zone
     control_zone: String,
 }
 
-pub fn inspect(args: Inspect, _: &GloablOpts) -> Result<()> {
+pub fn inspect(args: Inspect, global_opts: &GloablOpts) -> Result<()> {
     let full_config = match args.config {
         Some(path) => path,
         None => default_workdir(&args.control_zone).join(CZ_CONFIG),
@@ -26,5 +33,58 @@ pub fn inspect(args: Inspect, _: &GloablOpts) -> Result<()> {
     let cz = ControlZone::new_from_full_config(&full_config)
         .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
     serde_yaml::to_string(&cz).map(|cz_str| println!("{}", cz_str))?;
+
+    let snapshots = cz.list_snapshots()?;
+    if !snapshots.is_empty() {
+        println!("--------Snapshots--------\n");
+        print_snapshots(&snapshots);
+    }
+
+    if let Some(addr) = cz.channel_addr() {
+        println!("--------Channel--------\n");
+        println!("{:10}{}", "ADDRESS", addr);
+        if let Some(version) = cz
+            .channel(Duration::from_secs(CHANNEL_QUERY_TIMEOUT))
+            .and_then(|mut client| client.ping().ok())
+        {
+            println!("{:10}{}", "DAEMON", version);
+        }
+    }
+
+    let (workdir_holder, root_holder) = cz.lock_holders();
+    if workdir_holder.is_some() || root_holder.is_some() {
+        println!("--------Locks--------\n");
+        let holder = |holder: Option<String>| holder.unwrap_or(String::from("-"));
+        println!("{:10}{}", "WORKDIR", holder(workdir_holder));
+        println!("{:10}{}", "ROOT", holder(root_holder));
+    }
+
+    if args.runtime {
+        inspect_runtime(&cz, global_opts)?;
+    }
+    Ok(())
+}
+
+/// print fields of running domain differ from config
+fn inspect_runtime(cz: &ControlZone, global_opts: &GloablOpts) -> Result<()> {
+    if global_opts.vruntime != VRuntimeType::Libvirt {
+        bail!("runtime inspect is only supported by libvirt vruntime")
+    }
+
+    let Some(live) = live_domain(DEFAUL_LIBVIRT_URI, &cz.meta.name)? else {
+        bail!("control zone {} is not running", cz.meta.name)
+    };
+
+    println!("--------Runtime--------\n");
+    let diffs = DomainDef::from_cz(cz).diff(&live)?;
+    if diffs.is_empty() {
+        println!("runtime consistent with config");
+        return Ok(());
+    }
+
+    println!("{:16}{:32}{:32}", "FIELD", "CONFIG", "RUNTIME");
+    for diff in diffs {
+        println!("{:16}{:32}{:32}", diff.field, diff.config, diff.runtime);
+    }
     Ok(())
 }
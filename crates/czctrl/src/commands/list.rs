@@ -4,11 +4,12 @@ use anyhow::{anyhow, Result};
 use clap::Parser;
 
 use libcz::{
+    lock::LockWait,
     vruntime::{addition_info_bar, addition_info_per, DVRuntime},
     ControlZone, CZ_CONFIG,
 };
 
-use crate::GloablOpts;
+use crate::{commands::reconcile::reconcile_inner, GloablOpts};
 
 #[derive(Parser, Debug)]
 pub struct List {
@@ -17,9 +18,9 @@ pub struct List {
     use_vruntime: bool,
 }
 
-pub fn list(args: List, global_opts: &GloablOpts) -> Result<()> {
-    let root_dir = global_opts.root_dir();
-    let controlzones: Vec<ControlZone> = fs::read_dir(root_dir)?
+/// load all created control zones under root dir
+pub fn all_control_zones(root_dir: &PathBuf) -> Result<Vec<ControlZone>> {
+    Ok(fs::read_dir(root_dir)?
         .filter_map(|entry| entry.ok())
         .filter_map(|entry| {
             let path = entry.path();
@@ -34,9 +35,18 @@ pub fn list(args: List, global_opts: &GloablOpts) -> Result<()> {
 
             ControlZone::new_from_full_config(&full_config).ok()
         })
-        .collect();
+        .collect())
+}
+
+pub fn list(args: List, global_opts: &GloablOpts) -> Result<()> {
+    let mut controlzones = all_control_zones(&global_opts.root_dir())?;
 
     let vruntime: DVRuntime = global_opts.vruntime.into();
+    controlzones
+        .iter_mut()
+        // zones locked by other czctrl are being changed, listed as is
+        .for_each(|cz| reconcile_inner(cz, LockWait::NoWait, &vruntime));
+
     print!("{:16}{:20}{:10}{:10}", "NAME", "KERNEL", "CPUS", "STATUS");
 
     if args.use_vruntime {
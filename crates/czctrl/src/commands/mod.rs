@@ -1,20 +1,27 @@
 use clap::Parser;
 
 use self::{
-    apply::Apply, conn::Conn, create::Create, down::Down, inspect::Inspect, list::List, log::Log,
-    observe::Observe, remove::Remove, start::Start, stop::Stop, update::Update,
+    apply::Apply, conn::Conn, create::Create, down::Down, exec::Exec, exporter::Exporter,
+    inspect::Inspect, list::List, log::Log, observe::Observe, pause::Pause, reconcile::Reconcile,
+    remove::Remove, resume::Resume, start::Start, stats::Stats, stop::Stop, update::Update,
 };
 
 pub mod apply;
 pub mod conn;
 pub mod down;
+pub mod exec;
+pub mod exporter;
 pub mod list;
 pub mod observe;
+pub mod reconcile;
+pub mod stats;
 
 pub mod create;
 pub mod inspect;
 pub mod log;
+pub mod pause;
 pub mod remove;
+pub mod resume;
 pub mod start;
 pub mod stop;
 pub mod update;
@@ -35,6 +42,18 @@ pub enum AdvanceCmd {
 
     /// Connect to Control Zone
     Conn(Conn),
+
+    /// Run Command in Control Zone
+    Exec(Exec),
+
+    /// Correct State of Control Zones from VRuntime
+    Reconcile(Reconcile),
+
+    /// Serve Metrics of Control Zones in OpenMetrics Format
+    Exporter(Exporter),
+
+    /// Show Domain Stats of Control Zone
+    Stats(Stats),
 }
 
 #[derive(Parser, Debug)]
@@ -51,6 +70,12 @@ pub enum BasicCmd {
     /// Stop Control Zone
     Stop(Stop),
 
+    /// Pause Control Zone
+    Pause(Pause),
+
+    /// Resume Paused Control Zone
+    Resume(Resume),
+
     /// Remove Control Zone
     Remove(Remove),
 
@@ -4,16 +4,20 @@ use std::{
     io::Write,
     path::PathBuf,
     str::FromStr,
+    thread::sleep,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{anyhow, bail, Ok, Result};
 use clap::{Parser, ValueEnum};
 use libbpfmap::CgroupMapWrapper;
-use log::{debug, error, info};
+use libcz::{observability, ControlZone};
+use libutil::resctrl::{mon_rates, read_mon_data, MonRate};
+use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    config::{CGROUP_ROOT, RESCTL_ROOT},
+    config::{CGROUP_ROOT, DEFAULT_METRICS_INTERVAL, RESCTL_ROOT},
     GloablOpts,
 };
 
@@ -23,6 +27,15 @@ pub enum Monitor {
     Ebpf,
 }
 
+impl From<observability::Monitor> for Monitor {
+    fn from(monitor: observability::Monitor) -> Self {
+        match monitor {
+            observability::Monitor::Resctrl => Monitor::Resctrl,
+            observability::Monitor::Ebpf => Monitor::Ebpf,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 pub struct Observe {
     /// Observe all Control Zones
@@ -41,6 +54,18 @@ pub struct Observe {
     #[arg(short, long)]
     output: Option<PathBuf>,
 
+    /// Show resctrl metrics of Control Zones
+    #[arg(long)]
+    show_metrics: bool,
+
+    /// Seconds between metrics samples
+    #[arg(long, default_value_t = DEFAULT_METRICS_INTERVAL)]
+    interval: u64,
+
+    /// Print metrics as json lines
+    #[arg(long)]
+    json: bool,
+
     /// Control Zones
     control_zones: Vec<String>,
 }
@@ -48,43 +73,13 @@ pub struct Observe {
 #[derive(Debug)]
 enum Action {
     Show,
+    Metrics,
     Init,
     Clean,
 }
 
 pub fn observe(args: Observe, global_opts: &GloablOpts) -> Result<()> {
-    if !libutil::kvm::check_kvm() {
-        bail!("kvm not enabled or not a root user")
-    }
-
-    // kvm info -> proc info -> libvirt info
-    let mut vm_monitor_infos: Vec<VmMonitorInfo> = libutil::kvm::get_kvm_infos()?
-        .into_iter()
-        .filter_map(|kvm_info| {
-            let Some(tasks) = libutil::process::tasks_of(kvm_info.pid)
-                .map_err(|e| error!("parse tasks err: {}", e))
-                .ok()
-            else {
-                return None;
-            };
-
-            let Some(libvirt_info) = libutil::process::libvirt_info_of(kvm_info.pid)
-                .map_err(|e| error!("parse libvirt info err: {}", e))
-                .ok()
-            else {
-                return None;
-            };
-
-            Some(VmMonitorInfo {
-                pid: kvm_info.pid,
-                kvm_debug_dir: kvm_info.kvm_debug_dir,
-                tasks: tasks,
-                vm_id: libvirt_info.vm_id,
-                vm_cgroup: libvirt_info.vm_cgroup,
-                vm_name: libvirt_info.vm_name,
-            })
-        })
-        .collect();
+    let mut vm_monitor_infos = vm_monitor_infos()?;
 
     if !args.all && args.control_zones.len() != 0 {
         let mut control_zones: HashSet<String> = HashSet::from_iter(args.control_zones.into_iter());
@@ -109,6 +104,8 @@ pub fn observe(args: Observe, global_opts: &GloablOpts) -> Result<()> {
 
     let action = if global_opts.dry_run {
         Action::Show
+    } else if args.show_metrics {
+        Action::Metrics
     } else if args.clean {
         Action::Clean
     } else {
@@ -163,41 +160,13 @@ pub fn observe(args: Observe, global_opts: &GloablOpts) -> Result<()> {
             });
             Ok(())
         }
+        Action::Metrics => show_metrics(
+            &vm_monitor_infos,
+            Duration::from_secs(args.interval),
+            args.json,
+        ),
         Action::Init => {
-            monitor_set.iter().for_each(|monitor| match monitor {
-                Monitor::Resctrl => vm_monitor_infos.iter().for_each(|vm_monitor_info| {
-                    if let Err(e) = vm_monitor_info.init_resctrl_mgroup() {
-                        error!(
-                            "init resctrl mon group for {} failed: {}",
-                            vm_monitor_info.vm_name, e
-                        );
-                    } else {
-                        info!(
-                            "resctrl mon group for {} initialized",
-                            vm_monitor_info.vm_name
-                        );
-                    }
-                }),
-                Monitor::Ebpf => {
-                    let Some(wrapper) = libbpfmap::CgroupMapWrapper::new()
-                        .map_err(|e| error!("init cgroup map error: {}", e))
-                        .ok()
-                    else {
-                        return;
-                    };
-
-                    vm_monitor_infos.iter().for_each(|vm_monitor_info| {
-                        if let Err(e) = vm_monitor_info.init_ebpf_cgroup(&wrapper) {
-                            error!(
-                                "init ebpf cgroup for {} failed: {}",
-                                vm_monitor_info.vm_name, e
-                            );
-                        } else {
-                            info!("ebpf cgroup for {} initialized", vm_monitor_info.vm_name);
-                        }
-                    });
-                }
-            });
+            init_monitors(&vm_monitor_infos, &monitor_set);
 
             let Some(output) = args.output else {
                 return Ok(());
@@ -214,37 +183,7 @@ pub fn observe(args: Observe, global_opts: &GloablOpts) -> Result<()> {
             Ok(())
         }
         Action::Clean => {
-            monitor_set.iter().for_each(|monitor| match monitor {
-                Monitor::Resctrl => vm_monitor_infos.iter().for_each(|vm_monitor_info| {
-                    if let Err(e) = vm_monitor_info.remove_resctrl_group() {
-                        error!(
-                            "init resctrl mon group for {} failed: {}",
-                            vm_monitor_info.vm_name, e
-                        );
-                    } else {
-                        info!("resctrl mon group for {} cleaned", vm_monitor_info.vm_name);
-                    }
-                }),
-                Monitor::Ebpf => {
-                    let Some(wrapper) = libbpfmap::CgroupMapWrapper::new()
-                        .map_err(|e| error!("init cgroup map error: {}", e))
-                        .ok()
-                    else {
-                        return;
-                    };
-
-                    vm_monitor_infos.iter().for_each(|vm_monitor_info| {
-                        if let Err(e) = vm_monitor_info.remove_ebpf_cgroup(&wrapper) {
-                            error!(
-                                "init ebpf cgroup for {} failed: {}",
-                                vm_monitor_info.vm_name, e
-                            );
-                        } else {
-                            info!("ebpf cgroup for {} cleaned", vm_monitor_info.vm_name);
-                        }
-                    });
-                }
-            });
+            clean_monitors(&vm_monitor_infos, &monitor_set);
 
             let Some(output) = args.output else {
                 return Ok(());
@@ -263,11 +202,257 @@ pub fn observe(args: Observe, global_opts: &GloablOpts) -> Result<()> {
     }
 }
 
+/// register zone with monitors in its observability profile
+pub fn register(cz: &ControlZone) -> Result<()> {
+    if let Some((vm_monitor_infos, monitor_set)) = zone_monitors(cz)? {
+        init_monitors(&vm_monitor_infos, &monitor_set);
+    }
+    Ok(())
+}
+
+/// clean monitors registered by `register`, before vm exits
+pub fn deregister(cz: &ControlZone) -> Result<()> {
+    if let Some((vm_monitor_infos, monitor_set)) = zone_monitors(cz)? {
+        clean_monitors(&vm_monitor_infos, &monitor_set);
+    }
+    Ok(())
+}
+
+fn zone_monitors(cz: &ControlZone) -> Result<Option<(Vec<VmMonitorInfo>, HashSet<Monitor>)>> {
+    let monitors = &cz.observability.monitors;
+    if monitors.is_empty() {
+        return Ok(None);
+    }
+
+    let vm_monitor_infos: Vec<VmMonitorInfo> = vm_monitor_infos()?
+        .into_iter()
+        .filter(|vm_monitor_info| vm_monitor_info.vm_name == cz.meta.name)
+        .collect();
+    if vm_monitor_infos.is_empty() {
+        bail!("no vm of {} found to observe", cz.meta.name)
+    }
+    Ok(Some((
+        vm_monitor_infos,
+        monitors.iter().map(|monitor| (*monitor).into()).collect(),
+    )))
+}
+
+/// kvm info -> proc info -> libvirt or qemu info
+fn vm_monitor_infos() -> Result<Vec<VmMonitorInfo>> {
+    if !libutil::kvm::check_kvm() {
+        bail!("kvm not enabled or not a root user")
+    }
+
+    Ok(libutil::kvm::get_kvm_infos()?
+        .into_iter()
+        .filter_map(|kvm_info| {
+            let Some(tasks) = libutil::process::tasks_of(kvm_info.pid)
+                .map_err(|e| error!("parse tasks err: {}", e))
+                .ok()
+            else {
+                return None;
+            };
+
+            let (backend, vm_id, vm_name, vm_cgroup) =
+                match libutil::process::libvirt_info_of(kvm_info.pid) {
+                    Result::Ok(info) => {
+                        (VmBackend::Libvirt, info.vm_id, info.vm_name, info.vm_cgroup)
+                    }
+                    Err(libvirt_err) => match libutil::process::qemu_info_of(kvm_info.pid) {
+                        // qemu has no domain id, pid identifies it instead,
+                        // cgroup of zone is preferred once threads placed
+                        Result::Ok(info) => {
+                            let cgroup = libvm::zone_cgroup(&info.vm_name);
+                            let vm_cgroup = if cgroup.exists() {
+                                cgroup.path()
+                            } else {
+                                info.vm_cgroup
+                            };
+                            (VmBackend::Qemu, kvm_info.pid, info.vm_name, vm_cgroup)
+                        }
+                        Err(qemu_err) => {
+                            error!(
+                                "parse vm info of {} err: {libvirt_err}, {qemu_err}",
+                                kvm_info.pid
+                            );
+                            return None;
+                        }
+                    },
+                };
+
+            Some(VmMonitorInfo {
+                pid: kvm_info.pid,
+                kvm_debug_dir: kvm_info.kvm_debug_dir,
+                tasks: tasks,
+                backend,
+                vm_id,
+                vm_cgroup,
+                vm_name,
+            })
+        })
+        .collect())
+}
+
+fn init_monitors(vm_monitor_infos: &[VmMonitorInfo], monitor_set: &HashSet<Monitor>) {
+    monitor_set.iter().for_each(|monitor| match monitor {
+        Monitor::Resctrl => vm_monitor_infos.iter().for_each(|vm_monitor_info| {
+            if let Err(e) = vm_monitor_info.init_resctrl_mgroup() {
+                error!(
+                    "init resctrl mon group for {} failed: {}",
+                    vm_monitor_info.vm_name, e
+                );
+            } else {
+                info!(
+                    "resctrl mon group for {} initialized",
+                    vm_monitor_info.vm_name
+                );
+            }
+        }),
+        Monitor::Ebpf => {
+            let Some(wrapper) = libbpfmap::CgroupMapWrapper::new()
+                .map_err(|e| error!("init cgroup map error: {}", e))
+                .ok()
+            else {
+                return;
+            };
+
+            vm_monitor_infos.iter().for_each(|vm_monitor_info| {
+                if let Err(e) = vm_monitor_info.init_ebpf_cgroup(&wrapper) {
+                    error!(
+                        "init ebpf cgroup for {} failed: {}",
+                        vm_monitor_info.vm_name, e
+                    );
+                } else {
+                    info!("ebpf cgroup for {} initialized", vm_monitor_info.vm_name);
+                }
+            });
+        }
+    });
+}
+
+fn clean_monitors(vm_monitor_infos: &[VmMonitorInfo], monitor_set: &HashSet<Monitor>) {
+    monitor_set.iter().for_each(|monitor| match monitor {
+        Monitor::Resctrl => vm_monitor_infos.iter().for_each(|vm_monitor_info| {
+            if let Err(e) = vm_monitor_info.remove_resctrl_group() {
+                error!(
+                    "init resctrl mon group for {} failed: {}",
+                    vm_monitor_info.vm_name, e
+                );
+            } else {
+                info!("resctrl mon group for {} cleaned", vm_monitor_info.vm_name);
+            }
+        }),
+        Monitor::Ebpf => {
+            let Some(wrapper) = libbpfmap::CgroupMapWrapper::new()
+                .map_err(|e| error!("init cgroup map error: {}", e))
+                .ok()
+            else {
+                return;
+            };
+
+            vm_monitor_infos.iter().for_each(|vm_monitor_info| {
+                if let Err(e) = vm_monitor_info.remove_ebpf_cgroup(&wrapper) {
+                    error!(
+                        "init ebpf cgroup for {} failed: {}",
+                        vm_monitor_info.vm_name, e
+                    );
+                } else {
+                    info!("ebpf cgroup for {} cleaned", vm_monitor_info.vm_name);
+                }
+            });
+        }
+    });
+}
+
+#[derive(Serialize)]
+struct MetricsRecord<'a> {
+    zone: &'a str,
+    /// seconds since unix epoch
+    timestamp: u64,
+    #[serde(flatten)]
+    rate: &'a MonRate,
+}
+
+/// sample resctrl counters of each vm every interval, until interrupted
+fn show_metrics(vm_monitor_infos: &[VmMonitorInfo], interval: Duration, json: bool) -> Result<()> {
+    let groups: Vec<(&str, PathBuf)> = vm_monitor_infos
+        .iter()
+        .filter_map(|vm_monitor_info| match vm_monitor_info.resctrl_group() {
+            Some(group) => Some((vm_monitor_info.vm_name.as_str(), group)),
+            None => {
+                warn!("no resctrl group for {}", vm_monitor_info.vm_name);
+                None
+            }
+        })
+        .collect();
+    if groups.is_empty() {
+        bail!("no resctrl group to show, run observe first")
+    }
+
+    let sample = || -> Result<Vec<_>> {
+        groups
+            .iter()
+            .map(|(_, group)| read_mon_data(group))
+            .collect()
+    };
+    let mut prev = sample()?;
+    let mut prev_at = Instant::now();
+    loop {
+        sleep(interval);
+        let curr = sample()?;
+        let elapsed = prev_at.elapsed();
+        prev_at = Instant::now();
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+        if !json {
+            println!(
+                "{:16}{:8}{:16}{:20}{:20}",
+                "ZONE", "DOMAIN", "LLC(KiB)", "MBM_TOTAL(MB/s)", "MBM_LOCAL(MB/s)"
+            );
+        }
+        for ((zone, _), (prev, curr)) in groups.iter().zip(prev.iter().zip(curr.iter())) {
+            for rate in mon_rates(prev, curr, elapsed) {
+                if json {
+                    let record = MetricsRecord {
+                        zone,
+                        timestamp,
+                        rate: &rate,
+                    };
+                    println!("{}", serde_json::to_string(&record)?);
+                    continue;
+                }
+
+                let kib = |v: Option<u64>| v.map_or(String::from("-"), |v| (v / 1024).to_string());
+                let mbps =
+                    |v: Option<f64>| v.map_or(String::from("-"), |v| format!("{:.2}", v / 1e6));
+                println!(
+                    "{:16}{:<8}{:16}{:20}{:20}",
+                    zone,
+                    rate.domain,
+                    kib(rate.llc_occupancy),
+                    mbps(rate.mbm_total_rate),
+                    mbps(rate.mbm_local_rate)
+                );
+            }
+        }
+        prev = curr;
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum VmBackend {
+    Libvirt,
+    Qemu,
+}
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct VmMonitorInfo {
     pub pid: u32,
     pub kvm_debug_dir: String,
     pub tasks: Vec<u32>,
+    pub backend: VmBackend,
+    /// libvirt domain id, or pid of qemu
     pub vm_id: u32,
     pub vm_name: String,
     pub vm_cgroup: String,
@@ -284,8 +469,25 @@ impl VmMonitorInfo {
 
 /// Resctrl Option
 impl VmMonitorInfo {
+    /// control group allocated to vm, or monitor group created by observe
+    fn resctrl_group(&self) -> Option<PathBuf> {
+        let ctrl_group = PathBuf::from(RESCTL_ROOT).join(&self.vm_name);
+        let mon_group = PathBuf::from(RESCTL_ROOT)
+            .join("mon_groups")
+            .join(&self.vm_name);
+        [ctrl_group, mon_group]
+            .into_iter()
+            .find(|group| group.is_dir())
+    }
+
     /// init resctrl monitor group for Virtual Machine
     fn init_resctrl_mgroup(&self) -> Result<()> {
+        // tasks allocated to a control group are monitored by the group itself
+        if PathBuf::from(RESCTL_ROOT).join(&self.vm_name).is_dir() {
+            info!("{} has its own resctrl group, skip", self.vm_name);
+            return Ok(());
+        }
+
         let mgroup_dir = self.remove_resctrl_group()?;
         fs::create_dir(&mgroup_dir)?;
         debug!("resctrl mon group created for {}", self.vm_name);
@@ -329,13 +531,15 @@ impl VmMonitorInfo {
 
 /// Ebpf Cgroup Option
 impl VmMonitorInfo {
-    fn detect_libvirt_cgroup(&self) -> Result<Vec<String>> {
+    /// cgroups holding threads of vm, children of vm cgroup if any,
+    /// e.g. emulator and vcpuN, otherwise vm cgroup itself
+    fn detect_vm_cgroups(&self) -> Result<Vec<String>> {
         let cgroup_root_dir = PathBuf::from(format!("{CGROUP_ROOT}{}", &self.vm_cgroup));
         if !cgroup_root_dir.exists() || !cgroup_root_dir.is_dir() {
             bail!("{} is not a cgroup dir", self.vm_cgroup);
         }
 
-        Ok(fs::read_dir(cgroup_root_dir)?
+        let cgroups: Vec<String> = fs::read_dir(&cgroup_root_dir)?
             .filter_map(|entry| entry.ok())
             .filter(|entry| entry.path().is_dir())
             .filter_map(|dir| {
@@ -343,14 +547,22 @@ impl VmMonitorInfo {
                     .to_str()
                     .and_then(|dir_str| Some(dir_str.to_owned()))
             })
-            .collect())
+            .collect();
+        if !cgroups.is_empty() {
+            return Ok(cgroups);
+        }
+
+        let Some(cgroup) = cgroup_root_dir.to_str() else {
+            bail!("invalid cgroup dir {:?}", cgroup_root_dir)
+        };
+        Ok(vec![cgroup.to_owned()])
     }
 
     fn init_ebpf_cgroup(&self, bpf_map: &CgroupMapWrapper) -> Result<()> {
-        bpf_map.insert_list(&self.detect_libvirt_cgroup()?)
+        bpf_map.insert_list(&self.detect_vm_cgroups()?)
     }
 
     fn remove_ebpf_cgroup(&self, bpf_map: &CgroupMapWrapper) -> Result<()> {
-        bpf_map.delete_list(&self.detect_libvirt_cgroup()?)
+        bpf_map.delete_list(&self.detect_vm_cgroups()?)
     }
 }
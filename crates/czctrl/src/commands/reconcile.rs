@@ -0,0 +1,62 @@
+use anyhow::{anyhow, Ok, Result};
+use clap::Parser;
+use log::{debug, error, warn};
+
+use libcz::{lock::LockWait, vruntime::DVRuntime, ControlZone, CZ_CONFIG};
+
+use crate::{commands::list::all_control_zones, GloablOpts};
+
+#[derive(Parser, Debug)]
+pub struct Reconcile {
+    /// Reconcile all Control Zones
+    #[arg(short, long)]
+    all: bool,
+
+    /// Control Zones
+    control_zones: Vec<String>,
+}
+
+pub fn reconcile(args: Reconcile, global_opts: &GloablOpts) -> Result<()> {
+    let root_dir = global_opts.root_dir();
+    let mut controlzones = if args.all {
+        all_control_zones(&root_dir)?
+    } else {
+        args.control_zones
+            .iter()
+            .map(|name| {
+                let full_config = root_dir.join(name).join(CZ_CONFIG);
+                ControlZone::new_from_full_config(&full_config)
+                    .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))
+            })
+            .collect::<Result<Vec<ControlZone>>>()?
+    };
+
+    if global_opts.dry_run {
+        return Ok(());
+    }
+
+    let vruntime: DVRuntime = global_opts.vruntime.into();
+    controlzones
+        .iter_mut()
+        .for_each(|cz| reconcile_inner(cz, global_opts.lock_wait(), &vruntime));
+    Ok(())
+}
+
+/// reconcile and report drift, errors are only reported
+pub fn reconcile_inner(cz: &mut ControlZone, lock_wait: LockWait, vruntime: &DVRuntime) {
+    let _lock = match cz.lock(lock_wait) {
+        Result::Ok(lock) => lock,
+        Err(e) => {
+            warn!("reconcile {} skipped: {e}", cz.meta.name);
+            return;
+        }
+    };
+    match cz.reconcile(vruntime) {
+        Result::Ok(Some(drifted)) => warn!(
+            "{} state drifted: {} -> {}, state corrected",
+            cz.meta.name, drifted, cz.state
+        ),
+        Result::Ok(None) => debug!("{} state consistent: {}", cz.meta.name, cz.state),
+        Err(e) => error!("reconcile {} failed: {e}", cz.meta.name),
+    }
+}
@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, bail, Ok, Result};
 use clap::Parser;
@@ -35,13 +35,15 @@ pub fn remove(args: Remove, global_opts: &GloablOpts) -> Result<()> {
         return Ok(());
     }
 
+    let _lock = cz.lock(global_opts.lock_wait())?;
     let vruntime: DVRuntime = global_opts.vruntime.into();
     remove_inner(&mut cz, args.force, &vruntime)
 }
 
 pub fn remove_inner(cz: &mut ControlZone, force: bool, vruntime: &DVRuntime) -> Result<()> {
-    if cz.state == State::Running && force {
-        stop_inner(cz, vruntime)?
+    // control zone will be removed, no need to shutdown gracefully
+    if matches!(cz.state, State::Running | State::Paused) && force {
+        stop_inner(cz, Duration::ZERO, true, vruntime)?
     }
 
     if let Err(e) = cz.remove() {
@@ -1,13 +1,13 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, bail, Ok, Result};
 use clap::Parser;
 use libvm::cz_to_xml;
-use log::{error, info};
+use log::{error, info, warn};
 
-use crate::GloablOpts;
+use crate::{commands::observe, config::DEFAULT_WAIT_TIMEOUT, GloablOpts};
 
-use libcz::{default_workdir, vruntime::DVRuntime, ControlZone, CZ_CONFIG};
+use libcz::{default_workdir, lock::LockWait, vruntime::DVRuntime, ControlZone, CZ_CONFIG};
 
 #[derive(Parser, Debug)]
 pub struct Start {
@@ -19,6 +19,10 @@ pub struct Start {
     #[arg(short, long)]
     wait: bool,
 
+    /// Seconds to wait for Vm Boot
+    #[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT)]
+    wait_timeout: u64,
+
     /// asign static ip and using bridge network
     #[arg(short, long)]
     ip: Option<String>,
@@ -39,7 +43,7 @@ pub fn start(args: Start, global_opts: &GloablOpts) -> Result<()> {
     if global_opts.dry_run {
         match global_opts.vruntime {
             crate::vruntime::VRuntimeType::Libvirt => {
-                if let anyhow::Result::Ok(xml) = cz_to_xml(&cz, true) {
+                if let anyhow::Result::Ok(xml) = cz_to_xml(&cz) {
                     println!("{}", xml);
                 } else {
                     error!("{:?} Invalid Contorl Zone", global_opts.vruntime);
@@ -50,16 +54,29 @@ pub fn start(args: Start, global_opts: &GloablOpts) -> Result<()> {
         return Ok(());
     }
 
+    let wait = args.wait.then(|| Duration::from_secs(args.wait_timeout));
+    let lock_wait = global_opts.lock_wait();
+    let _lock = cz.lock(lock_wait)?;
     let vruntime: DVRuntime = global_opts.vruntime.into();
-    start_inner(&mut cz, args.wait, &vruntime)
+    start_inner(&mut cz, wait, lock_wait, &vruntime)
 }
 
-pub fn start_inner(cz: &mut ControlZone, wait: bool, vruntime: &DVRuntime) -> Result<()> {
+pub fn start_inner(
+    cz: &mut ControlZone,
+    wait: Option<Duration>,
+    lock_wait: LockWait,
+    vruntime: &DVRuntime,
+) -> Result<()> {
     info!("starting controlzone...");
-    if let Err(e) = cz.start(wait, vruntime) {
+    if let Err(e) = cz.start(wait, lock_wait, vruntime) {
         bail!("start {} failed: {e}", cz.meta.name)
     }
 
     info!("{} started", cz.meta.name);
+
+    // monitors are best effort, the zone keeps running without them
+    if let Err(e) = observe::register(cz) {
+        warn!("register monitors of {} failed: {e}", cz.meta.name);
+    }
     Ok(())
 }
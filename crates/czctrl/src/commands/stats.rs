@@ -0,0 +1,200 @@
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    thread::sleep,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Ok, Result};
+use clap::Parser;
+use libcz::{default_workdir, ControlZone, CZ_CONFIG};
+use libvm::{
+    stats::{domain_stats, StatsGroup},
+    Libvirt,
+};
+use serde::Serialize;
+
+use crate::{
+    config::{DEFAULT_STATS_INTERVAL, DEFAUL_LIBVIRT_URI},
+    vruntime::VRuntimeType,
+    GloablOpts,
+};
+
+const STATS_GROUPS: [StatsGroup; 5] = [
+    StatsGroup::Perf,
+    StatsGroup::Vcpu,
+    StatsGroup::Balloon,
+    StatsGroup::Block,
+    StatsGroup::Net,
+];
+
+#[derive(Parser, Debug)]
+pub struct Stats {
+    /// Control Zone Config
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+
+    /// Refresh stats until interrupted
+    #[arg(short, long)]
+    watch: bool,
+
+    /// Seconds between refreshes
+    #[arg(long, default_value_t = DEFAULT_STATS_INTERVAL)]
+    interval: u64,
+
+    /// Print stats as json lines
+    #[arg(long)]
+    json: bool,
+
+    /// Name of Control Zone
+    control_zone: String,
+}
+
+/// ratios derived from perf counters
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct Derived {
+    /// instructions per cycle
+    ipc: Option<f64>,
+    /// cache misses per cache reference
+    cache_miss_rate: Option<f64>,
+}
+
+impl Derived {
+    /// computed over the interval since `prev`, or since boot if none
+    fn new(prev: Option<&BTreeMap<String, f64>>, curr: &BTreeMap<String, f64>) -> Self {
+        let delta = |field: &str| {
+            let curr = curr.get(field)?;
+            let prev = prev.map_or(Some(&0.0), |prev| prev.get(field))?;
+            // counters restart if perf event re-enabled
+            (curr >= prev).then_some(curr - prev)
+        };
+        let ratio = |num: &str, den: &str| {
+            let den = delta(den)?;
+            (den > 0.0).then_some(delta(num)? / den)
+        };
+
+        Derived {
+            ipc: ratio("perf.instructions", "perf.cpu_cycles"),
+            cache_miss_rate: ratio("perf.cache_misses", "perf.cache_references"),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StatsRecord<'a> {
+    zone: &'a str,
+    timestamp: u64,
+    #[serde(flatten)]
+    derived: &'a Derived,
+    stats: &'a BTreeMap<String, f64>,
+}
+
+pub fn stats(args: Stats, global_opts: &GloablOpts) -> Result<()> {
+    if global_opts.vruntime != VRuntimeType::Libvirt {
+        bail!("stats is only supported by libvirt vruntime")
+    }
+
+    let full_config = match args.config {
+        Some(path) => path,
+        None => default_workdir(&args.control_zone).join(CZ_CONFIG),
+    };
+    let cz = ControlZone::new_from_full_config(&full_config)
+        .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+
+    let libvirt = Libvirt::new(DEFAUL_LIBVIRT_URI)?;
+    let sample = || -> Result<BTreeMap<String, f64>> {
+        match domain_stats(&libvirt, &cz.meta.name, &STATS_GROUPS)? {
+            Some(stats) => Ok(stats),
+            None => bail!("control zone {} is not running", cz.meta.name),
+        }
+    };
+
+    let mut prev: Option<BTreeMap<String, f64>> = None;
+    loop {
+        let curr = sample()?;
+        let derived = Derived::new(prev.as_ref(), &curr);
+
+        if args.json {
+            let record = StatsRecord {
+                zone: &cz.meta.name,
+                timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+                derived: &derived,
+                stats: &curr,
+            };
+            println!("{}", serde_json::to_string(&record)?);
+        } else {
+            if args.watch {
+                // clear screen and move cursor home
+                print!("\x1b[2J\x1b[H");
+            }
+            print_stats(&cz.meta.name, &derived, &curr);
+        }
+
+        if !args.watch {
+            return Ok(());
+        }
+        prev = Some(curr);
+        sleep(Duration::from_secs(args.interval));
+    }
+}
+
+fn print_stats(zone: &str, derived: &Derived, stats: &BTreeMap<String, f64>) {
+    let ratio = |v: Option<f64>| v.map_or(String::from("-"), |v| format!("{:.3}", v));
+    println!("{:32}{}", "ZONE", zone);
+    println!("{:32}{}", "IPC", ratio(derived.ipc));
+    println!("{:32}{}", "CACHE_MISS_RATE", ratio(derived.cache_miss_rate));
+    println!();
+    println!("{:32}VALUE", "FIELD");
+    for (field, value) in stats {
+        println!("{:32}{}", field, value);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::Derived;
+
+    fn stats(fields: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        fields.iter().map(|(k, v)| (k.to_string(), *v)).collect()
+    }
+
+    #[test]
+    fn test_derived() {
+        let prev = stats(&[
+            ("perf.cpu_cycles", 1000.0),
+            ("perf.instructions", 1500.0),
+            ("perf.cache_references", 100.0),
+            ("perf.cache_misses", 10.0),
+        ]);
+        let curr = stats(&[
+            ("perf.cpu_cycles", 3000.0),
+            ("perf.instructions", 5500.0),
+            ("perf.cache_references", 300.0),
+            ("perf.cache_misses", 60.0),
+        ]);
+
+        // since boot
+        assert_eq!(
+            Derived::new(None, &prev),
+            Derived {
+                ipc: Some(1.5),
+                cache_miss_rate: Some(0.1),
+            }
+        );
+        // over interval
+        assert_eq!(
+            Derived::new(Some(&prev), &curr),
+            Derived {
+                ipc: Some(2.0),
+                cache_miss_rate: Some(0.25),
+            }
+        );
+        // perf disabled or idle
+        assert_eq!(Derived::new(None, &stats(&[])), Derived::default());
+        assert_eq!(Derived::new(Some(&curr), &curr).ipc, None);
+        // counter went backwards
+        assert_eq!(Derived::new(Some(&curr), &prev).ipc, None);
+    }
+}
@@ -1,12 +1,12 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, bail, Ok, Result};
 use clap::Parser;
-use log::info;
+use log::{info, warn};
 
-use crate::GloablOpts;
+use crate::{commands::observe, config::DEFAULT_STOP_TIMEOUT, GloablOpts};
 
-use libcz::{default_workdir, vruntime::DVRuntime, ControlZone, CZ_CONFIG};
+use libcz::{default_workdir, state::State, vruntime::DVRuntime, ControlZone, CZ_CONFIG};
 
 #[derive(Parser, Debug)]
 pub struct Stop {
@@ -14,6 +14,14 @@ pub struct Stop {
     #[arg(short, long)]
     config: Option<PathBuf>,
 
+    /// Seconds to wait for graceful shutdown before destroying
+    #[arg(short, long, default_value_t = DEFAULT_STOP_TIMEOUT)]
+    timeout: u64,
+
+    /// Destroy without graceful shutdown
+    #[arg(short, long)]
+    force: bool,
+
     /// Name of Control Zone
     control_zone: String,
 }
@@ -31,12 +39,31 @@ pub fn stop(args: Stop, global_opts: &GloablOpts) -> Result<()> {
         return Ok(());
     }
 
+    let _lock = cz.lock(global_opts.lock_wait())?;
     let vruntime: DVRuntime = global_opts.vruntime.into();
-    stop_inner(&mut cz, &vruntime)
+    stop_inner(
+        &mut cz,
+        Duration::from_secs(args.timeout),
+        args.force,
+        &vruntime,
+    )
 }
 
-pub fn stop_inner(cz: &mut ControlZone, vruntime: &DVRuntime) -> Result<()> {
-    if let Err(e) = cz.stop(&vruntime) {
+pub fn stop_inner(
+    cz: &mut ControlZone,
+    timeout: Duration,
+    force: bool,
+    vruntime: &DVRuntime,
+) -> Result<()> {
+    info!("stopping controlzone...");
+    // monitors are looked up through the running vm
+    if matches!(cz.state, State::Running | State::Paused) {
+        if let Err(e) = observe::deregister(cz) {
+            warn!("clean monitors of {} failed: {e}", cz.meta.name);
+        }
+    }
+
+    if let Err(e) = cz.stop(timeout, force, vruntime) {
         bail!("stop {} failed: {e}", cz.meta.name)
     }
 
@@ -1,15 +1,19 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
-use anyhow::{anyhow, Ok, Result};
+use anyhow::{anyhow, bail, Ok, Result};
 use clap::Parser;
 use log::{debug, info};
 
 use crate::{
     commands::{start::start_inner, stop::stop_inner},
+    config::{DEFAULT_STOP_TIMEOUT, DEFAULT_WAIT_TIMEOUT},
     GloablOpts,
 };
 
-use libcz::{default_workdir, vruntime::DVRuntime, ControlZone, UpdateMode, CZ_CONFIG};
+use libcz::{
+    default_workdir, lock::LockWait, state::State, vruntime::DVRuntime, ControlZone, UpdateMode,
+    CZ_CONFIG,
+};
 
 #[derive(Parser, Debug)]
 pub struct Update {
@@ -17,6 +21,10 @@ pub struct Update {
     #[arg(short, long)]
     wait: bool,
 
+    /// Seconds to wait for Vm Boot
+    #[arg(long, default_value_t = DEFAULT_WAIT_TIMEOUT)]
+    wait_timeout: u64,
+
     /// Control Zone Config
     #[arg(short, long, required = true)]
     file: PathBuf,
@@ -41,27 +49,62 @@ pub fn update(args: Update, global_opts: &GloablOpts) -> Result<()> {
         return Ok(());
     }
 
+    let wait = args.wait.then(|| Duration::from_secs(args.wait_timeout));
+    let _lock = curr_cz.lock(global_opts.lock_wait())?;
     let vruntime: DVRuntime = global_opts.vruntime.into();
-    update_innner(&mut curr_cz, new_cz, args.wait, &vruntime)
+    update_innner(
+        &mut curr_cz,
+        new_cz,
+        wait,
+        global_opts.lock_wait(),
+        &vruntime,
+    )
 }
 
 pub fn update_innner(
     curr_cz: &mut ControlZone,
-    new_cz: ControlZone,
-    wait: bool,
+    mut new_cz: ControlZone,
+    wait: Option<Duration>,
+    lock_wait: LockWait,
     vruntime: &DVRuntime,
 ) -> Result<()> {
-    let update_mod = curr_cz.update_config(new_cz)?;
+    let root_lock = curr_cz.lock_root(lock_wait)?;
+    new_cz.allocate_cpus(Some(&curr_cz.resource))?;
+    if let Err(e) = curr_cz.validate_resource(&new_cz.resource) {
+        bail!("invalid resource of {}: {e}", curr_cz.meta.name)
+    }
+
+    let update_mod = curr_cz.update(new_cz, vruntime)?;
+    // cpus are recorded, a reboot below needs no lock of root
+    drop(root_lock);
     debug!("control zone update mode: {:?}", update_mod);
     match update_mod {
         UpdateMode::Reboot => {
-            stop_inner(curr_cz, vruntime)?;
-            start_inner(curr_cz, wait, vruntime)?;
+            stop_inner(
+                curr_cz,
+                Duration::from_secs(DEFAULT_STOP_TIMEOUT),
+                false,
+                vruntime,
+            )?;
+            start_inner(curr_cz, wait, lock_wait, vruntime)?;
 
             info!("control zone {} have updated", curr_cz.meta.name);
             Ok(())
         }
-        UpdateMode::Hot => todo!(),
+        UpdateMode::Hot => {
+            if matches!(curr_cz.state, State::Running | State::Paused) {
+                info!(
+                    "control zone {} have updated without reboot",
+                    curr_cz.meta.name
+                );
+            } else {
+                info!(
+                    "control zone {} have updated, take effect on next start",
+                    curr_cz.meta.name
+                );
+            }
+            Ok(())
+        }
         UpdateMode::Stale => {
             info!("control zone {} have not been changed", curr_cz.meta.name);
             Ok(())
@@ -1,6 +1,21 @@
 // apply
 pub const DEFAUL_LIBVIRT_URI: &str = "qemu:///system";
 
+// start
+pub const DEFAULT_WAIT_TIMEOUT: u64 = 60;
+
+// stop
+pub const DEFAULT_STOP_TIMEOUT: u64 = 30;
+
 // observe
 pub const RESCTL_ROOT: &str = "/sys/fs/resctrl";
 pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+// seconds
+pub const DEFAULT_METRICS_INTERVAL: u64 = 1;
+
+// stats
+// seconds
+pub const DEFAULT_STATS_INTERVAL: u64 = 1;
+
+// exporter
+pub const DEFAULT_EXPORTER_LISTEN: &str = "127.0.0.1:9810";
@@ -1,14 +1,15 @@
-use std::path::PathBuf;
+use std::{path::PathBuf, time::Duration};
 
 use anyhow::Result;
 use clap::{Args, Parser};
-use libcz::WORKDIR_ROOT;
+use libcz::{lock::LockWait, WORKDIR_ROOT};
 use log::error;
 use vruntime::VRuntimeType;
 
 mod commands;
 mod config;
 mod pod;
+mod snapshot;
 mod vruntime;
 
 #[derive(Parser, Debug)]
@@ -22,6 +23,10 @@ enum SubCommand {
     /// Manage Pod of Control Zone
     #[clap(subcommand)]
     Pod(Box<pod::PodCmd>),
+
+    /// Manage Snapshot of Control Zone
+    #[clap(subcommand)]
+    Snapshot(Box<snapshot::SnapshotCmd>),
 }
 
 #[derive(Parser)]
@@ -46,6 +51,14 @@ struct GloablOpts {
 
     #[arg(long, value_enum, default_value_t = VRuntimeType::Libvirt ,global = true)]
     vruntime: VRuntimeType,
+
+    /// Seconds to wait for locks held by other czctrl, forever if not set
+    #[arg(long, global = true, conflicts_with = "no_wait")]
+    wait_lock: Option<u64>,
+
+    /// Fail at once if locks are held by other czctrl
+    #[arg(long, global = true)]
+    no_wait: bool,
 }
 
 impl GloablOpts {
@@ -56,6 +69,14 @@ impl GloablOpts {
             None => PathBuf::from(WORKDIR_ROOT),
         }
     }
+
+    fn lock_wait(&self) -> LockWait {
+        match (self.no_wait, self.wait_lock) {
+            (true, _) => LockWait::NoWait,
+            (false, Some(timeout)) => LockWait::Timeout(Duration::from_secs(timeout)),
+            (false, None) => LockWait::Forever,
+        }
+    }
 }
 
 fn main() -> Result<()> {
@@ -73,6 +94,14 @@ fn main() -> Result<()> {
             }
             commands::AdvanceCmd::List(list) => commands::list::list(list, &opts.global_opts),
             commands::AdvanceCmd::Conn(conn) => commands::conn::conn(conn, &opts.global_opts),
+            commands::AdvanceCmd::Exec(exec) => commands::exec::exec(exec, &opts.global_opts),
+            commands::AdvanceCmd::Reconcile(reconcile) => {
+                commands::reconcile::reconcile(reconcile, &opts.global_opts)
+            }
+            commands::AdvanceCmd::Exporter(exporter) => {
+                commands::exporter::exporter(exporter, &opts.global_opts)
+            }
+            commands::AdvanceCmd::Stats(stats) => commands::stats::stats(stats, &opts.global_opts),
         },
 
         SubCommand::Basic(cmd) => match *cmd {
@@ -81,6 +110,10 @@ fn main() -> Result<()> {
             }
             commands::BasicCmd::Start(start) => commands::start::start(start, &opts.global_opts),
             commands::BasicCmd::Stop(stop) => commands::stop::stop(stop, &opts.global_opts),
+            commands::BasicCmd::Pause(pause) => commands::pause::pause(pause, &opts.global_opts),
+            commands::BasicCmd::Resume(resume) => {
+                commands::resume::resume(resume, &opts.global_opts)
+            }
             commands::BasicCmd::Remove(remove) => {
                 commands::remove::remove(remove, &opts.global_opts)
             }
@@ -98,6 +131,18 @@ fn main() -> Result<()> {
             pod::PodCmd::Delete(delete) => pod::delete::delete(delete, &opts.global_opts),
             pod::PodCmd::Show(show) => pod::show::show(show, &opts.global_opts),
         },
+        SubCommand::Snapshot(cmd) => match *cmd {
+            snapshot::SnapshotCmd::Create(create) => {
+                snapshot::create::create(create, &opts.global_opts)
+            }
+            snapshot::SnapshotCmd::List(list) => snapshot::list::list(list, &opts.global_opts),
+            snapshot::SnapshotCmd::Restore(restore) => {
+                snapshot::restore::restore(restore, &opts.global_opts)
+            }
+            snapshot::SnapshotCmd::Delete(delete) => {
+                snapshot::delete::delete(delete, &opts.global_opts)
+            }
+        },
     };
 
     if let Err(ref e) = cmd_result {
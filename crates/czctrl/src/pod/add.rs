@@ -1,8 +1,8 @@
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, bail, Result};
 use clap::Parser;
-use libcz::{state::State, ControlZone, CZ_CONFIG, POD_APPLY_DIR, POD_DIR};
+use libcz::{state::State, ControlZone, CHANNEL_TIMEOUT, CZ_CONFIG, POD_APPLY_DIR, POD_DIR};
 
 use crate::GloablOpts;
 
@@ -21,21 +21,27 @@ pub fn add(args: Add, global_opts: &GloablOpts) -> Result<()> {
     }
 
     let full_config = global_opts.root_dir().join(args.zone).join(CZ_CONFIG);
-    let cz = ControlZone::new_from_full_config(&full_config)
+    let mut cz = ControlZone::new_from_full_config(&full_config)
         .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+    let _lock = cz.lock(global_opts.lock_wait())?;
 
     if cz.state != State::Running {
         bail!("contol zone {} unable to create pod", cz.meta.name);
     }
 
-    let pod_apply_dir = PathBuf::from(cz.meta.share_folder)
-        .join(POD_DIR)
-        .join(POD_APPLY_DIR);
-
     let Some(yaml_name) = args.yaml.file_name().and_then(|fname| fname.to_str()) else {
         bail!("parse yaml name failed");
     };
 
+    if let Some(mut client) = cz.channel(Duration::from_secs(CHANNEL_TIMEOUT)) {
+        return client.pod_apply(yaml_name, fs::read_to_string(&args.yaml)?);
+    }
+
+    // fallback to share folder
+    let pod_apply_dir = PathBuf::from(cz.meta.share_folder)
+        .join(POD_DIR)
+        .join(POD_APPLY_DIR);
+
     let des_yaml = pod_apply_dir.join(yaml_name);
     fs::copy(args.yaml, des_yaml)?;
 
@@ -1,8 +1,8 @@
-use std::{fs, path::PathBuf};
+use std::{fs, path::PathBuf, time::Duration};
 
 use anyhow::{anyhow, bail, Ok, Result};
 use clap::Parser;
-use libcz::{state::State, ControlZone, CZ_CONFIG, POD_APPLY_DIR, POD_DIR};
+use libcz::{state::State, ControlZone, CHANNEL_QUERY_TIMEOUT, CZ_CONFIG, POD_APPLY_DIR, POD_DIR};
 
 use crate::GloablOpts;
 
@@ -15,6 +15,7 @@ pub struct Show {
 
 pub fn show(args: Show, global_opts: &GloablOpts) -> Result<()> {
     let full_config = global_opts.root_dir().join(args.zone).join(CZ_CONFIG);
+    // read only, not locked
     let cz = ControlZone::new_from_full_config(&full_config)
         .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
 
@@ -22,6 +23,15 @@ pub fn show(args: Show, global_opts: &GloablOpts) -> Result<()> {
         bail!("contol zone {} unable to create pod", cz.meta.name);
     }
 
+    if let Some(mut client) = cz.channel(Duration::from_secs(CHANNEL_QUERY_TIMEOUT)) {
+        client
+            .pod_list()?
+            .iter()
+            .for_each(|pod| println!("{:?}", pod));
+        return Ok(());
+    }
+
+    // fallback to share folder
     let pod_apply_dir = PathBuf::from(cz.meta.share_folder)
         .join(POD_DIR)
         .join(POD_APPLY_DIR);
@@ -0,0 +1,42 @@
+use anyhow::{anyhow, bail, Ok, Result};
+use clap::Parser;
+use libcz::{vruntime::DVRuntime, ControlZone, CZ_CONFIG};
+use log::info;
+
+use crate::GloablOpts;
+
+#[derive(Parser, Debug)]
+pub struct Create {
+    /// Name of Snapshot, generated from time if not set
+    #[arg(short, long)]
+    name: Option<String>,
+
+    /// Name of Control Zone
+    control_zone: String,
+}
+
+pub fn create(args: Create, global_opts: &GloablOpts) -> Result<()> {
+    let full_config = global_opts
+        .root_dir()
+        .join(args.control_zone)
+        .join(CZ_CONFIG);
+    let mut cz = ControlZone::new_from_full_config(&full_config)
+        .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+
+    if global_opts.dry_run {
+        return Ok(());
+    }
+
+    let _lock = cz.lock(global_opts.lock_wait())?;
+    let vruntime: DVRuntime = global_opts.vruntime.into();
+    let snapshot = match cz.create_snapshot(args.name, &vruntime) {
+        Result::Ok(snapshot) => snapshot,
+        Err(e) => bail!("snapshot {} failed: {e}", cz.meta.name),
+    };
+
+    info!(
+        "snapshot {} of {} created, memory included: {}",
+        snapshot.name, cz.meta.name, snapshot.memory
+    );
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use anyhow::{anyhow, bail, Ok, Result};
+use clap::Parser;
+use libcz::{vruntime::DVRuntime, ControlZone, CZ_CONFIG};
+use log::info;
+
+use crate::GloablOpts;
+
+#[derive(Parser, Debug)]
+pub struct Delete {
+    /// Name of Snapshot
+    #[arg(short, long, required = true)]
+    name: String,
+
+    /// Name of Control Zone
+    control_zone: String,
+}
+
+pub fn delete(args: Delete, global_opts: &GloablOpts) -> Result<()> {
+    let full_config = global_opts
+        .root_dir()
+        .join(args.control_zone)
+        .join(CZ_CONFIG);
+    let mut cz = ControlZone::new_from_full_config(&full_config)
+        .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+
+    if global_opts.dry_run {
+        return Ok(());
+    }
+
+    let _lock = cz.lock(global_opts.lock_wait())?;
+    let vruntime: DVRuntime = global_opts.vruntime.into();
+    if let Err(e) = cz.delete_snapshot(&args.name, &vruntime) {
+        bail!("delete snapshot of {} failed: {e}", cz.meta.name)
+    }
+
+    info!("snapshot {} of {} deleted", args.name, cz.meta.name);
+    Ok(())
+}
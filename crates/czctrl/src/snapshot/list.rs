@@ -0,0 +1,25 @@
+use anyhow::{anyhow, Ok, Result};
+use clap::Parser;
+use libcz::{ControlZone, CZ_CONFIG};
+
+use crate::GloablOpts;
+
+use super::print_snapshots;
+
+#[derive(Parser, Debug)]
+pub struct List {
+    /// Name of Control Zone
+    control_zone: String,
+}
+
+pub fn list(args: List, global_opts: &GloablOpts) -> Result<()> {
+    let full_config = global_opts
+        .root_dir()
+        .join(args.control_zone)
+        .join(CZ_CONFIG);
+    let cz = ControlZone::new_from_full_config(&full_config)
+        .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+
+    print_snapshots(&cz.list_snapshots()?);
+    Ok(())
+}
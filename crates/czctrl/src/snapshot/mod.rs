@@ -0,0 +1,34 @@
+use clap::Parser;
+
+use self::{create::Create, delete::Delete, list::List, restore::Restore};
+
+pub mod create;
+pub mod delete;
+pub mod list;
+pub mod restore;
+
+#[derive(Parser, Debug)]
+pub enum SnapshotCmd {
+    /// Snapshot Rootfs and Config of Control Zone
+    Create(Create),
+
+    /// List Snapshots of Control Zone
+    List(List),
+
+    /// Restore Control Zone to Snapshot
+    Restore(Restore),
+
+    /// Delete Snapshot of Control Zone
+    Delete(Delete),
+}
+
+/// print snapshots as a table
+pub fn print_snapshots(snapshots: &[libcz::snapshot::Snapshot]) {
+    println!("{:24}{:16}{:8}", "NAME", "CREATED", "MEMORY");
+    snapshots.iter().for_each(|snapshot| {
+        println!(
+            "{:24}{:<16}{:8}",
+            snapshot.name, snapshot.created, snapshot.memory
+        )
+    });
+}
@@ -0,0 +1,38 @@
+use anyhow::{anyhow, bail, Ok, Result};
+use clap::Parser;
+use libcz::{vruntime::DVRuntime, ControlZone, CZ_CONFIG};
+use log::info;
+
+use crate::GloablOpts;
+
+#[derive(Parser, Debug)]
+pub struct Restore {
+    /// Name of Snapshot
+    #[arg(short, long, required = true)]
+    name: String,
+
+    /// Name of Control Zone
+    control_zone: String,
+}
+
+pub fn restore(args: Restore, global_opts: &GloablOpts) -> Result<()> {
+    let full_config = global_opts
+        .root_dir()
+        .join(args.control_zone)
+        .join(CZ_CONFIG);
+    let mut cz = ControlZone::new_from_full_config(&full_config)
+        .map_err(|e| anyhow!("error parsing config {:#?}: {}", full_config, e))?;
+
+    if global_opts.dry_run {
+        return Ok(());
+    }
+
+    let _lock = cz.lock(global_opts.lock_wait())?;
+    let vruntime: DVRuntime = global_opts.vruntime.into();
+    if let Err(e) = cz.restore_snapshot(&args.name, &vruntime) {
+        bail!("restore {} failed: {e}", cz.meta.name)
+    }
+
+    info!("{} restored to snapshot {}", cz.meta.name, args.name);
+    Ok(())
+}
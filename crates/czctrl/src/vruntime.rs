@@ -6,6 +6,8 @@ use libcz::vruntime::DVRuntime;
 pub enum VRuntimeType {
     Libvirt,
     Qemu,
+    #[cfg(feature = "fake")]
+    Fake,
 }
 
 impl From<VRuntimeType> for DVRuntime {
@@ -13,6 +15,8 @@ impl From<VRuntimeType> for DVRuntime {
         match t {
             VRuntimeType::Libvirt => libvm::new_libvirt_vruntime(DEFAUL_LIBVIRT_URI),
             VRuntimeType::Qemu => libvm::new_qemu_vruntime(),
+            #[cfg(feature = "fake")]
+            VRuntimeType::Fake => libvm::new_fake_vruntime(),
         }
     }
 }
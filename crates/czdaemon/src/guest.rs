@@ -1,5 +1,9 @@
+use std::fs;
+
 use anyhow::{bail, Ok};
 
+const BOOT_ID: &str = "/proc/sys/kernel/random/boot_id";
+
 #[derive(Debug)]
 pub struct Info {
     pub ip: String,
@@ -23,6 +27,11 @@ fn first_ip_on_first_nic() -> anyhow::Result<String> {
     }
 }
 
+/// boot id of current guest kernel
+pub fn boot_id() -> anyhow::Result<String> {
+    Ok(fs::read_to_string(BOOT_ID)?.trim().to_owned())
+}
+
 pub fn fetch_info() -> anyhow::Result<Info> {
     // first ip on first nic
 
@@ -0,0 +1,169 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::AtomicBool,
+        mpsc::{self, Sender},
+        Arc,
+    },
+};
+
+use anyhow::bail;
+
+use libcz::{
+    channel::{Addr, Listener},
+    ready::Ready,
+    state::State,
+    CHANNEL_VSOCK_PORT, INFO_DIR, IP_FILE, POD_APPLY_DIR, POD_CRUNTIME_LOG, POD_DIR, POD_DOWN_DIR,
+    READY_FILE, STATE_FILE,
+};
+use log::{debug, info, warn};
+use server::Server;
+use watcher::{watcher_loop, Claimed};
+use worker::{Event, Worker};
+
+use crate::guest::{boot_id, fetch_info};
+
+mod guest;
+mod server;
+mod watcher;
+mod worker;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// listen on any cid in guest
+const VMADDR_CID_ANY: u32 = u32::MAX;
+pub const POWEROFF: &str = "poweroff";
+
+/// controlzone daemon serving one share folder
+pub struct Daemon {
+    /// share folder mounted from host
+    pub share_root: PathBuf,
+    /// container runtime
+    pub cruntime: String,
+    /// control channel address, vsock on any cid if none
+    pub listen: Option<Addr>,
+    /// command to power off guest on shutdown request,
+    /// daemon just exits if none
+    pub poweroff: Option<String>,
+}
+
+impl Daemon {
+    /// serve until `want_to_stop` is set, state is reported stopped at last
+    pub fn run(self, want_to_stop: Arc<AtomicBool>) -> anyhow::Result<()> {
+        let share_root = self.share_root;
+        debug!("controlzone daemon starting");
+
+        let boot_id = boot_id()?;
+        let ready_file = share_root.join(INFO_DIR).join(READY_FILE);
+        let pod_root = share_root.join(POD_DIR);
+        let (ip, tx, worker) = match init(&share_root, self.cruntime) {
+            Ok(inited) => inited,
+            Err(e) => {
+                // let host know why control zone not coming up
+                Ready::failed(boot_id, VERSION.to_owned(), &e)?.write(&ready_file)?;
+                return Err(e);
+            }
+        };
+
+        // start control channel, share folder still works without it
+        let claimed = Claimed::default();
+        let listen = self.listen.unwrap_or(Addr::Vsock {
+            cid: VMADDR_CID_ANY,
+            port: CHANNEL_VSOCK_PORT,
+        });
+        match Listener::bind(&listen) {
+            Ok(listener) => {
+                let server = Server {
+                    pod_root: pod_root.clone(),
+                    ready_file: ready_file.clone(),
+                    worker,
+                    claimed: claimed.clone(),
+                    poweroff: self.poweroff,
+                    want_to_stop: want_to_stop.clone(),
+                };
+                server.run(listener);
+                info!("control channel listening on {listen}");
+            }
+            Err(e) => warn!("control channel unavailable: {e}"),
+        }
+
+        Ready::new(boot_id, VERSION.to_owned(), ip)?.write(&ready_file)?;
+        info!("controlzone ready");
+
+        // start dir watcher
+        watcher_loop(pod_root, tx, claimed, want_to_stop)?;
+
+        // report stopped, so that host could stop control zone gracefully
+        sync_state(&share_root.join(INFO_DIR).join(STATE_FILE), State::Stopped)?;
+        info!("controlzone state updated");
+        Ok(())
+    }
+}
+
+/// parse control channel address given by command line
+pub fn parse_listen(addr: Option<String>) -> anyhow::Result<Option<Addr>> {
+    addr.map(|addr| Addr::from_str(&addr)).transpose()
+}
+
+/// init worker and sync state, return ip of guest
+fn init(
+    share_root: &Path,
+    cruntime: String,
+) -> anyhow::Result<(Option<String>, Sender<Event>, Worker)> {
+    // sync info
+    let ip = match fetch_info() {
+        Ok(info) => {
+            fs::write(share_root.join(INFO_DIR).join(IP_FILE), &info.ip)?;
+            Some(info.ip)
+        }
+        Err(e) => {
+            warn!("fetch info failed: {e}");
+            None
+        }
+    };
+    debug!("info fetched");
+
+    // init worker
+    let pod_root = share_root.join(POD_DIR);
+    let Some(log_file) = pod_root
+        .join(POD_CRUNTIME_LOG)
+        .to_str()
+        .and_then(|s| Some(s.to_owned()))
+    else {
+        bail!("fail to genrate pod")
+    };
+
+    let apply_dir = pod_root.join(POD_APPLY_DIR);
+    if !apply_dir.exists() {
+        fs::create_dir(apply_dir)?;
+    }
+
+    let down_dir = pod_root.join(POD_DOWN_DIR);
+    if !down_dir.exists() {
+        fs::create_dir(down_dir)?;
+    }
+    debug!("pod dir ready");
+
+    let (tx, rx) = mpsc::channel();
+    let worker = Worker { cruntime, log_file };
+    worker.run(rx);
+    info!("worker initialized");
+
+    // sync state
+    let state_file = share_root.join(INFO_DIR).join(STATE_FILE);
+    sync_state(&state_file, State::Running)?;
+    info!("controlzone state updated");
+    Ok((ip, tx, worker))
+}
+
+fn sync_state(state_file: &PathBuf, new_state: State) -> anyhow::Result<()> {
+    let state = State::from_str(&fs::read_to_string(state_file)?)?;
+    if !state.check_update(new_state)? {
+        let mut f = fs::File::create(state_file)?;
+        f.write_all(new_state.to_string().as_bytes())?;
+        f.sync_all()?;
+    };
+    Ok(())
+}
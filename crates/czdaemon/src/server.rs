@@ -0,0 +1,301 @@
+use std::{
+    fs,
+    io::{ErrorKind, Read},
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Sender},
+        Arc,
+    },
+    thread::{self, sleep},
+    time::Duration,
+};
+
+use anyhow::{bail, Ok};
+use libcz::{
+    channel::{read_frame, write_frame, Listener, Request, Response, Stream},
+    ready::Ready,
+    POD_APPLY_DIR, POD_DOWN_DIR,
+};
+use log::{debug, error, info};
+
+use crate::{
+    watcher::Claimed,
+    worker::{Event, PodOps, Worker},
+    VERSION,
+};
+
+/// serve requests from czctrl over control channel
+pub struct Server {
+    pub pod_root: PathBuf,
+    pub ready_file: PathBuf,
+    pub worker: Worker,
+    pub claimed: Claimed,
+    /// power off command, daemon stops itself if none
+    pub poweroff: Option<String>,
+    pub want_to_stop: Arc<AtomicBool>,
+}
+
+impl Server {
+    /// accept connections in background, one thread per connection
+    pub fn run(self, listener: Listener) {
+        let server = Arc::new(self);
+        thread::spawn(move || loop {
+            let stream = match listener.accept() {
+                Result::Ok(stream) => stream,
+                Err(e) => {
+                    error!("accept failed: {e}");
+                    continue;
+                }
+            };
+
+            let server = server.clone();
+            thread::spawn(move || {
+                if let Err(e) = server.serve(stream) {
+                    error!("serve failed: {e}");
+                }
+            });
+        });
+    }
+
+    fn serve(&self, mut stream: Stream) -> anyhow::Result<()> {
+        loop {
+            let req: Request = match read_frame(&mut stream) {
+                Result::Ok(req) => req,
+                Err(e) => match e.downcast_ref::<std::io::Error>() {
+                    Some(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+                    _ => bail!(e),
+                },
+            };
+            debug!("request: {:?}", req);
+
+            // output of exec is streamed
+            let req = match req {
+                Request::Exec { cmd } => {
+                    self.exec(&mut stream, cmd)?;
+                    continue;
+                }
+                req => req,
+            };
+
+            let shutdown = req == Request::Shutdown;
+            let resp = match self.handle(req) {
+                Result::Ok(resp) => resp,
+                Err(e) => Response::Error(e.to_string()),
+            };
+            write_frame(&mut stream, &resp)?;
+
+            // power off after responded
+            if shutdown && resp == Response::Ok {
+                info!("shutdown requested");
+                let poweroff = self.poweroff.clone();
+                let want_to_stop = self.want_to_stop.clone();
+                thread::spawn(move || {
+                    sleep(Duration::from_millis(100));
+                    let Some(poweroff) = poweroff else {
+                        want_to_stop.store(true, Ordering::SeqCst);
+                        return;
+                    };
+                    if let Err(e) = Command::new(poweroff).status() {
+                        error!("poweroff failed: {e}");
+                    }
+                });
+            }
+        }
+    }
+
+    fn handle(&self, req: Request) -> anyhow::Result<Response> {
+        match req {
+            Request::Ping => Ok(Response::Pong {
+                version: VERSION.to_owned(),
+            }),
+            Request::Info => match Ready::read(&self.ready_file)? {
+                Some(ready) => Ok(Response::Info(ready)),
+                None => bail!("not ready"),
+            },
+            Request::PodApply { name, yaml } => {
+                let des_yaml = self.pod_yaml(POD_APPLY_DIR, &name)?;
+                if des_yaml.exists() {
+                    bail!("pod {name} already applied")
+                }
+
+                self.claim(&des_yaml, PodOps::Apply, |des_yaml| {
+                    fs::write(des_yaml, yaml)
+                })?;
+                Ok(Response::Ok)
+            }
+            Request::PodDown { name } => {
+                let src_yaml = self.pod_yaml(POD_APPLY_DIR, &name)?;
+                if !src_yaml.exists() {
+                    bail!("pod {name} not applied")
+                }
+
+                let des_yaml = self.pod_yaml(POD_DOWN_DIR, &name)?;
+                self.claim(&des_yaml, PodOps::Down, |des_yaml| {
+                    fs::rename(&src_yaml, des_yaml)
+                })?;
+                Ok(Response::Ok)
+            }
+            Request::PodList => {
+                let mut pods: Vec<String> = fs::read_dir(self.pod_root.join(POD_APPLY_DIR))?
+                    .filter_map(|entry| entry.ok())
+                    .filter_map(|entry| entry.file_name().to_str().map(|s| s.to_owned()))
+                    .collect();
+                pods.sort();
+                Ok(Response::Pods(pods))
+            }
+            Request::Shutdown => Ok(Response::Ok),
+            Request::Exec { .. } => bail!("exec could not be handled here"),
+        }
+    }
+
+    /// run command and stream its output, exit code is sent at last
+    fn exec(&self, stream: &mut Stream, cmd: Vec<String>) -> anyhow::Result<()> {
+        let Some((prog, args)) = cmd.split_first() else {
+            return write_frame(stream, &Response::Error(String::from("empty command")));
+        };
+
+        let mut cmd = Command::new(prog);
+        cmd.args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        debug!("{:?}", cmd);
+
+        let mut child = match cmd.spawn() {
+            Result::Ok(child) => child,
+            Err(e) => {
+                return write_frame(
+                    stream,
+                    &Response::Error(format!("spawn {prog} failed: {e}")),
+                )
+            }
+        };
+
+        let (tx, rx) = mpsc::channel();
+        if let Some(stdout) = child.stdout.take() {
+            forward(stdout, Response::Stdout, tx.clone());
+        }
+        if let Some(stderr) = child.stderr.take() {
+            forward(stderr, Response::Stderr, tx);
+        }
+
+        // ends when both output closed
+        for resp in rx {
+            if let Err(e) = write_frame(stream, &resp) {
+                // client gone, no one cares about the command
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!(e)
+            }
+        }
+
+        let status = child.wait()?;
+        let code = status
+            .code()
+            .unwrap_or_else(|| 128 + status.signal().unwrap_or_default());
+        write_frame(stream, &Response::Exit { code })
+    }
+
+    /// path of pod yaml in share folder, name should be a plain file name
+    fn pod_yaml(&self, dir: &str, name: &str) -> anyhow::Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains('/') {
+            bail!("invalid pod name: {name}")
+        }
+        Ok(self.pod_root.join(dir).join(name))
+    }
+
+    /// claim yaml before it is placed, so that watcher skips it,
+    /// then run pod event and wait for it
+    fn claim<F>(&self, path: &Path, ops: PodOps, place: F) -> anyhow::Result<()>
+    where
+        F: FnOnce(&Path) -> std::io::Result<()>,
+    {
+        let Some(yaml) = path.to_str().map(|s| s.to_owned()) else {
+            bail!("invalid pod yaml: {:?}", path)
+        };
+
+        if let Result::Ok(mut claimed) = self.claimed.lock() {
+            claimed.insert(yaml.clone());
+        }
+
+        if let Err(e) = place(path) {
+            if let Result::Ok(mut claimed) = self.claimed.lock() {
+                claimed.remove(&yaml);
+            }
+            bail!(e)
+        }
+
+        self.worker.exec(&Event { ops, yaml })
+    }
+}
+
+/// forward output of command chunk by chunk
+fn forward<R: Read + Send + 'static>(
+    mut r: R,
+    wrap: fn(Vec<u8>) -> Response,
+    tx: Sender<Response>,
+) {
+    thread::spawn(move || {
+        let mut buf = [0u8; 4096];
+        loop {
+            match r.read(&mut buf) {
+                Result::Ok(0) | Err(_) => break,
+                Result::Ok(n) => {
+                    if tx.send(wrap(buf[..n].to_vec())).is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use libcz::channel::{Addr, Client, Listener};
+
+    use super::Server;
+    use crate::worker::Worker;
+
+    #[test]
+    fn test_exec() {
+        let root = std::env::temp_dir().join("czdaemon_test_exec");
+        std::fs::create_dir_all(&root).unwrap();
+        let addr = Addr::Unix(root.join("channel.sock"));
+
+        let server = Server {
+            pod_root: root.clone(),
+            ready_file: root.join("ready"),
+            worker: Worker {
+                cruntime: String::from("true"),
+                log_file: String::from("/dev/null"),
+            },
+            claimed: Default::default(),
+            poweroff: None,
+            want_to_stop: Default::default(),
+        };
+        server.run(Listener::bind(&addr).unwrap());
+
+        let mut client = Client::connect(&addr, Duration::from_secs(5)).unwrap();
+        let (mut stdout, mut stderr) = (vec![], vec![]);
+        let cmd = ["sh", "-c", "echo out; echo err >&2; exit 3"]
+            .map(String::from)
+            .to_vec();
+        assert_eq!(client.exec(cmd, &mut stdout, &mut stderr).unwrap(), 3);
+        assert_eq!(stdout, b"out\n");
+        assert_eq!(stderr, b"err\n");
+
+        // connection is kept after exec
+        assert!(client.ping().is_ok());
+        assert!(client
+            .exec(vec![String::from("/nonexistent")], &mut stdout, &mut stderr)
+            .is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
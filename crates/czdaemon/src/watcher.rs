@@ -1,8 +1,9 @@
 use std::{
+    collections::HashSet,
     path::PathBuf,
     sync::{
         atomic::{AtomicBool, Ordering},
-        Arc,
+        Arc, Mutex,
     },
     thread::sleep,
     time::Duration,
@@ -21,17 +22,30 @@ use std::sync::mpsc;
 #[cfg(feature = "poll_watcher")]
 use notify::event::RemoveKind;
 
-fn init_signal(flag: Arc<AtomicBool>) -> anyhow::Result<()> {
-    signal_hook::flag::register(signal_hook::consts::SIGINT, flag.clone())?;
-    signal_hook::flag::register(signal_hook::consts::SIGTERM, flag.clone())?;
-    Ok(())
+/// yaml already handled by control channel, should not be handled again
+pub type Claimed = Arc<Mutex<HashSet<String>>>;
+
+fn send_event(tx: &mpsc::Sender<Event>, claimed: &Claimed, ops: PodOps, yaml: String) {
+    if claimed
+        .lock()
+        .is_ok_and(|mut claimed| claimed.remove(&yaml))
+    {
+        debug!("{yaml} handled by channel, skip");
+        return;
+    }
+
+    if let Err(e) = tx.send(Event { ops, yaml }) {
+        debug!("send event failed: {e}")
+    };
 }
 
 #[cfg(feature = "poll_watcher")]
-pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Result<()> {
-    let want_to_stop = Arc::new(AtomicBool::new(false));
-    init_signal(want_to_stop.clone())?;
-
+pub fn watcher_loop(
+    pod_root: PathBuf,
+    tx: mpsc::Sender<Event>,
+    claimed: Claimed,
+    want_to_stop: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
     let root = pod_root.clone();
     let event_handler = move |res: Result<notify::Event, notify::Error>| match res {
         Result::Ok(event) => match event.kind {
@@ -52,12 +66,7 @@ pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Resul
                     return;
                 };
 
-                if let Err(e) = tx.send(Event {
-                    ops: PodOps::Apply,
-                    yaml,
-                }) {
-                    debug!("send event failed: {e}")
-                };
+                send_event(&tx, &claimed, PodOps::Apply, yaml);
             }
             notify::EventKind::Remove(RemoveKind::Any) => {
                 let src_file = &event.paths[0];
@@ -81,12 +90,7 @@ pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Resul
                     return;
                 };
 
-                if let Err(e) = tx.send(Event {
-                    ops: PodOps::Down,
-                    yaml,
-                }) {
-                    debug!("send event failed: {e}")
-                };
+                send_event(&tx, &claimed, PodOps::Down, yaml);
             }
             _ => {}
         },
@@ -111,10 +115,12 @@ pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Resul
 }
 
 #[cfg(not(feature = "poll_watcher"))]
-pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Result<()> {
-    let want_to_stop = Arc::new(AtomicBool::new(false));
-    init_signal(want_to_stop.clone())?;
-
+pub fn watcher_loop(
+    pod_root: PathBuf,
+    tx: mpsc::Sender<Event>,
+    claimed: Claimed,
+    want_to_stop: Arc<AtomicBool>,
+) -> anyhow::Result<()> {
     let event_handler = move |res: Result<notify::Event, notify::Error>| match res {
         Result::Ok(event) => {
             debug!("{:?}", event);
@@ -134,12 +140,7 @@ pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Resul
                         return;
                     };
 
-                    if let Err(e) = tx.send(Event {
-                        ops: PodOps::Apply,
-                        yaml,
-                    }) {
-                        debug!("send event failed: {e}")
-                    };
+                    send_event(&tx, &claimed, PodOps::Apply, yaml);
                 }
                 notify::EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => {
                     let src_file = &event.paths[0];
@@ -161,12 +162,7 @@ pub fn watcher_loop(pod_root: PathBuf, tx: mpsc::Sender<Event>) -> anyhow::Resul
                         return;
                     };
 
-                    if let Err(e) = tx.send(Event {
-                        ops: PodOps::Down,
-                        yaml,
-                    }) {
-                        debug!("send event failed: {e}")
-                    };
+                    send_event(&tx, &claimed, PodOps::Down, yaml);
                 }
                 _ => {}
             }
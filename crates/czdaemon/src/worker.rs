@@ -5,6 +5,7 @@ use std::{
     thread,
 };
 
+use anyhow::bail;
 use log::{debug, error, info};
 
 #[derive(Debug)]
@@ -22,6 +23,7 @@ pub struct Event {
 unsafe impl Sync for Event {}
 unsafe impl Send for Event {}
 
+#[derive(Clone)]
 pub struct Worker {
     pub cruntime: String,
     pub log_file: String,
@@ -29,68 +31,64 @@ pub struct Worker {
 
 impl Worker {
     pub fn run(&self, rx: Receiver<Event>) {
-        let cruntime = self.cruntime.clone();
-        let log_file = self.log_file.clone();
+        let worker = self.clone();
 
         thread::spawn(move || loop {
             match rx.recv() {
-                Ok(event) => {
-                    Command::new(&cruntime).arg("kube").arg("play");
-
-                    let mut cmd = Command::new(&cruntime);
-                    cmd.arg("kube");
-                    match event.ops {
-                        PodOps::Apply => {
-                            info!("run pod: {}", event.yaml);
-                            cmd.arg("play");
-                        }
-                        PodOps::Down => {
-                            info!("remove pod: {}", event.yaml);
-                            cmd.arg("down");
-                        }
-                    };
-
-                    cmd.arg(&event.yaml);
-                    debug!("{:?}", cmd);
+                Ok(event) => match worker.exec(&event) {
+                    Ok(_) => info!("{:?} successfully", event.ops),
+                    Err(e) => error!("{e}"),
+                },
+                Err(_) => break,
+            }
+        });
+    }
 
-                    let Ok(file) = OpenOptions::new().create(true).append(true).open(&log_file)
-                    else {
-                        error!("command run failed");
-                        continue;
-                    };
+    /// run pod event and wait for it
+    pub fn exec(&self, event: &Event) -> anyhow::Result<()> {
+        let mut cmd = Command::new(&self.cruntime);
+        cmd.arg("kube");
+        match event.ops {
+            PodOps::Apply => {
+                info!("run pod: {}", event.yaml);
+                cmd.arg("play");
+            }
+            PodOps::Down => {
+                info!("remove pod: {}", event.yaml);
+                cmd.arg("down");
+            }
+        };
 
-                    cmd.stdout(Stdio::from(file));
-                    let Ok(mut cmd_fd) = cmd.spawn().map_err(|e| error!("command run failed: {e}"))
-                    else {
-                        continue;
-                    };
+        cmd.arg(&event.yaml);
+        debug!("{:?}", cmd);
 
-                    let success = match cmd_fd.wait() {
-                        Ok(code) => code.success(),
-                        Err(e) => {
-                            error!("could not wait for command: {e}");
-                            false
-                        }
-                    };
+        let Ok(file) = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.log_file)
+        else {
+            bail!("command run failed")
+        };
 
-                    if !success {
-                        error!("command run failed");
-                        continue;
-                    }
+        cmd.stdout(Stdio::from(file));
+        let mut cmd_fd = match cmd.spawn() {
+            Ok(cmd_fd) => cmd_fd,
+            Err(e) => bail!("command run failed: {e}"),
+        };
 
-                    // remove downed pod yaml
-                    if let Err(e) = match event.ops {
-                        PodOps::Apply => Ok(()),
-                        PodOps::Down => fs::remove_file(&event.yaml),
-                    } {
-                        error!("remove pod yaml: {} failed: {e}", event.yaml);
-                        continue;
-                    }
+        match cmd_fd.wait() {
+            Ok(code) if code.success() => {}
+            Ok(_) => bail!("command run failed"),
+            Err(e) => bail!("could not wait for command: {e}"),
+        };
 
-                    info!("{:?} successfully", event.ops);
-                }
-                Err(_) => break,
-            }
-        });
+        // remove downed pod yaml
+        if let Err(e) = match event.ops {
+            PodOps::Apply => Ok(()),
+            PodOps::Down => fs::remove_file(&event.yaml),
+        } {
+            bail!("remove pod yaml: {} failed: {e}", event.yaml);
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,127 @@
+//! Host cpu allocator, chooses free cpus for zones configured with a cpu
+//! count instead of a cpuset.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{bail, Ok};
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use super::{resource::pinned_cpus, topology::host_cpu, util::parse_cpuset};
+
+// sysfs/devices/system/cpu/
+const CPU_DIR: &str = "devices/system/cpu";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Placement {
+    /// all cpus on a single numa node
+    SameNuma,
+    /// fill cores with siblings before taking another core
+    SmtPack,
+    /// one thread per core before taking siblings
+    SmtSpread,
+}
+
+/// choose `count` free cpus of host under sysfs root, cpus pinned by
+/// zones other than `name` under workdir root and `reserved` are skipped,
+/// isolated cpus are taken first, housekeeping ones only if not enough
+pub fn allocate(
+    count: usize,
+    placement: Option<Placement>,
+    name: &str,
+    reserved: &BTreeSet<u32>,
+    root: &Path,
+    sysfs_root: &Path,
+) -> anyhow::Result<BTreeSet<u32>> {
+    let cpu_dir = sysfs_root.join(CPU_DIR);
+    let read_cpus = |file: &str| {
+        fs::read_to_string(cpu_dir.join(file))
+            .ok()
+            .and_then(|cpulist| parse_cpuset(&cpulist).ok())
+            .unwrap_or_default()
+    };
+    let online = read_cpus("online");
+    if online.is_empty() {
+        bail!("online cpus not found under {:?}", cpu_dir)
+    }
+    let isolated: BTreeSet<u32> = read_cpus("isolated")
+        .union(&read_cpus("nohz_full"))
+        .filter(|cpu| online.contains(cpu))
+        .copied()
+        .collect();
+
+    let pinned: BTreeSet<u32> = pinned_cpus(root, name)
+        .into_iter()
+        .flat_map(|(_, cpus)| cpus)
+        .collect();
+    // (package, node, core, cpu), a cpu of unknown topology is a core
+    let free = |candidates: &BTreeSet<u32>| -> Vec<(u32, u32, u32, u32)> {
+        candidates
+            .iter()
+            .filter(|cpu| !pinned.contains(cpu) && !reserved.contains(cpu))
+            .map(|cpu| {
+                let (package, node, core) = host_cpu(*cpu, sysfs_root).unwrap_or((0, 0, *cpu));
+                (package, node, core, *cpu)
+            })
+            .collect()
+    };
+
+    if !isolated.is_empty() {
+        match place(free(&isolated), count, placement, &isolated) {
+            Result::Ok(cpus) => return Ok(cpus),
+            Err(e) => debug!("allocate from isolated cpus failed: {e}"),
+        }
+    }
+    place(free(&online), count, placement, &isolated)
+}
+
+/// choose `count` of free cpus by placement, `isolated` ones first
+fn place(
+    mut free: Vec<(u32, u32, u32, u32)>,
+    count: usize,
+    placement: Option<Placement>,
+    isolated: &BTreeSet<u32>,
+) -> anyhow::Result<BTreeSet<u32>> {
+    if free.len() < count {
+        bail!("{count} cpus asked but {} free", free.len())
+    }
+
+    match placement {
+        None => free.sort_by_key(|(_, _, _, cpu)| (!isolated.contains(cpu), *cpu)),
+        Some(Placement::SameNuma) => {
+            let mut nodes: BTreeMap<u32, Vec<u32>> = BTreeMap::new();
+            for (_, node, _, cpu) in &free {
+                nodes.entry(*node).or_default().push(*cpu);
+            }
+            let Some(cpus) = nodes.into_values().find(|cpus| cpus.len() >= count) else {
+                bail!("no numa node with {count} free cpus")
+            };
+            return Ok(cpus.into_iter().take(count).collect());
+        }
+        Some(Placement::SmtPack) => free.sort(),
+        Some(Placement::SmtSpread) => {
+            // nth thread of every core before the next thread
+            free.sort();
+            let mut threads: BTreeMap<(u32, u32, u32), u32> = BTreeMap::new();
+            let mut ranked: Vec<(u32, (u32, u32, u32, u32))> = free
+                .iter()
+                .map(|host| {
+                    let thread = threads.entry((host.0, host.1, host.2)).or_default();
+                    *thread += 1;
+                    (*thread, *host)
+                })
+                .collect();
+            ranked.sort();
+            free = ranked.into_iter().map(|(_, host)| host).collect();
+        }
+    }
+    Ok(free
+        .into_iter()
+        .take(count)
+        .map(|(_, _, _, cpu)| cpu)
+        .collect())
+}
@@ -0,0 +1,402 @@
+//! Length-prefixed request/response channel between czctrl and czdaemon,
+//! served over vsock in guest, or over unix socket locally.
+use std::{
+    fmt::Display,
+    fs::{self, OpenOptions},
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Ok};
+use log::debug;
+use nix::{
+    errno::Errno,
+    sys::{
+        socket::{self, sockopt, AddressFamily, Backlog, SockFlag, SockType, VsockAddr},
+        time::TimeVal,
+    },
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::{lock::LockWait, ready::Ready, ControlZone, CHANNEL_FILE, CHANNEL_VSOCK_PORT};
+
+/// frames larger than this are rejected
+const MAX_FRAME: u32 = 16 << 20;
+/// cid 0-2 are reserved for hypervisor, local and host
+const MIN_GUEST_CID: u32 = 3;
+/// guest cids are registered host wide through vhost-vsock
+const VHOST_VSOCK_DEV: &str = "/dev/vhost-vsock";
+
+// VHOST_VSOCK_SET_GUEST_CID of linux/vhost.h
+nix::ioctl_write_ptr!(vhost_vsock_set_guest_cid, 0xAF, 0x60, u64);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Addr {
+    Vsock { cid: u32, port: u32 },
+    Unix(PathBuf),
+}
+
+impl FromStr for Addr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(path) = s.strip_prefix("unix:") {
+            return Ok(Addr::Unix(PathBuf::from(path)));
+        }
+
+        let Some((cid, port)) = s.strip_prefix("vsock:").and_then(|s| s.split_once(':')) else {
+            bail!("invalid channel address: {s}")
+        };
+        Ok(Addr::Vsock {
+            cid: cid.parse()?,
+            port: port.parse()?,
+        })
+    }
+}
+
+impl Display for Addr {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Addr::Vsock { cid, port } => write!(f, "vsock:{cid}:{port}"),
+            Addr::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    Ping,
+    Info,
+    PodApply {
+        name: String,
+        yaml: String,
+    },
+    PodDown {
+        name: String,
+    },
+    PodList,
+    Shutdown,
+    /// run command, output is streamed back before exit code
+    Exec {
+        cmd: Vec<String>,
+    },
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", content = "data", rename_all = "snake_case")]
+pub enum Response {
+    Ok,
+    Pong { version: String },
+    Info(Ready),
+    Pods(Vec<String>),
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+    Exit { code: i32 },
+    Error(String),
+}
+
+/// stream socket of either transport
+pub struct Stream(OwnedFd);
+
+impl Stream {
+    pub fn connect(addr: &Addr) -> anyhow::Result<Self> {
+        let fd = match addr {
+            Addr::Unix(path) => UnixStream::connect(path)?.into(),
+            Addr::Vsock { cid, port } => {
+                let fd = socket::socket(
+                    AddressFamily::Vsock,
+                    SockType::Stream,
+                    SockFlag::SOCK_CLOEXEC,
+                    None,
+                )?;
+                socket::connect(fd.as_raw_fd(), &VsockAddr::new(*cid, *port))?;
+                fd
+            }
+        };
+        Ok(Stream(fd))
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) -> anyhow::Result<()> {
+        let timeout = TimeVal::new(timeout.as_secs() as _, timeout.subsec_micros() as _);
+        socket::setsockopt(&self.0, sockopt::ReceiveTimeout, &timeout)?;
+        socket::setsockopt(&self.0, sockopt::SendTimeout, &timeout)?;
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        Result::Ok(nix::unistd::read(self.0.as_raw_fd(), buf)?)
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Result::Ok(nix::unistd::write(&self.0, buf)?)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Result::Ok(())
+    }
+}
+
+pub struct Listener(OwnedFd);
+
+impl Listener {
+    pub fn bind(addr: &Addr) -> anyhow::Result<Self> {
+        let fd = match addr {
+            Addr::Unix(path) => {
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+                UnixListener::bind(path)?.into()
+            }
+            Addr::Vsock { cid, port } => {
+                let fd = socket::socket(
+                    AddressFamily::Vsock,
+                    SockType::Stream,
+                    SockFlag::SOCK_CLOEXEC,
+                    None,
+                )?;
+                socket::bind(fd.as_raw_fd(), &VsockAddr::new(*cid, *port))?;
+                socket::listen(&fd, Backlog::MAXCONN)?;
+                fd
+            }
+        };
+        Ok(Listener(fd))
+    }
+
+    pub fn accept(&self) -> anyhow::Result<Stream> {
+        let fd = socket::accept(self.0.as_raw_fd())?;
+        // SAFETY: fd is just accepted and owned by nobody else
+        Ok(Stream(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+}
+
+pub fn write_frame<W: Write, T: Serialize>(w: &mut W, msg: &T) -> anyhow::Result<()> {
+    let body = serde_json::to_vec(msg)?;
+    if body.len() > MAX_FRAME as usize {
+        bail!("frame too large: {} bytes", body.len())
+    }
+    w.write_all(&(body.len() as u32).to_be_bytes())?;
+    w.write_all(&body)?;
+    w.flush()?;
+    Ok(())
+}
+
+pub fn read_frame<R: Read, T: DeserializeOwned>(r: &mut R) -> anyhow::Result<T> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_FRAME {
+        bail!("frame too large: {len} bytes")
+    }
+
+    let mut body = vec![0u8; len as usize];
+    r.read_exact(&mut body)?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+pub struct Client {
+    stream: Stream,
+}
+
+impl Client {
+    pub fn connect(addr: &Addr, timeout: Duration) -> anyhow::Result<Self> {
+        let stream = Stream::connect(addr)?;
+        stream.set_timeout(timeout)?;
+        debug!("channel connected: {addr}");
+        Ok(Client { stream })
+    }
+
+    /// zero timeout blocks forever
+    pub fn set_timeout(&self, timeout: Duration) -> anyhow::Result<()> {
+        self.stream.set_timeout(timeout)
+    }
+
+    /// send a request and wait for its response, error response is returned as error
+    pub fn call(&mut self, req: &Request) -> anyhow::Result<Response> {
+        write_frame(&mut self.stream, req)?;
+        match read_frame(&mut self.stream)? {
+            Response::Error(e) => bail!("czdaemon: {e}"),
+            resp => Ok(resp),
+        }
+    }
+
+    /// return version of czdaemon
+    pub fn ping(&mut self) -> anyhow::Result<String> {
+        match self.call(&Request::Ping)? {
+            Response::Pong { version } => Ok(version),
+            resp => bail!("unexpected response: {:?}", resp),
+        }
+    }
+
+    pub fn info(&mut self) -> anyhow::Result<Ready> {
+        match self.call(&Request::Info)? {
+            Response::Info(ready) => Ok(ready),
+            resp => bail!("unexpected response: {:?}", resp),
+        }
+    }
+
+    pub fn pod_apply(&mut self, name: &str, yaml: String) -> anyhow::Result<()> {
+        self.expect_ok(&Request::PodApply {
+            name: name.to_owned(),
+            yaml,
+        })
+    }
+
+    pub fn pod_down(&mut self, name: &str) -> anyhow::Result<()> {
+        self.expect_ok(&Request::PodDown {
+            name: name.to_owned(),
+        })
+    }
+
+    pub fn pod_list(&mut self) -> anyhow::Result<Vec<String>> {
+        match self.call(&Request::PodList)? {
+            Response::Pods(pods) => Ok(pods),
+            resp => bail!("unexpected response: {:?}", resp),
+        }
+    }
+
+    /// ask guest to power off
+    pub fn shutdown(&mut self) -> anyhow::Result<()> {
+        self.expect_ok(&Request::Shutdown)
+    }
+
+    /// run command in guest, stream its output to `stdout` and `stderr`,
+    /// return its exit code
+    pub fn exec<O: Write, E: Write>(
+        &mut self,
+        cmd: Vec<String>,
+        stdout: &mut O,
+        stderr: &mut E,
+    ) -> anyhow::Result<i32> {
+        write_frame(&mut self.stream, &Request::Exec { cmd })?;
+        loop {
+            match read_frame(&mut self.stream)? {
+                Response::Stdout(buf) => {
+                    stdout.write_all(&buf)?;
+                    stdout.flush()?;
+                }
+                Response::Stderr(buf) => {
+                    stderr.write_all(&buf)?;
+                    stderr.flush()?;
+                }
+                Response::Exit { code } => return Ok(code),
+                Response::Error(e) => bail!("czdaemon: {e}"),
+                resp => bail!("unexpected response: {:?}", resp),
+            }
+        }
+    }
+
+    fn expect_ok(&mut self, req: &Request) -> anyhow::Result<()> {
+        match self.call(req)? {
+            Response::Ok => Ok(()),
+            resp => bail!("unexpected response: {:?}", resp),
+        }
+    }
+}
+
+/// whether guest cid is taken on host, by a zone out of root, a libvirt
+/// guest or any other vm. The cid is registered to a probing vhost-vsock
+/// device and released once closed, unknown is taken as free
+pub fn cid_in_use(cid: u32) -> bool {
+    let Result::Ok(dev) = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(VHOST_VSOCK_DEV)
+    else {
+        return false;
+    };
+
+    let cid = cid as u64;
+    // SAFETY: dev is a vhost-vsock device, cid outlives the call
+    let res = unsafe { vhost_vsock_set_guest_cid(dev.as_raw_fd(), &cid) };
+    res == Err(Errno::EADDRINUSE)
+}
+
+/// pick the smallest guest cid neither used by control zones under root
+/// nor taken on host
+fn alloc_cid(root: &Path) -> anyhow::Result<u32> {
+    let used: Vec<u32> = fs::read_dir(root)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join(CHANNEL_FILE)).ok())
+        .filter_map(|addr| match Addr::from_str(&addr) {
+            Result::Ok(Addr::Vsock { cid, .. }) => Some(cid),
+            _ => None,
+        })
+        .collect();
+
+    (MIN_GUEST_CID..u32::MAX)
+        .find(|cid| !used.contains(cid) && !cid_in_use(*cid))
+        .ok_or(anyhow!("no free vsock cid"))
+}
+
+impl ControlZone {
+    #[inline]
+    pub fn channel_file(&self) -> PathBuf {
+        PathBuf::from(&self.meta.workdir).join(CHANNEL_FILE)
+    }
+
+    /// address of control channel, none for zones created without it
+    pub fn channel_addr(&self) -> Option<Addr> {
+        let addr = fs::read_to_string(self.channel_file()).ok()?;
+        Addr::from_str(&addr).ok()
+    }
+
+    /// vsock cid of guest, if control channel is over vsock
+    pub fn vsock_cid(&self) -> Option<u32> {
+        match self.channel_addr()? {
+            Addr::Vsock { cid, .. } => Some(cid),
+            Addr::Unix(_) => None,
+        }
+    }
+
+    /// assign a vsock address to control zone, called on create with
+    /// root locked as cids recorded under root are taken
+    pub fn alloc_channel(&self) -> anyhow::Result<Addr> {
+        let workdir = PathBuf::from(&self.meta.workdir);
+        let Some(root) = workdir.parent() else {
+            bail!("invalid workdir: {}", self.meta.workdir)
+        };
+
+        let addr = Addr::Vsock {
+            cid: alloc_cid(root)?,
+            port: CHANNEL_VSOCK_PORT,
+        };
+        fs::write(self.channel_file(), addr.to_string())?;
+        Ok(addr)
+    }
+
+    /// assign another vsock address if the recorded cid is taken on host,
+    /// none if channel is not over vsock or the cid is free
+    pub fn realloc_channel(&self, wait: LockWait) -> anyhow::Result<Option<Addr>> {
+        if !self.vsock_cid().is_some_and(cid_in_use) {
+            return Ok(None);
+        }
+
+        let _root_lock = self.lock_root(wait)?;
+        self.alloc_channel().map(Some)
+    }
+
+    /// connect to czdaemon, none if channel is unavailable,
+    /// share folder should be used instead
+    pub fn channel(&self, timeout: Duration) -> Option<Client> {
+        let addr = self.channel_addr()?;
+        match Client::connect(&addr, timeout) {
+            Result::Ok(client) => Some(client),
+            Err(e) => {
+                debug!("channel {addr} of {} unavailable: {e}", self.meta.name);
+                None
+            }
+        }
+    }
+}
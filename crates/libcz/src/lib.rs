@@ -1,13 +1,23 @@
 use anyhow::{anyhow, bail, Ok};
-use log::debug;
+use log::{debug, warn};
 use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf, str::FromStr};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, Instant},
+};
 use vruntime::DVRuntime;
 
 use self::{
+    allocator::allocate,
     czos::CZOS,
+    lock::{FileLock, LockWait},
     meta::{Meta, MetaBuilder},
-    resource::Resource,
+    observability::Observability,
+    resctrl::ResctrlGroup,
+    resource::{Resource, ResourceError},
+    util::format_cpuset,
 };
 
 use self::state::State;
@@ -15,10 +25,19 @@ use self::state::State;
 pub mod state;
 pub mod vruntime;
 
+pub mod allocator;
+pub mod channel;
 pub mod czos;
+pub mod lock;
+pub mod memory;
 pub mod meta;
+pub mod observability;
+pub mod ready;
+pub mod resctrl;
 pub mod resource;
-mod util;
+pub mod snapshot;
+pub mod topology;
+pub mod util;
 
 #[cfg(test)]
 mod test;
@@ -28,6 +47,27 @@ pub const WORKDIR_ROOT: &str = "/tmp/controlzones";
 pub const CZ_PRIO_KEY: &str = "cz_pri_key";
 pub const CZ_CONFIG: &str = "controlzone.yaml";
 pub const CZ_IMAGE: &str = "cz.img";
+// root/.lock and workdir/.lock
+pub const LOCK_FILE: &str = ".lock";
+// workdir/vruntime
+pub const VRUNTIME_FILE: &str = "vruntime";
+// workdir/channel
+pub const CHANNEL_FILE: &str = "channel";
+pub const CHANNEL_VSOCK_PORT: u32 = 10240;
+// seconds
+pub const CHANNEL_TIMEOUT: u64 = 120;
+// seconds, for read only queries answered at once
+pub const CHANNEL_QUERY_TIMEOUT: u64 = 3;
+
+// /sys/fs/resctrl/<name>/
+pub const RESCTRL_ROOT: &str = "/sys/fs/resctrl";
+// hugepages and numa nodes of host
+pub const SYSFS_ROOT: &str = "/sys";
+
+// workdir/snapshots/<name>/
+pub const SNAPSHOT_DIR: &str = "snapshots";
+// workdir/snapshots/<name>/snapshot.yaml
+pub const SNAPSHOT_META: &str = "snapshot.yaml";
 
 pub const POD_DIR: &str = "pod";
 // pod/apply
@@ -43,6 +83,8 @@ pub const INFO_DIR: &str = "info";
 pub const STATE_FILE: &str = "state";
 // sharefolder/info/ip
 pub const IP_FILE: &str = "ip";
+// sharefolder/info/ready
+pub const READY_FILE: &str = "ready";
 // sharefolder/info/static_net
 pub const STATIC_NET_FILE: &str = "static_net";
 
@@ -51,7 +93,7 @@ pub fn default_workdir(cz_name: &str) -> PathBuf {
     PathBuf::from(WORKDIR_ROOT).join(cz_name)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum UpdateMode {
     // Os changed
     Reboot,
@@ -77,6 +119,8 @@ pub struct ControlZone {
     pub meta: Meta,
     pub os: CZOS,
     pub resource: Resource,
+    #[serde(default)]
+    pub observability: Observability,
 
     #[serde(skip)]
     pub state: State,
@@ -98,6 +142,7 @@ impl ControlZone {
             State::from_str(&fs::read_to_string(state_file)?)?
         };
         cz.resource.gen_cpus();
+        cz.resource.gen_topology(Path::new(SYSFS_ROOT));
         Ok(cz)
     }
 
@@ -113,6 +158,7 @@ impl ControlZone {
 
         // init resource
         cz.resource.gen_cpus();
+        cz.resource.gen_topology(Path::new(SYSFS_ROOT));
 
         // init state
         let state_file = cz.state_file();
@@ -131,6 +177,13 @@ impl ControlZone {
             .join(INFO_DIR)
             .join(STATE_FILE)
     }
+
+    #[inline]
+    pub fn ready_file(&self) -> PathBuf {
+        PathBuf::from(&self.meta.share_folder)
+            .join(INFO_DIR)
+            .join(READY_FILE)
+    }
 }
 
 impl ControlZone {
@@ -183,6 +236,8 @@ impl ControlZone {
             )?;
         }
 
+        // assign control channel
+        self.alloc_channel()?;
         Ok(())
     }
 
@@ -215,6 +270,13 @@ impl ControlZone {
             mode = UpdateMode::Reboot
         }
 
+        // perf and balloon stats of vm are only set on boot
+        if new_cz.observability != self.observability {
+            debug!("update observability and reboot");
+            self.observability = new_cz.observability;
+            mode = UpdateMode::Reboot
+        }
+
         self.sync_to_file()?;
         Ok(mode)
     }
@@ -225,6 +287,19 @@ impl ControlZone {
         let state = State::Created;
         check_update!(self.state, state);
 
+        if let Some(backing) = &self.resource.memory_backing {
+            let cpus = &self.resource.cpus;
+            if let Err(e) = backing.validate(self.resource.memory, cpus, Path::new(SYSFS_ROOT)) {
+                bail!("memory backing of {} is invalid: {e}", self.meta.name)
+            }
+        }
+        if let Some(topology) = &self.resource.topology {
+            let (vcpus, memory) = (self.resource.cpus.len(), self.resource.memory);
+            if let Err(e) = topology.validate(vcpus, memory, self.resource.hugepage_mib()) {
+                bail!("topology of {} is invalid: {e}", self.meta.name)
+            }
+        }
+
         if let Err(e) = self.init_workdir() {
             self.delete_workdir()?;
             bail!(e);
@@ -242,29 +317,301 @@ impl ControlZone {
         Ok(())
     }
 
-    pub fn start(&mut self, wait: bool, vruntime: &DVRuntime) -> anyhow::Result<()> {
+    /// start control zone, wait for at most timeout until guest is ready,
+    /// root is locked within lock_wait if vsock cid has to be reassigned
+    pub fn start(
+        &mut self,
+        wait: Option<Duration>,
+        lock_wait: LockWait,
+        vruntime: &DVRuntime,
+    ) -> anyhow::Result<()> {
         let state = State::Running;
         check_update!(self.state, state);
 
-        vruntime.start(self)?;
-        if wait {
-            vruntime.wait(self)?;
+        // ready record of last boot is stale
+        let ready_file = self.ready_file();
+        if ready_file.exists() {
+            fs::remove_file(ready_file)?;
+        }
+
+        // vsock cids are host global, the one recorded on create
+        // may be taken by a vm out of root since then
+        if let Some(addr) = self.realloc_channel(lock_wait)? {
+            warn!("vsock cid of {} taken, reassigned {addr}", self.meta.name);
+        }
+        if let Err(e) = vruntime.start(self) {
+            // lost the cid to a vm started meanwhile
+            let Some(addr) = self.realloc_channel(lock_wait)? else {
+                bail!(e)
+            };
+            warn!("{} retry with {addr}: {e}", self.meta.name);
+            vruntime.start(self)?;
+        }
+        if let Err(e) = self.sync_resctrl(vruntime) {
+            vruntime.stop(self)?;
+            bail!("resctrl allocation of {} failed: {e}", self.meta.name)
+        }
+        fs::write(
+            PathBuf::from(&self.meta.workdir).join(VRUNTIME_FILE),
+            vruntime.name(),
+        )?;
+        if let Some(timeout) = wait {
+            let ready = vruntime.wait(self, timeout)?;
+            debug!("{} ready: {:?}", self.meta.name, ready);
         }
 
         Ok(())
     }
 
-    pub fn stop(&mut self, vruntime: &DVRuntime) -> anyhow::Result<()> {
-        let state = State::Stopped;
-        check_update!(self.state, state);
+    /// correct persisted state by the actual state reported from vruntime,
+    /// return the drifted state if corrected
+    pub fn reconcile(&mut self, vruntime: &DVRuntime) -> anyhow::Result<Option<State>> {
+        if matches!(self.state, State::Pending | State::Zombied) {
+            return Ok(None);
+        }
+
+        // control zone started by another vruntime is unknown to this one
+        let vruntime_file = PathBuf::from(&self.meta.workdir).join(VRUNTIME_FILE);
+        if vruntime_file.exists() && fs::read_to_string(&vruntime_file)? != vruntime.name() {
+            debug!(
+                "{} not started by {} vruntime, skip reconcile",
+                self.meta.name,
+                vruntime.name()
+            );
+            return Ok(None);
+        }
+
+        let actual = match (self.state, vruntime.status(self)?) {
+            // vm gone without a graceful shutdown
+            (State::Running | State::Paused | State::Error, State::Stopped) => State::Killed,
+            (_, State::Stopped) => return Ok(None),
+            (_, actual) => actual,
+        };
+
+        if actual == self.state {
+            return Ok(None);
+        }
+
+        // actual state wins, no need to check update
+        let drifted = self.state;
+        self.sync_state(actual)?;
+        Ok(Some(drifted))
+    }
+
+    /// shutdown control zone gracefully and wait for at most timeout,
+    /// then destroy it. zone destroyed without graceful shutdown is killed
+    pub fn stop(
+        &mut self,
+        timeout: Duration,
+        force: bool,
+        vruntime: &DVRuntime,
+    ) -> anyhow::Result<()> {
+        check_update!(self.state, State::Stopped);
+
+        // paused guest could not response to shutdown
+        let start = Instant::now();
+        let graceful = !force
+            && self.state != State::Paused
+            && match self.shutdown(timeout, vruntime) {
+                Result::Ok(_) => {
+                    vruntime.wait_stopped(self, timeout.saturating_sub(start.elapsed()))?
+                }
+                Err(e) => {
+                    warn!("shutdown control zone {} failed: {e}", self.meta.name);
+                    false
+                }
+            };
 
         vruntime.stop(self)?;
+        if let Err(e) = self.resctrl_group().remove() {
+            warn!("remove resctrl group of {} failed: {e}", self.meta.name);
+        }
+
+        let state = if graceful {
+            State::Stopped
+        } else {
+            warn!("control zone {} stopped by force", self.meta.name);
+            State::Killed
+        };
+
         if let Err(e) = self.sync_state(state) {
             bail!(e);
         }
         Ok(())
     }
 
+    /// ask czdaemon to power off guest, fallback to vruntime if
+    /// control channel is unavailable, channel waits no longer than timeout
+    fn shutdown(&self, timeout: Duration, vruntime: &DVRuntime) -> anyhow::Result<()> {
+        let channel_timeout = Duration::from_secs(CHANNEL_TIMEOUT).min(timeout);
+        if let Some(mut client) = self.channel(channel_timeout) {
+            match client.shutdown() {
+                Result::Ok(_) => return Ok(()),
+                Err(e) => warn!("shutdown through channel failed: {e}"),
+            }
+        }
+        vruntime.shutdown(self)
+    }
+
+    /// root dir of workdirs, zones under which are checked against
+    fn workdir_root(&self) -> PathBuf {
+        PathBuf::from(&self.meta.workdir)
+            .parent()
+            .map_or(PathBuf::from(WORKDIR_ROOT), Path::to_path_buf)
+    }
+
+    /// validate resource for this zone against host cpus and zones
+    /// under the same workdir root
+    pub fn validate_resource(&self, resource: &Resource) -> Result<(), ResourceError> {
+        resource.validate(&self.meta.name, &self.workdir_root(), Path::new(SYSFS_ROOT))
+    }
+
+    /// lock workdir for a mutating operation, config and state are
+    /// reloaded as they may be changed by the previous holder
+    pub fn lock(&mut self, wait: LockWait) -> anyhow::Result<FileLock> {
+        let lock = FileLock::acquire(Path::new(&self.meta.workdir), LOCK_FILE, wait)?;
+        *self = Self::new_from_full_config(&PathBuf::from(&self.meta.full_config))?;
+        Ok(lock)
+    }
+
+    /// lock workdir root, cpus allocated are recorded before released
+    pub fn lock_root(&self, wait: LockWait) -> anyhow::Result<FileLock> {
+        let root = self.workdir_root();
+        fs::create_dir_all(&root)?;
+        FileLock::acquire(&root, LOCK_FILE, wait)
+    }
+
+    /// holders of the workdir lock and of the root lock
+    pub fn lock_holders(&self) -> (Option<String>, Option<String>) {
+        (
+            FileLock::holder(Path::new(&self.meta.workdir), LOCK_FILE),
+            FileLock::holder(&self.workdir_root(), LOCK_FILE),
+        )
+    }
+
+    /// choose host cpus if only a cpu count configured, cpus of `current`
+    /// are kept while its count and placement are the same
+    pub fn allocate_cpus(&mut self, current: Option<&Resource>) -> anyhow::Result<()> {
+        let root = self.workdir_root();
+        let resource = &mut self.resource;
+        let Some(count) = resource.cpu_count else {
+            return Ok(());
+        };
+        if !resource.cpuset.is_empty() {
+            return Ok(());
+        }
+
+        let kept = current.filter(|current| {
+            current.cpu_count == Some(count)
+                && current.placement == resource.placement
+                && !current.cpuset.is_empty()
+        });
+        resource.cpuset = match kept {
+            Some(current) => current.cpuset.clone(),
+            None => {
+                let reserved = resource.emulator_cpus.iter().copied().collect();
+                let cpus = allocate(
+                    count as usize,
+                    resource.placement,
+                    &self.meta.name,
+                    &reserved,
+                    &root,
+                    Path::new(SYSFS_ROOT),
+                )?;
+                format_cpuset(&cpus)
+            }
+        };
+        debug!("cpus {} allocated to {}", resource.cpuset, self.meta.name);
+
+        resource.gen_cpus();
+        resource.gen_topology(Path::new(SYSFS_ROOT));
+        Ok(())
+    }
+
+    #[inline]
+    pub fn resctrl_group(&self) -> ResctrlGroup {
+        ResctrlGroup::new(Path::new(RESCTRL_ROOT), &self.meta.name)
+    }
+
+    /// apply resctrl allocation to threads of vm,
+    /// group is removed if allocation is not configured any more
+    fn sync_resctrl(&self, vruntime: &DVRuntime) -> anyhow::Result<()> {
+        let group = self.resctrl_group();
+        match &self.resource.resctrl {
+            Some(resctrl) => group.apply(resctrl, &vruntime.tasks(self)?),
+            None => group.remove(),
+        }
+    }
+
+    pub fn pause(&mut self, vruntime: &DVRuntime) -> anyhow::Result<()> {
+        let state = State::Paused;
+        check_update!(self.state, state);
+
+        vruntime.pause(self)?;
+        if let Err(e) = self.sync_state(state) {
+            bail!(e);
+        }
+        Ok(())
+    }
+
+    pub fn resume(&mut self, vruntime: &DVRuntime) -> anyhow::Result<()> {
+        let state = State::Running;
+        check_update!(self.state, state);
+        if self.state != State::Paused {
+            bail!("control zone {} is not paused", self.meta.name)
+        }
+
+        vruntime.resume(self)?;
+        if let Err(e) = self.sync_state(state) {
+            bail!(e);
+        }
+        Ok(())
+    }
+
+    /// update config of control zone, resource changes of a running
+    /// control zone are applied through vruntime without reboot if possible
+    pub fn update(&mut self, new_cz: Self, vruntime: &DVRuntime) -> anyhow::Result<UpdateMode> {
+        // pinning, balloon and resctrl of a paused vm are changed live as well
+        let live = matches!(self.state, State::Running | State::Paused)
+            && new_cz.os == self.os
+            && new_cz.resource != self.resource;
+
+        // apply before config synced, so a failed hot update keeps the old config
+        let live_mode = if !live {
+            UpdateMode::Stale
+        } else if new_cz.resource.static_net != self.resource.static_net {
+            warn!(
+                "static net of {} can not be changed live, fallback to reboot",
+                self.meta.name
+            );
+            UpdateMode::Reboot
+        } else if new_cz.resource.memory_backing != self.resource.memory_backing {
+            warn!(
+                "memory backing of {} can not be changed live, fallback to reboot",
+                self.meta.name
+            );
+            UpdateMode::Reboot
+        } else if new_cz.resource.topology != self.resource.topology {
+            warn!(
+                "topology of {} can not be changed live, fallback to reboot",
+                self.meta.name
+            );
+            UpdateMode::Reboot
+        } else {
+            vruntime.update(self, &new_cz.resource)?
+        };
+
+        let mode = self.update_config(new_cz)?;
+        if live_mode == UpdateMode::Reboot {
+            return Ok(UpdateMode::Reboot);
+        }
+
+        if live_mode == UpdateMode::Hot {
+            self.sync_resctrl(vruntime)?;
+        }
+        Ok(mode)
+    }
+
     pub fn remove(&mut self) -> anyhow::Result<()> {
         let state = State::Zombied;
         check_update!(self.state, state);
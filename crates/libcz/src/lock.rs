@@ -0,0 +1,104 @@
+//! Advisory locks between czctrl processes, taken by flock on lock files.
+//! A workdir lock is taken before the lock of root, never after.
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::Path,
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Ok};
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg},
+};
+
+const PROC_FS: &str = "/proc";
+/// milliseconds
+const LOCK_POLL_INTERVAL: u64 = 100;
+
+/// how long to wait for a lock held by another process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockWait {
+    Forever,
+    Timeout(Duration),
+    NoWait,
+}
+
+/// exclusive lock, released on drop
+pub struct FileLock {
+    flock: Flock<File>,
+}
+
+impl FileLock {
+    /// lock file under dir, the holder is recorded in file for inspection
+    pub fn acquire(dir: &Path, file: &str, wait: LockWait) -> anyhow::Result<Self> {
+        let path = dir.join(file);
+        let deadline = match wait {
+            LockWait::Forever => None,
+            LockWait::Timeout(timeout) => Some(Instant::now() + timeout),
+            LockWait::NoWait => Some(Instant::now()),
+        };
+
+        let mut file = match OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(&path)
+        {
+            Result::Ok(file) => file,
+            Err(e) => bail!("open lock {:?} failed: {e}", path),
+        };
+        let mut flock = loop {
+            let arg = match deadline {
+                Some(_) => FlockArg::LockExclusiveNonblock,
+                None => FlockArg::LockExclusive,
+            };
+            match Flock::lock(file, arg) {
+                Result::Ok(flock) => break flock,
+                Err((locked, Errno::EWOULDBLOCK)) => {
+                    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                        let holder =
+                            read_holder(&locked).unwrap_or(String::from("another process"));
+                        bail!("{:?} is locked by {holder}", path)
+                    }
+                    file = locked;
+                    sleep(Duration::from_millis(LOCK_POLL_INTERVAL));
+                }
+                Err((_, errno)) => bail!("lock {:?} failed: {errno}", path),
+            }
+        };
+
+        let args: Vec<String> = std::env::args().collect();
+        flock.set_len(0)?;
+        writeln!(flock, "{} {}", std::process::id(), args.join(" "))?;
+        Ok(FileLock { flock })
+    }
+
+    /// pid and command line of the process holding lock, none if not held,
+    /// read without locking so acquirers are never disturbed
+    pub fn holder(dir: &Path, file: &str) -> Option<String> {
+        let file = File::open(dir.join(file)).ok()?;
+        read_holder(&file)
+    }
+}
+
+impl Drop for FileLock {
+    /// holder is cleared before the lock released
+    fn drop(&mut self) {
+        let _ = self.flock.set_len(0);
+    }
+}
+
+/// holder recorded in lock file, none if cleared or its process exited
+fn read_holder(mut file: &File) -> Option<String> {
+    let mut holder = String::new();
+    file.read_to_string(&mut holder).ok()?;
+    let pid = holder.split_whitespace().next()?;
+    if !Path::new(PROC_FS).join(pid).exists() {
+        return None;
+    }
+    Some(holder.trim().to_owned())
+}
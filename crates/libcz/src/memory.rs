@@ -0,0 +1,104 @@
+//! Memory backing of control zone, guest memory from preallocated
+//! hugepages, locked in host memory and bound to the numa node of its cpus.
+use std::{fs, path::Path};
+
+use anyhow::{bail, Ok};
+use serde::{Deserialize, Serialize};
+
+use super::util::parse_cpuset;
+
+// sysfs/kernel/mm/hugepages/hugepages-<size>kB/
+const HUGEPAGES_DIR: &str = "kernel/mm/hugepages";
+// sysfs/devices/system/node/node<id>/
+const NODE_DIR: &str = "devices/system/node";
+const FREE_HUGEPAGES_FILE: &str = "free_hugepages";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HugePageSize {
+    #[serde(rename = "2M")]
+    Size2M,
+    #[serde(rename = "1G")]
+    Size1G,
+}
+
+impl HugePageSize {
+    pub fn kib(&self) -> u64 {
+        match self {
+            HugePageSize::Size2M => 2 << 10,
+            HugePageSize::Size1G => 1 << 20,
+        }
+    }
+
+    /// hugetlbfs mounted by systemd for the size
+    pub fn mount_path(&self) -> &'static str {
+        match self {
+            HugePageSize::Size2M => "/dev/hugepages",
+            HugePageSize::Size1G => "/dev/hugepages1G",
+        }
+    }
+
+    fn sysfs_dir(&self) -> String {
+        format!("hugepages-{}kB", self.kib())
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryBacking {
+    /// size of hugepages backing guest memory, normal pages if none
+    pub hugepages: Option<HugePageSize>,
+    /// lock guest memory in host memory, never swapped
+    #[serde(default)]
+    pub locked: bool,
+    /// host numa node guest memory is bound to, should hold pinned cpus
+    pub numa_node: Option<u32>,
+}
+
+impl MemoryBacking {
+    /// check backing against host under sysfs root, `memory` in MiB
+    pub fn validate(&self, memory: u32, cpus: &[u32], sysfs_root: &Path) -> anyhow::Result<()> {
+        let node_dir = self
+            .numa_node
+            .map(|node| sysfs_root.join(NODE_DIR).join(format!("node{node}")));
+
+        if let (Some(node), Some(node_dir)) = (self.numa_node, &node_dir) {
+            let Result::Ok(cpulist) = fs::read_to_string(node_dir.join("cpulist")) else {
+                bail!("numa node {node} not exists")
+            };
+            let node_cpus = parse_cpuset(&cpulist)?;
+            let remote: Vec<&u32> = cpus.iter().filter(|cpu| !node_cpus.contains(cpu)).collect();
+            if !remote.is_empty() {
+                bail!("cpus {remote:?} are not on numa node {node}")
+            }
+        }
+
+        let Some(size) = self.hugepages else {
+            return Ok(());
+        };
+        let memory_kib = memory as u64 * 1024;
+        if !memory_kib.is_multiple_of(size.kib()) {
+            bail!(
+                "memory {memory}MiB is not a multiple of {}KiB hugepages",
+                size.kib()
+            )
+        }
+
+        // pages must come from the bound node if any
+        let pages_dir = match &node_dir {
+            Some(node_dir) => node_dir.join("hugepages"),
+            None => sysfs_root.join(HUGEPAGES_DIR),
+        };
+        let free_file = pages_dir.join(size.sysfs_dir()).join(FREE_HUGEPAGES_FILE);
+        let free = match fs::read_to_string(&free_file) {
+            Result::Ok(free) => free.trim().parse::<u64>()?,
+            Err(e) => bail!("read free hugepages from {:?} failed: {e}", free_file),
+        };
+        let needed = memory_kib / size.kib();
+        if free < needed {
+            bail!(
+                "{needed} hugepages of {}KiB needed but {free} free",
+                size.kib()
+            )
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+//! Observability profile of control zone, what the hypervisor collects
+//! and which host monitors the zone is registered with on start.
+use serde::{Deserialize, Serialize};
+
+/// perf events enabled if not configured
+const DEFAULT_PERF_EVENTS: [&str; 7] = [
+    "cpu_cycles",
+    "instructions",
+    "cache_references",
+    "cache_misses",
+    "branch_instructions",
+    "branch_misses",
+    "context_switches",
+];
+/// seconds
+const DEFAULT_STATS_PERIOD: u32 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Monitor {
+    Resctrl,
+    Ebpf,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Observability {
+    /// perf events counted for vm, e.g. `cpu_cycles`, empty disables perf
+    #[serde(default = "default_perf_events")]
+    pub perf_events: Vec<String>,
+    /// seconds between balloon stats refreshes, 0 disables balloon stats
+    #[serde(default = "default_stats_period")]
+    pub stats_period: u32,
+    /// monitors registered on start and cleaned on stop
+    #[serde(default)]
+    pub monitors: Vec<Monitor>,
+}
+
+fn default_perf_events() -> Vec<String> {
+    DEFAULT_PERF_EVENTS.map(String::from).to_vec()
+}
+
+fn default_stats_period() -> u32 {
+    DEFAULT_STATS_PERIOD
+}
+
+impl Default for Observability {
+    fn default() -> Self {
+        Observability {
+            perf_events: default_perf_events(),
+            stats_period: default_stats_period(),
+            monitors: vec![],
+        }
+    }
+}
+
+impl Observability {
+    /// nothing collected and no monitor registered
+    pub fn disabled() -> Self {
+        Observability {
+            perf_events: vec![],
+            stats_period: 0,
+            monitors: vec![],
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use std::{
+    fs,
+    net::IpAddr,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Ok};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+/// record written by czdaemon once guest is ready, or failed to be
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ready {
+    /// boot id of guest kernel
+    pub boot_id: String,
+    /// version of czdaemon
+    pub version: String,
+    pub ip: Option<String>,
+    /// seconds since unix epoch
+    pub timestamp: u64,
+    /// reason why guest failed to get ready
+    pub error: Option<String>,
+}
+
+impl Ready {
+    pub fn new(boot_id: String, version: String, ip: Option<String>) -> anyhow::Result<Self> {
+        Ok(Ready {
+            boot_id,
+            version,
+            ip,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            error: None,
+        })
+    }
+
+    pub fn failed(boot_id: String, version: String, reason: impl ToString) -> anyhow::Result<Self> {
+        let mut ready = Self::new(boot_id, version, None)?;
+        ready.error = Some(reason.to_string());
+        Ok(ready)
+    }
+
+    /// write record atomically, so that a partial record is never seen
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_yaml::to_string(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// read record, return none if not written yet
+    pub fn read<P: AsRef<Path>>(path: P) -> anyhow::Result<Option<Self>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(None);
+        }
+        Ok(Some(serde_yaml::from_str(&fs::read_to_string(path)?)?))
+    }
+
+    /// check record is complete and guest got ready
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if let Some(reason) = &self.error {
+            bail!("czdaemon failed: {reason}")
+        }
+
+        if self.boot_id.trim().is_empty() {
+            bail!("invalid ready record: empty boot id")
+        }
+
+        if let Some(ip) = &self.ip {
+            if ip.parse::<IpAddr>().is_err() {
+                bail!("invalid ready record: bad ip {ip}")
+            }
+        }
+
+        if self.version != env!("CARGO_PKG_VERSION") {
+            warn!(
+                "czdaemon version {} differs from {}",
+                self.version,
+                env!("CARGO_PKG_VERSION")
+            );
+        }
+        Ok(())
+    }
+}
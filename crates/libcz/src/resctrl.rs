@@ -0,0 +1,151 @@
+//! Resctrl allocation of control zone, cache ways and memory bandwidth
+//! are given to a control group named after the zone.
+use std::{
+    collections::BTreeMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Ok};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+const SCHEMATA_FILE: &str = "schemata";
+const TASKS_FILE: &str = "tasks";
+/// entries of resctrl root which are not control groups
+const RESERVED_GROUPS: [&str; 3] = ["info", "mon_groups", "mon_data"];
+
+/// whether zone name collides with an entry of resctrl root
+pub fn reserved(name: &str) -> bool {
+    RESERVED_GROUPS.contains(&name)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Resctrl {
+    /// l3 cache way mask in hex per cache id, e.g. `0: "ff0"`
+    #[serde(default)]
+    pub l3: BTreeMap<u32, String>,
+    /// memory bandwidth percentage per socket
+    #[serde(default)]
+    pub mba: BTreeMap<u32, u32>,
+}
+
+impl Resctrl {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.l3.is_empty() && self.mba.is_empty() {
+            bail!("empty resctrl allocation")
+        }
+
+        for (id, mask) in &self.l3 {
+            let Result::Ok(bits) = u64::from_str_radix(mask.trim_start_matches("0x"), 16) else {
+                bail!("invalid l3 mask of cache {id}: {mask}")
+            };
+            // cat requires a non-empty contiguous mask
+            let shifted = bits >> bits.trailing_zeros().min(63);
+            if bits == 0 || shifted.trailing_ones() != shifted.count_ones() {
+                bail!("l3 mask of cache {id} must be contiguous: {mask}")
+            }
+        }
+
+        for (id, percent) in &self.mba {
+            if *percent == 0 || *percent > 100 {
+                bail!("invalid mba of socket {id}: {percent}%")
+            }
+        }
+        Ok(())
+    }
+
+    /// content of schemata file, one line per resource
+    pub fn schemata(&self) -> String {
+        let mut schemata = String::new();
+        if !self.l3.is_empty() {
+            let domains: Vec<String> = self
+                .l3
+                .iter()
+                .map(|(id, mask)| format!("{id}={}", mask.trim_start_matches("0x")))
+                .collect();
+            schemata.push_str(&format!("L3:{}\n", domains.join(";")));
+        }
+
+        if !self.mba.is_empty() {
+            let domains: Vec<String> = self
+                .mba
+                .iter()
+                .map(|(id, percent)| format!("{id}={percent}"))
+                .collect();
+            schemata.push_str(&format!("MB:{}\n", domains.join(";")));
+        }
+        schemata
+    }
+}
+
+/// control group under resctrl root
+pub struct ResctrlGroup {
+    root: PathBuf,
+    name: String,
+    dir: PathBuf,
+}
+
+impl ResctrlGroup {
+    pub fn new(root: &Path, name: &str) -> Self {
+        ResctrlGroup {
+            root: root.to_owned(),
+            name: name.to_owned(),
+            dir: root.join(name),
+        }
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn exists(&self) -> bool {
+        !reserved(&self.name) && self.dir.is_dir()
+    }
+
+    /// create group if not exists, then write schemata and move tasks into it,
+    /// could be applied again to update a live group
+    pub fn apply(&self, resctrl: &Resctrl, tasks: &[u32]) -> anyhow::Result<()> {
+        resctrl.validate()?;
+        if reserved(&self.name) {
+            bail!("{} is reserved by resctrl", self.name)
+        }
+        if !self.root.is_dir() {
+            bail!("resctrl not enabled")
+        }
+
+        if !self.exists() {
+            fs::create_dir(&self.dir)?;
+            debug!("resctrl group {:?} created", self.dir);
+        }
+
+        if let Err(e) = fs::write(self.dir.join(SCHEMATA_FILE), resctrl.schemata()) {
+            bail!("write schemata of {:?} failed: {e}", self.dir)
+        }
+
+        // kernel takes one task per write
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(TASKS_FILE))?;
+        for task in tasks {
+            // thread may exit meanwhile
+            if let Err(e) = file.write_all(format!("{task}\n").as_bytes()) {
+                warn!("move task {task} to {:?} failed: {e}", self.dir);
+            }
+        }
+        debug!("{} tasks moved to {:?}", tasks.len(), self.dir);
+        Ok(())
+    }
+
+    /// remove group, its tasks fall back to default group
+    pub fn remove(&self) -> anyhow::Result<()> {
+        if self.exists() {
+            fs::remove_dir(&self.dir)?;
+            debug!("resctrl group {:?} removed", self.dir);
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,56 @@
+use std::{collections::BTreeSet, fs, path::Path};
+
 use anyhow::{bail, Ok};
+use log::warn;
 use serde::{Deserialize, Serialize};
 
-use super::util::parse_cpuset;
+use super::{
+    allocator::Placement,
+    memory::MemoryBacking,
+    resctrl::{self, Resctrl},
+    topology::Topology,
+    util::{parse_cpuset, CpusetError},
+    ControlZone, CZ_CONFIG,
+};
+
+// sysfs/devices/system/cpu/
+const CPU_DIR: &str = "devices/system/cpu";
+const ONLINE_FILE: &str = "online";
+/// cpus of `isolcpus=`
+const ISOLATED_FILE: &str = "isolated";
+const NOHZ_FULL_FILE: &str = "nohz_full";
+
+/// Errors of resource validation.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum ResourceError {
+    /// Cpuset not parsable.
+    #[error("invalid {field} {cpuset:?}: {source}")]
+    Cpuset {
+        field: &'static str,
+        cpuset: String,
+        source: CpusetError,
+    },
+
+    /// No cpu for vcpus.
+    #[error("no cpus in cpuset")]
+    NoCpus,
+
+    /// Cpuset not of the cpu count configured.
+    #[error("{cpus} cpus in cpuset but {count} configured")]
+    Count { cpus: usize, count: u32 },
+
+    /// Cpus not online on host.
+    #[error("cpus {0:?} are offline or not exist")]
+    Offline(Vec<u32>),
+
+    /// Cpus pinned by vcpus or emulator of another zone.
+    #[error("cpus {cpus:?} are pinned by control zone {zone}")]
+    Pinned { cpus: Vec<u32>, zone: String },
+
+    /// Zone named after an entry of resctrl root, not a group.
+    #[error("name {0} is reserved by resctrl")]
+    Reserved(String),
+}
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct StaticNet {
@@ -29,24 +78,187 @@ iface eth0 inet static
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct Resource {
+    /// host cpus of vcpus, chosen by allocator on create if empty
+    #[serde(default)]
     pub cpuset: String,
+    /// count of cpus to allocate if no cpuset
+    #[serde(rename = "cpus", default)]
+    pub cpu_count: Option<u32>,
+    /// placement of allocated cpus on host
+    #[serde(default)]
+    pub placement: Option<Placement>,
+    /// MiB
     pub memory: u32,
     pub static_net: Option<StaticNet>,
+    /// host cpus for emulator threads of vm, floating if none
+    pub emulator_cpuset: Option<String>,
+    /// cache and memory bandwidth allocation
+    pub resctrl: Option<Resctrl>,
+    /// hugepages, locking and numa binding of guest memory
+    pub memory_backing: Option<MemoryBacking>,
+    /// guest cpu topology, derived from host cpus if none
+    pub topology: Option<Topology>,
 
     #[serde(skip)]
     pub cpus: Vec<u32>,
+    #[serde(skip)]
+    pub emulator_cpus: Vec<u32>,
+    /// topology given to guest, flat if none
+    #[serde(skip)]
+    pub guest_topology: Option<Topology>,
 }
 
 impl Resource {
     pub fn gen_cpus(&mut self) {
-        self.cpus = parse_cpuset(&self.cpuset).into_iter().collect();
+        // malformed cpusets are reported by validation
+        self.cpus = parse_cpuset(&self.cpuset)
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        self.emulator_cpus = self
+            .emulator_cpuset
+            .as_deref()
+            .map(|cpuset| {
+                parse_cpuset(cpuset)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .collect()
+            })
+            .unwrap_or_default();
+    }
+
+    /// size of hugepages backing guest memory, 1 for normal pages
+    pub fn hugepage_mib(&self) -> u32 {
+        self.memory_backing
+            .as_ref()
+            .and_then(|backing| backing.hugepages)
+            .map_or(1, |pages| (pages.kib() >> 10) as u32)
+    }
+
+    /// topology configured or derived from host under sysfs root, a
+    /// derived one reorders cpus so vcpus follow host cores
+    pub fn gen_topology(&mut self, sysfs_root: &Path) {
+        if let Some(topology) = &self.topology {
+            self.guest_topology = Some(topology.clone());
+            return;
+        }
+
+        let align = self.hugepage_mib();
+        if let Some((topology, cpus)) =
+            Topology::from_host(&self.cpus, self.memory, align, sysfs_root)
+        {
+            self.guest_topology = Some(topology);
+            self.cpus = cpus;
+        }
+    }
+
+    /// check cpus against host under sysfs root and zones other than
+    /// `name` under workdir root, cpus not isolated are only warned
+    pub fn validate(
+        &self,
+        name: &str,
+        root: &Path,
+        sysfs_root: &Path,
+    ) -> Result<(), ResourceError> {
+        let parse = |field: &'static str, cpuset: &str| {
+            parse_cpuset(cpuset).map_err(|source| ResourceError::Cpuset {
+                field,
+                cpuset: cpuset.to_owned(),
+                source,
+            })
+        };
+        // resctrl group of zone is named after it
+        if resctrl::reserved(name) {
+            return Err(ResourceError::Reserved(name.to_owned()));
+        }
+
+        let cpus = parse("cpuset", &self.cpuset)?;
+        if cpus.is_empty() {
+            return Err(ResourceError::NoCpus);
+        }
+        if let Some(count) = self.cpu_count.filter(|count| *count as usize != cpus.len()) {
+            return Err(ResourceError::Count {
+                cpus: cpus.len(),
+                count,
+            });
+        }
+        let emulator_cpus = match &self.emulator_cpuset {
+            Some(cpuset) => parse("emulator_cpuset", cpuset)?,
+            None => BTreeSet::new(),
+        };
+
+        let cpu_dir = sysfs_root.join(CPU_DIR);
+        let read_cpus = |file: &str| {
+            fs::read_to_string(cpu_dir.join(file))
+                .ok()
+                .and_then(|cpulist| parse_cpuset(&cpulist).ok())
+        };
+        match read_cpus(ONLINE_FILE) {
+            Some(online) => {
+                let offline: Vec<u32> = cpus
+                    .union(&emulator_cpus)
+                    .filter(|cpu| !online.contains(cpu))
+                    .copied()
+                    .collect();
+                if !offline.is_empty() {
+                    return Err(ResourceError::Offline(offline));
+                }
+            }
+            None => warn!("online cpus not found under {:?}", cpu_dir),
+        }
+
+        for (zone, pinned) in pinned_cpus(root, name) {
+            let cpus: Vec<u32> = cpus.intersection(&pinned).copied().collect();
+            if !cpus.is_empty() {
+                return Err(ResourceError::Pinned { cpus, zone });
+            }
+        }
+
+        let isolated: BTreeSet<u32> = [ISOLATED_FILE, NOHZ_FULL_FILE]
+            .iter()
+            .filter_map(|file| read_cpus(file))
+            .flatten()
+            .collect();
+        let noisy: Vec<&u32> = cpus.difference(&isolated).collect();
+        if !noisy.is_empty() {
+            warn!("cpus {noisy:?} of {name} are not in isolcpus or nohz_full");
+        }
+        Result::Ok(())
     }
 
     pub fn update(&mut self, new: Self) -> anyhow::Result<()> {
         self.cpuset = new.cpuset;
+        self.cpu_count = new.cpu_count;
+        self.placement = new.placement;
         self.cpus = new.cpus;
         self.memory = new.memory;
         self.static_net = new.static_net;
+        self.emulator_cpuset = new.emulator_cpuset;
+        self.emulator_cpus = new.emulator_cpus;
+        self.resctrl = new.resctrl;
+        self.memory_backing = new.memory_backing;
+        self.topology = new.topology;
+        self.guest_topology = new.guest_topology;
         Ok(())
     }
 }
+
+/// cpus of vcpus and emulator of zones other than `name` under workdir root
+pub(crate) fn pinned_cpus(root: &Path, name: &str) -> Vec<(String, BTreeSet<u32>)> {
+    let Result::Ok(entries) = fs::read_dir(root) else {
+        return vec![];
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| fs::read_to_string(entry.path().join(CZ_CONFIG)).ok())
+        .filter_map(|config| serde_yaml::from_str::<ControlZone>(&config).ok())
+        .filter(|cz| cz.meta.name != name)
+        .filter_map(|cz| {
+            let mut cpus = parse_cpuset(&cz.resource.cpuset).ok()?;
+            if let Some(emulator_cpuset) = &cz.resource.emulator_cpuset {
+                cpus.extend(parse_cpuset(emulator_cpuset).ok()?);
+            }
+            Some((cz.meta.name, cpus))
+        })
+        .collect()
+}
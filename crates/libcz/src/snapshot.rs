@@ -0,0 +1,174 @@
+use std::{
+    fs,
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{anyhow, bail, Ok};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    state::State, vruntime::DVRuntime, ControlZone, CZ_CONFIG, SNAPSHOT_DIR, SNAPSHOT_META,
+};
+
+const QEMU_IMG: &str = "qemu-img";
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub name: String,
+    /// seconds since unix epoch
+    pub created: u64,
+    /// memory state included, only for snapshot of a running control zone
+    pub memory: bool,
+}
+
+/// operate qcow2 internal snapshot of an image not in use
+fn qemu_img_snapshot(op: &str, name: &str, image: &str) -> anyhow::Result<()> {
+    let mut cmd = Command::new(QEMU_IMG);
+    cmd.args(["snapshot", op, name, image]);
+    debug!("{:?}", cmd);
+
+    let output = match cmd.output() {
+        Result::Ok(output) => output,
+        Err(e) => bail!("command spawn failed: {e}"),
+    };
+
+    if !output.status.success() {
+        bail!(
+            "command exec failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+    }
+    Ok(())
+}
+
+#[inline]
+fn valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl ControlZone {
+    #[inline]
+    pub fn snapshot_dir(&self) -> PathBuf {
+        PathBuf::from(&self.meta.workdir).join(SNAPSHOT_DIR)
+    }
+
+    /// list snapshots recorded in workdir, oldest first
+    pub fn list_snapshots(&self) -> anyhow::Result<Vec<Snapshot>> {
+        let snapshot_dir = self.snapshot_dir();
+        if !snapshot_dir.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut snapshots: Vec<Snapshot> = fs::read_dir(snapshot_dir)?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| fs::read_to_string(entry.path().join(SNAPSHOT_META)).ok())
+            .filter_map(|meta| serde_yaml::from_str(&meta).ok())
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.created);
+        Ok(snapshots)
+    }
+
+    fn find_snapshot(&self, name: &str) -> anyhow::Result<Snapshot> {
+        let meta = self.snapshot_dir().join(name).join(SNAPSHOT_META);
+        if !valid_name(name) || !meta.exists() {
+            bail!("snapshot {name} not found")
+        }
+        Ok(serde_yaml::from_str(&fs::read_to_string(meta)?)?)
+    }
+
+    /// snapshot rootfs and full config, memory state is included
+    /// if control zone is running
+    pub fn create_snapshot(
+        &self,
+        name: Option<String>,
+        vruntime: &DVRuntime,
+    ) -> anyhow::Result<Snapshot> {
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let name = name.unwrap_or_else(|| format!("snap-{created}"));
+        if !valid_name(&name) {
+            bail!("invalid snapshot name: {name}")
+        }
+
+        let snapshot_dir = self.snapshot_dir().join(&name);
+        if snapshot_dir.exists() {
+            bail!("snapshot {name} already exists")
+        }
+
+        // memory state is not saved with some devices, e.g. a mounted 9p share
+        let memory = match self.state {
+            State::Running => match vruntime.snapshot(self, &name) {
+                Result::Ok(_) => true,
+                Err(e) => {
+                    warn!("snapshot memory of {} failed: {e}", self.meta.name);
+                    if let Err(e) = vruntime.snapshot_disk(self, &name) {
+                        bail!("snapshot rootfs of running {} failed: {e}", self.meta.name)
+                    }
+                    false
+                }
+            },
+            State::Paused => bail!("control zone {} is paused", self.meta.name),
+            _ => {
+                qemu_img_snapshot("-c", &name, &self.os.rootfs)?;
+                false
+            }
+        };
+
+        let snapshot = Snapshot {
+            name,
+            created,
+            memory,
+        };
+        fs::create_dir_all(&snapshot_dir)?;
+        fs::copy(&self.meta.full_config, snapshot_dir.join(CZ_CONFIG))?;
+        fs::write(
+            snapshot_dir.join(SNAPSHOT_META),
+            serde_yaml::to_string(&snapshot)?,
+        )?;
+        Ok(snapshot)
+    }
+
+    /// roll back rootfs and full config to snapshot, a running control zone
+    /// could only be rolled back to a snapshot with memory state
+    pub fn restore_snapshot(&mut self, name: &str, vruntime: &DVRuntime) -> anyhow::Result<()> {
+        let snapshot = self.find_snapshot(name)?;
+        match self.state {
+            State::Running => {
+                if !snapshot.memory {
+                    bail!(
+                        "snapshot {name} has no memory state, stop control zone {} first",
+                        self.meta.name
+                    )
+                }
+                vruntime.revert_snapshot(self, name)?;
+            }
+            State::Paused => bail!("control zone {} is paused", self.meta.name),
+            _ => qemu_img_snapshot("-a", name, &self.os.rootfs)?,
+        }
+
+        let full_config = PathBuf::from(&self.meta.full_config);
+        fs::copy(self.snapshot_dir().join(name).join(CZ_CONFIG), &full_config)?;
+
+        let restored = ControlZone::new_from_full_config(&full_config)
+            .map_err(|e| anyhow!("error parsing restored config: {e}"))?;
+        self.os = restored.os;
+        self.resource = restored.resource;
+        Ok(())
+    }
+
+    pub fn delete_snapshot(&self, name: &str, vruntime: &DVRuntime) -> anyhow::Result<()> {
+        self.find_snapshot(name)?;
+        match self.state {
+            State::Running | State::Paused => vruntime.delete_snapshot(self, name)?,
+            _ => qemu_img_snapshot("-d", name, &self.os.rootfs)?,
+        }
+
+        fs::remove_dir_all(self.snapshot_dir().join(name))?;
+        Ok(())
+    }
+}
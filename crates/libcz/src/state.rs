@@ -6,7 +6,10 @@ pub enum State {
     Pending,
     Created,
     Running,
+    Paused,
     Stopped,
+    // stopped by force, without a graceful shutdown
+    Killed,
     Zombied,
     Error,
 }
@@ -27,12 +30,22 @@ impl State {
         }
 
         match (self, new_state) {
+            // killed is also stopped
+            (State::Killed, State::Stopped) => return Ok(true),
+
             (State::Pending, State::Created) => {}
             (State::Created, State::Running) => {}
             (State::Running, State::Stopped) => {}
+            (State::Running, State::Killed) => {}
+            (State::Running, State::Paused) => {}
+            (State::Paused, State::Running) => {}
+            (State::Paused, State::Stopped) => {}
+            (State::Paused, State::Killed) => {}
             (State::Running, State::Error) => {}
             (State::Stopped, State::Running) => {}
             (State::Stopped, State::Zombied) => {}
+            (State::Killed, State::Running) => {}
+            (State::Killed, State::Zombied) => {}
             (State::Created, State::Zombied) => {}
 
             _ => bail!("can not change state from {:#?} to {:#?}", self, new_state),
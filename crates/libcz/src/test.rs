@@ -1,24 +1,95 @@
-use std::collections::BTreeSet;
+use std::{collections::BTreeSet, str::FromStr, time::Duration};
 
-use crate::{resource::StaticNet, util::parse_cpuset};
+use anyhow::bail;
+
+use crate::{
+    allocator::{allocate, Placement},
+    channel::{read_frame, write_frame, Addr, Client, Listener, Request, Response},
+    czos::CZOS,
+    lock::{FileLock, LockWait},
+    memory::{HugePageSize, MemoryBacking},
+    meta::Meta,
+    observability::{Monitor, Observability},
+    ready::Ready,
+    resctrl::{Resctrl, ResctrlGroup},
+    resource::{Resource, ResourceError, StaticNet},
+    state::State,
+    topology::{NumaCell, Topology},
+    util::{format_cpuset, parse_cpuset, CpusetError},
+    vruntime::{DVRuntime, VRuntime},
+    ControlZone, UpdateMode, CHANNEL_FILE, CHANNEL_VSOCK_PORT, CZ_CONFIG, VRUNTIME_FILE,
+};
 
 #[test]
 fn test_parse_cpuset() {
     let cpu_set = "0,3";
-    let cpus = parse_cpuset(cpu_set);
+    let cpus = parse_cpuset(cpu_set).unwrap();
     assert_eq!(cpus, BTreeSet::from_iter(vec![0, 3]));
 
     let cpu_set = "0-3";
-    let cpus = parse_cpuset(cpu_set);
+    let cpus = parse_cpuset(cpu_set).unwrap();
     assert_eq!(cpus, BTreeSet::from_iter(vec![0, 1, 2, 3]));
 
     let cpu_set = "0-3,4,6-7";
-    let cpus = parse_cpuset(cpu_set);
+    let cpus = parse_cpuset(cpu_set).unwrap();
     assert_eq!(cpus, BTreeSet::from_iter(vec![0, 1, 2, 3, 4, 6, 7]));
 
-    let cpu_set = "0-3, 0-3";
-    let cpus = parse_cpuset(cpu_set);
-    assert_eq!(cpus, BTreeSet::from_iter(vec![0, 1, 2, 3]));
+    // sysfs cpulists end with newline, and may be empty
+    let cpus = parse_cpuset("0-1, 4\n").unwrap();
+    assert_eq!(cpus, BTreeSet::from_iter(vec![0, 1, 4]));
+    assert!(parse_cpuset("\n").unwrap().is_empty());
+
+    assert_eq!(parse_cpuset("0-3, 0-3"), Err(CpusetError::Duplicated(0)));
+    assert_eq!(
+        parse_cpuset("a-b"),
+        Err(CpusetError::Malformed(String::from("a-b")))
+    );
+    assert_eq!(
+        parse_cpuset("3-1"),
+        Err(CpusetError::Reversed(String::from("3-1")))
+    );
+    assert!(parse_cpuset("0,,1").is_err());
+    assert!(parse_cpuset("0-1-2").is_err());
+}
+
+#[test]
+fn test_emulator_cpuset() {
+    let mut resource: Resource = serde_yaml::from_str("cpuset: 2-3\nmemory: 512").unwrap();
+    resource.gen_cpus();
+    assert_eq!(resource.emulator_cpuset, None);
+    assert!(resource.emulator_cpus.is_empty());
+
+    let mut resource: Resource =
+        serde_yaml::from_str("cpuset: 2-3\nmemory: 512\nemulator_cpuset: 0-1").unwrap();
+    resource.gen_cpus();
+    assert_eq!(resource.cpus, vec![2, 3]);
+    assert_eq!(resource.emulator_cpus, vec![0, 1]);
+}
+
+#[test]
+fn test_observability() {
+    let observability: Observability = serde_yaml::from_str("monitors: [resctrl]").unwrap();
+    assert_eq!(
+        observability.perf_events,
+        Observability::default().perf_events
+    );
+    assert_eq!(observability.stats_period, 4);
+    assert_eq!(observability.monitors, vec![Monitor::Resctrl]);
+
+    let observability: Observability = serde_yaml::from_str(
+        "perf_events: [cpu_cycles, instructions]\nstats_period: 0\nmonitors: [resctrl, ebpf]",
+    )
+    .unwrap();
+    assert_eq!(
+        observability.perf_events,
+        vec!["cpu_cycles", "instructions"]
+    );
+    assert_eq!(observability.stats_period, 0);
+    assert_eq!(
+        observability.monitors,
+        vec![Monitor::Resctrl, Monitor::Ebpf]
+    );
+    assert!(serde_yaml::from_str::<Observability>("monitors: [perf]").is_err());
 }
 
 #[test]
@@ -39,3 +110,849 @@ iface eth0 inet static
 
     assert_eq!(cfg, target_cfg)
 }
+
+struct MockVRuntime {
+    mode: UpdateMode,
+    /// guest accepts graceful shutdown
+    shutdown: bool,
+    /// memory state of guest could be saved
+    memory_snapshot: bool,
+}
+
+impl Default for MockVRuntime {
+    fn default() -> Self {
+        MockVRuntime {
+            mode: UpdateMode::Hot,
+            shutdown: true,
+            memory_snapshot: true,
+        }
+    }
+}
+
+impl VRuntime for MockVRuntime {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    fn start(&self, _: &mut ControlZone) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn stop(&self, _: &mut ControlZone) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn shutdown(&self, _: &ControlZone) -> anyhow::Result<()> {
+        if !self.shutdown {
+            bail!("guest not responding")
+        }
+        Ok(())
+    }
+
+    fn tasks(&self, _: &ControlZone) -> anyhow::Result<Vec<u32>> {
+        Ok(vec![])
+    }
+
+    fn status(&self, _: &ControlZone) -> anyhow::Result<State> {
+        Ok(State::Stopped)
+    }
+
+    fn pause(&self, _: &ControlZone) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn resume(&self, _: &ControlZone) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn snapshot(&self, _: &ControlZone, _: &str) -> anyhow::Result<()> {
+        if !self.memory_snapshot {
+            bail!("migration blocked by 9p share")
+        }
+        Ok(())
+    }
+
+    fn snapshot_disk(&self, _: &ControlZone, _: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn revert_snapshot(&self, _: &ControlZone, _: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn delete_snapshot(&self, _: &ControlZone, _: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn update(&self, _: &ControlZone, _: &Resource) -> anyhow::Result<UpdateMode> {
+        Ok(self.mode)
+    }
+}
+
+fn mock_cz(name: &str, cpuset: &str, memory: u32) -> ControlZone {
+    let workdir = std::env::temp_dir().join(name);
+    std::fs::create_dir_all(&workdir).unwrap();
+
+    let mut cz = ControlZone {
+        meta: Meta {
+            name: name.to_owned(),
+            workdir: workdir.to_str().unwrap().to_owned(),
+            share_folder: workdir.join("controlzone").to_str().unwrap().to_owned(),
+            full_config: workdir.join(CZ_CONFIG).to_str().unwrap().to_owned(),
+        },
+        os: CZOS {
+            kernel: String::from("/tmp/kernel"),
+            initram_fs: None,
+            rootfs: String::from("/tmp/rootfs"),
+            kcmdline: String::from("console=ttyS0"),
+        },
+        resource: Resource {
+            cpuset: cpuset.to_owned(),
+            cpu_count: None,
+            placement: None,
+            memory,
+            static_net: None,
+            emulator_cpuset: None,
+            resctrl: None,
+            memory_backing: None,
+            topology: None,
+            cpus: vec![],
+            emulator_cpus: vec![],
+            guest_topology: None,
+        },
+        observability: Observability::default(),
+        state: State::Running,
+    };
+    cz.resource.gen_cpus();
+    cz
+}
+
+#[test]
+fn test_hot_update() {
+    let hot: DVRuntime = Box::<MockVRuntime>::default();
+    let reboot: DVRuntime = Box::new(MockVRuntime {
+        mode: UpdateMode::Reboot,
+        ..Default::default()
+    });
+
+    let mut cz = mock_cz("cz_test_hot_update", "0-1", 1024);
+    let mode = cz
+        .update(mock_cz("cz_test_hot_update", "0-1", 1024), &hot)
+        .unwrap();
+    assert_eq!(mode, UpdateMode::Stale);
+
+    let mode = cz
+        .update(mock_cz("cz_test_hot_update", "2-3", 2048), &hot)
+        .unwrap();
+    assert_eq!(mode, UpdateMode::Hot);
+    assert_eq!(cz.resource.cpus, vec![2, 3]);
+    assert_eq!(cz.resource.memory, 2048);
+
+    // vruntime can not apply it live
+    let mode = cz
+        .update(mock_cz("cz_test_hot_update", "0-3", 2048), &reboot)
+        .unwrap();
+    assert_eq!(mode, UpdateMode::Reboot);
+    assert_eq!(cz.resource.cpus, vec![0, 1, 2, 3]);
+
+    // paused vm is updated live as well
+    cz.state = State::Paused;
+    let mode = cz
+        .update(mock_cz("cz_test_hot_update", "0-1", 2048), &reboot)
+        .unwrap();
+    assert_eq!(mode, UpdateMode::Reboot);
+    let mode = cz
+        .update(mock_cz("cz_test_hot_update", "2-3", 2048), &hot)
+        .unwrap();
+    assert_eq!(mode, UpdateMode::Hot);
+    assert_eq!(cz.state, State::Paused);
+
+    // not running, nothing to apply live
+    cz.state = State::Stopped;
+    let mode = cz
+        .update(mock_cz("cz_test_hot_update", "0", 512), &reboot)
+        .unwrap();
+    assert_eq!(mode, UpdateMode::Hot);
+
+    // observability is applied on boot, and saved
+    cz.state = State::Running;
+    let mut new_cz = mock_cz("cz_test_hot_update", "0", 512);
+    new_cz.observability.stats_period = 0;
+    let mode = cz.update(new_cz, &hot).unwrap();
+    assert_eq!(mode, UpdateMode::Reboot);
+    assert_eq!(cz.observability.stats_period, 0);
+    let saved = std::fs::read_to_string(&cz.meta.full_config).unwrap();
+    let saved: ControlZone = serde_yaml::from_str(&saved).unwrap();
+    assert_eq!(saved.observability.stats_period, 0);
+
+    std::fs::remove_dir_all(&cz.meta.workdir).unwrap();
+}
+
+#[test]
+fn test_stop() {
+    let vruntime: DVRuntime = Box::<MockVRuntime>::default();
+
+    let mut cz = mock_cz("cz_test_stop", "0-1", 1024);
+    std::fs::create_dir_all(cz.state_file().parent().unwrap()).unwrap();
+
+    cz.stop(Duration::from_secs(1), false, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Stopped);
+    assert_eq!(
+        std::fs::read_to_string(cz.state_file()).unwrap(),
+        State::Stopped.to_string()
+    );
+
+    cz.state = State::Running;
+    cz.stop(Duration::from_secs(1), true, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Killed);
+
+    // killed zone is already stopped
+    cz.stop(Duration::from_secs(1), false, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Killed);
+    assert!(State::Killed.check_update(State::Running).is_ok());
+
+    // zone is destroyed if graceful shutdown could not be requested
+    let vruntime: DVRuntime = Box::new(MockVRuntime {
+        shutdown: false,
+        ..Default::default()
+    });
+    cz.state = State::Running;
+    cz.stop(Duration::from_secs(1), false, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Killed);
+
+    std::fs::remove_dir_all(&cz.meta.workdir).unwrap();
+}
+
+#[test]
+fn test_pause() {
+    let vruntime: DVRuntime = Box::<MockVRuntime>::default();
+
+    let mut cz = mock_cz("cz_test_pause", "0-1", 1024);
+    std::fs::create_dir_all(cz.state_file().parent().unwrap()).unwrap();
+
+    cz.pause(&vruntime).unwrap();
+    assert_eq!(cz.state, State::Paused);
+    cz.resume(&vruntime).unwrap();
+    assert_eq!(cz.state, State::Running);
+
+    cz.state = State::Stopped;
+    assert!(cz.pause(&vruntime).is_err());
+    assert!(cz.resume(&vruntime).is_err());
+
+    std::fs::remove_dir_all(&cz.meta.workdir).unwrap();
+}
+
+#[test]
+fn test_snapshot() {
+    let vruntime: DVRuntime = Box::<MockVRuntime>::default();
+
+    let mut cz = mock_cz("cz_test_snapshot", "0-1", 1024);
+    std::fs::create_dir_all(cz.state_file().parent().unwrap()).unwrap();
+    std::fs::write(cz.state_file(), State::Running.to_string()).unwrap();
+    cz.sync_to_file().unwrap();
+
+    let snapshot = cz
+        .create_snapshot(Some(String::from("base")), &vruntime)
+        .unwrap();
+    assert!(snapshot.memory);
+    assert!(cz
+        .create_snapshot(Some(String::from("base")), &vruntime)
+        .is_err());
+    assert!(cz
+        .create_snapshot(Some(String::from("../base")), &vruntime)
+        .is_err());
+    assert_eq!(cz.list_snapshots().unwrap(), vec![snapshot]);
+
+    // config is rolled back with snapshot
+    cz.update_config(mock_cz("cz_test_snapshot", "2-3", 2048))
+        .unwrap();
+    cz.restore_snapshot("base", &vruntime).unwrap();
+    assert_eq!(cz.resource.cpus, vec![0, 1]);
+    assert_eq!(cz.resource.memory, 1024);
+
+    assert!(cz.restore_snapshot("nothing", &vruntime).is_err());
+    cz.delete_snapshot("base", &vruntime).unwrap();
+    assert!(cz.list_snapshots().unwrap().is_empty());
+
+    // rootfs only if memory state could not be saved
+    let no_memory: DVRuntime = Box::new(MockVRuntime {
+        memory_snapshot: false,
+        ..Default::default()
+    });
+    let snapshot = cz
+        .create_snapshot(Some(String::from("disk")), &no_memory)
+        .unwrap();
+    assert!(!snapshot.memory);
+    assert!(cz.restore_snapshot("disk", &no_memory).is_err());
+    cz.delete_snapshot("disk", &no_memory).unwrap();
+
+    std::fs::remove_dir_all(&cz.meta.workdir).unwrap();
+}
+
+#[test]
+fn test_reconcile() {
+    let vruntime: DVRuntime = Box::<MockVRuntime>::default();
+
+    let mut cz = mock_cz("cz_test_reconcile", "0-1", 1024);
+    std::fs::create_dir_all(cz.state_file().parent().unwrap()).unwrap();
+    cz.sync_to_file().unwrap();
+
+    // zone started by another vruntime is skipped
+    std::fs::write(cz.state_file(), State::Running.to_string()).unwrap();
+    std::fs::write(
+        std::path::PathBuf::from(&cz.meta.workdir).join(VRUNTIME_FILE),
+        "other",
+    )
+    .unwrap();
+    assert_eq!(cz.reconcile(&vruntime).unwrap(), None);
+    assert_eq!(cz.state, State::Running);
+
+    // vm gone without a graceful shutdown
+    std::fs::write(
+        std::path::PathBuf::from(&cz.meta.workdir).join(VRUNTIME_FILE),
+        vruntime.name(),
+    )
+    .unwrap();
+    assert_eq!(cz.reconcile(&vruntime).unwrap(), Some(State::Running));
+    assert_eq!(cz.state, State::Killed);
+    assert_eq!(cz.reconcile(&vruntime).unwrap(), None);
+
+    std::fs::remove_dir_all(&cz.meta.workdir).unwrap();
+}
+
+#[test]
+fn test_wait() {
+    let vruntime: DVRuntime = Box::<MockVRuntime>::default();
+
+    let mut cz = mock_cz("cz_test_wait", "0-1", 1024);
+    std::fs::create_dir_all(cz.state_file().parent().unwrap()).unwrap();
+    std::fs::write(cz.state_file(), State::Running.to_string()).unwrap();
+    let timeout = Duration::from_secs(1);
+
+    // vm exited without ready record
+    assert!(vruntime.wait(&mut cz, timeout).is_err());
+
+    // boot complete before watch began
+    let ready = Ready::new(
+        String::from("boot"),
+        String::from(env!("CARGO_PKG_VERSION")),
+        Some(String::from("192.168.1.2")),
+    )
+    .unwrap();
+    ready.write(cz.ready_file()).unwrap();
+    assert_eq!(vruntime.wait(&mut cz, timeout).unwrap(), ready);
+    assert_eq!(cz.state, State::Running);
+
+    // failure reason of guest is reported
+    Ready::failed(String::from("boot"), String::from("0.1.0"), "no pod dir")
+        .unwrap()
+        .write(cz.ready_file())
+        .unwrap();
+    let err = vruntime.wait(&mut cz, timeout).unwrap_err();
+    assert!(err.to_string().contains("no pod dir"));
+
+    std::fs::remove_dir_all(&cz.meta.workdir).unwrap();
+}
+
+#[test]
+fn test_channel() {
+    let addr = Addr::from_str("vsock:3:10240").unwrap();
+    assert_eq!(
+        addr,
+        Addr::Vsock {
+            cid: 3,
+            port: 10240
+        }
+    );
+    assert_eq!(Addr::from_str(&addr.to_string()).unwrap(), addr);
+    assert!(Addr::from_str("tcp:3:10240").is_err());
+
+    let sock = std::env::temp_dir().join("cz_test_channel.sock");
+    let addr = Addr::Unix(sock.clone());
+    let listener = Listener::bind(&addr).unwrap();
+    let server = std::thread::spawn(move || {
+        let mut stream = listener.accept().unwrap();
+        while let Result::Ok(req) = read_frame::<_, Request>(&mut stream) {
+            let resp = match req {
+                Request::Ping => Response::Pong {
+                    version: String::from("0.1.0"),
+                },
+                Request::PodList => Response::Pods(vec![String::from("nginx.yaml")]),
+                Request::PodApply { name, yaml } if yaml.is_empty() => {
+                    Response::Error(format!("empty pod {name}"))
+                }
+                _ => Response::Ok,
+            };
+            write_frame(&mut stream, &resp).unwrap();
+        }
+    });
+
+    let mut client = Client::connect(&addr, Duration::from_secs(1)).unwrap();
+    assert_eq!(client.ping().unwrap(), "0.1.0");
+    assert_eq!(client.pod_list().unwrap(), vec![String::from("nginx.yaml")]);
+    client.pod_down("nginx.yaml").unwrap();
+    let err = client.pod_apply("nginx.yaml", String::new()).unwrap_err();
+    assert!(err.to_string().contains("empty pod nginx.yaml"));
+
+    drop(client);
+    server.join().unwrap();
+    std::fs::remove_file(sock).unwrap();
+
+    // cids recorded by zones under root are skipped
+    let root = std::env::temp_dir().join("cz_test_channel");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    std::fs::create_dir_all(root.join("other")).unwrap();
+    std::fs::write(root.join("other").join(CHANNEL_FILE), "vsock:3:10240").unwrap();
+    let cz = mock_cz("cz_test_channel/zone", "0", 1024);
+    let Addr::Vsock { cid, port } = cz.alloc_channel().unwrap() else {
+        panic!("channel not over vsock")
+    };
+    assert!(cid > 3);
+    assert_eq!(port, CHANNEL_VSOCK_PORT);
+    assert_eq!(cz.vsock_cid(), Some(cid));
+
+    // unix channel has no cid to reassign
+    std::fs::write(cz.channel_file(), "unix:/tmp/cz.sock").unwrap();
+    assert_eq!(cz.realloc_channel(LockWait::NoWait).unwrap(), None);
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_resctrl() {
+    let resctrl: Resctrl = serde_yaml::from_str(
+        "l3:
+  0: \"0xff0\"
+  1: \"ff\"
+mba:
+  0: 50
+  1: 100",
+    )
+    .unwrap();
+    assert!(resctrl.validate().is_ok());
+    assert_eq!(resctrl.schemata(), "L3:0=ff0;1=ff\nMB:0=50;1=100\n");
+
+    assert!(Resctrl::default().validate().is_err());
+    for mask in ["ffffffffffffffff", "0x8000000000000000", "fffffffffffffff0"] {
+        let resctrl = Resctrl {
+            l3: [(0, mask.to_owned())].into(),
+            ..Default::default()
+        };
+        assert!(resctrl.validate().is_ok(), "{mask}");
+    }
+    for mask in ["0", "f0f", "xyz", "8000000000000001"] {
+        let resctrl = Resctrl {
+            l3: [(0, mask.to_owned())].into(),
+            ..Default::default()
+        };
+        assert!(resctrl.validate().is_err(), "{mask}");
+    }
+    let resctrl = Resctrl {
+        mba: [(0, 0)].into(),
+        ..Default::default()
+    };
+    assert!(resctrl.validate().is_err());
+
+    // fake resctrl tree
+    let root = std::env::temp_dir().join("cz_test_resctrl");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    let group = ResctrlGroup::new(&root, "cz01");
+    let allocation = Resctrl {
+        l3: [(0, String::from("f"))].into(),
+        ..Default::default()
+    };
+    assert!(group.apply(&allocation, &[1]).is_err());
+
+    std::fs::create_dir(&root).unwrap();
+    group.apply(&allocation, &[1001, 1002]).unwrap();
+    let read = |file: &str| std::fs::read_to_string(group.dir().join(file)).unwrap();
+    assert_eq!(read("schemata"), "L3:0=f\n");
+    assert_eq!(read("tasks"), "1001\n1002\n");
+
+    // hot update rewrites schemata
+    let allocation = Resctrl {
+        mba: [(0, 30)].into(),
+        ..Default::default()
+    };
+    group.apply(&allocation, &[]).unwrap();
+    assert_eq!(read("schemata"), "MB:0=30\n");
+
+    // kernel removes files of group by itself
+    std::fs::remove_file(group.dir().join("schemata")).unwrap();
+    std::fs::remove_file(group.dir().join("tasks")).unwrap();
+    group.remove().unwrap();
+    assert!(!group.exists());
+
+    // entries of resctrl root are never taken as group of zone
+    let info = ResctrlGroup::new(&root, "info");
+    std::fs::create_dir(info.dir()).unwrap();
+    assert!(!info.exists());
+    assert!(info.apply(&allocation, &[1001]).is_err());
+    info.remove().unwrap();
+    assert!(info.dir().is_dir());
+
+    std::fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_memory_backing() {
+    let backing: MemoryBacking = serde_yaml::from_str(
+        "hugepages: 2M
+locked: true
+numa_node: 1",
+    )
+    .unwrap();
+    assert_eq!(backing.hugepages, Some(HugePageSize::Size2M));
+    assert!(backing.locked);
+
+    // fake sysfs with 2 nodes, 512 free 2M pages on node 1 only
+    let root = std::env::temp_dir().join("cz_test_memory_backing");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    for (node, cpus, free) in [(0, "0-3", "0"), (1, "4-7", "512")] {
+        let node_dir = root.join(format!("devices/system/node/node{node}"));
+        let pages_dir = node_dir.join("hugepages/hugepages-2048kB");
+        std::fs::create_dir_all(&pages_dir).unwrap();
+        std::fs::write(node_dir.join("cpulist"), format!("{cpus}\n")).unwrap();
+        std::fs::write(pages_dir.join("free_hugepages"), format!("{free}\n")).unwrap();
+    }
+    let pages_dir = root.join("kernel/mm/hugepages/hugepages-2048kB");
+    std::fs::create_dir_all(&pages_dir).unwrap();
+    std::fs::write(pages_dir.join("free_hugepages"), "512\n").unwrap();
+
+    assert!(backing.validate(1024, &[4, 5], &root).is_ok());
+    // not enough pages
+    assert!(backing.validate(2048, &[4, 5], &root).is_err());
+    // cpus on other node
+    assert!(backing.validate(1024, &[3, 4], &root).is_err());
+    // not a multiple of page size
+    assert!(backing.validate(1023, &[4, 5], &root).is_err());
+    // node not exists
+    let mut backing = backing;
+    backing.numa_node = Some(2);
+    assert!(backing.validate(1024, &[4, 5], &root).is_err());
+
+    // pages of any node
+    backing.numa_node = None;
+    assert!(backing.validate(1024, &[0, 4], &root).is_ok());
+    // no 1G pages reserved
+    backing.hugepages = Some(HugePageSize::Size1G);
+    assert!(backing.validate(1024, &[0, 4], &root).is_err());
+    // locking only
+    backing.hugepages = None;
+    assert!(backing.validate(1023, &[0, 4], &root).is_ok());
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_topology() {
+    let topology: Topology = serde_yaml::from_str(
+        "sockets: 1
+cores: 2
+threads: 2
+cells:
+  - cpus: \"0-1\"
+    memory: 1024
+  - cpus: \"2-3\"
+    memory: 1024",
+    )
+    .unwrap();
+    assert_eq!(topology.vcpus(), 4);
+    assert!(topology.validate(4, 2048, 1).is_ok());
+    assert!(topology.validate(2, 2048, 1).is_err());
+    assert!(topology.validate(4, 4096, 1).is_err());
+    let mut overlapped = topology.clone();
+    overlapped.cells[1].cpus = String::from("1-3");
+    assert!(overlapped.validate(4, 2048, 1).is_err());
+
+    // fake host of 2 sockets, 2 cores per socket and 2 threads per core,
+    // siblings are cpu N and N+4, a numa node per socket
+    let root = std::env::temp_dir().join("cz_test_topology");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    for cpu in 0..8 {
+        let cpu_dir = root.join(format!("devices/system/cpu/cpu{cpu}"));
+        let package = cpu % 4 / 2;
+        std::fs::create_dir_all(cpu_dir.join("topology")).unwrap();
+        std::fs::create_dir(cpu_dir.join(format!("node{package}"))).unwrap();
+        std::fs::write(
+            cpu_dir.join("topology/physical_package_id"),
+            format!("{package}\n"),
+        )
+        .unwrap();
+        std::fs::write(cpu_dir.join("topology/core_id"), format!("{}\n", cpu % 2)).unwrap();
+    }
+
+    let (host, cpus) = Topology::from_host(&[0, 1, 2, 3, 4, 5, 6, 7], 4096, 1, &root).unwrap();
+    assert_eq!(cpus, vec![0, 4, 1, 5, 2, 6, 3, 7]);
+    assert_eq!(
+        host,
+        Topology {
+            sockets: 2,
+            cores: 2,
+            threads: 2,
+            cells: vec![
+                NumaCell {
+                    cpus: String::from("0-3"),
+                    memory: 2048,
+                },
+                NumaCell {
+                    cpus: String::from("4-7"),
+                    memory: 2048,
+                },
+            ],
+        }
+    );
+    assert!(host.validate(8, 4096, 1).is_ok());
+
+    // no siblings pinned
+    let (host, cpus) = Topology::from_host(&[0, 1], 4096, 1, &root).unwrap();
+    assert_eq!(cpus, vec![0, 1]);
+    assert_eq!((host.sockets, host.cores, host.threads), (1, 2, 1));
+    assert!(host.cells.is_empty());
+    // only part of a core pinned
+    assert!(Topology::from_host(&[0, 1, 4], 4096, 1, &root).is_none());
+    assert!(Topology::from_host(&[8], 4096, 1, &root).is_none());
+    // cells are split on hugepages, or not at all if too small
+    let (host, _) = Topology::from_host(&[0, 1, 2, 3, 4, 5, 6, 7], 3072, 1024, &root).unwrap();
+    let memory: Vec<u32> = host.cells.iter().map(|cell| cell.memory).collect();
+    assert_eq!(memory, vec![1024, 2048]);
+    assert!(host.validate(8, 3072, 1024).is_ok());
+    assert!(host.validate(8, 3072, 2048).is_err());
+    let (host, _) = Topology::from_host(&[0, 1, 2, 3, 4, 5, 6, 7], 1024, 1024, &root).unwrap();
+    assert!(host.cells.is_empty());
+
+    // derived topology reorders cpus, a configured one is kept as is
+    let mut cz = mock_cz("cz_test_topology_zone", "0-1,4-5", 2048);
+    cz.resource.gen_topology(&root);
+    assert_eq!(cz.resource.cpus, vec![0, 4, 1, 5]);
+    assert_eq!(cz.resource.guest_topology.as_ref().unwrap().threads, 2);
+    cz.resource.gen_cpus();
+    cz.resource.topology = Some(topology.clone());
+    cz.resource.gen_topology(&root);
+    assert_eq!(cz.resource.cpus, vec![0, 1, 4, 5]);
+    assert_eq!(cz.resource.guest_topology, Some(topology.clone()));
+
+    // only a configured topology forces reboot
+    let vruntime: DVRuntime = Box::<MockVRuntime>::default();
+    let mut derived = mock_cz("cz_test_topology_zone", "0-7", 2048);
+    derived.resource.gen_topology(&root);
+    let mut new_cz = mock_cz("cz_test_topology_zone", "0-7", 4096);
+    new_cz.resource.gen_topology(&root);
+    assert_ne!(
+        new_cz.resource.guest_topology,
+        derived.resource.guest_topology
+    );
+    let mode = derived.update(new_cz, &vruntime).unwrap();
+    assert_eq!(mode, UpdateMode::Hot);
+    let mut new_cz = mock_cz("cz_test_topology_zone", "0-1,4-5", 4096);
+    new_cz.resource.topology = Some(topology);
+    let mode = derived.update(new_cz, &vruntime).unwrap();
+    assert_eq!(mode, UpdateMode::Reboot);
+
+    std::fs::remove_dir_all(&derived.meta.workdir).unwrap();
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_validate_resource() {
+    let root = std::env::temp_dir().join("cz_test_validate_resource");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    // host of 8 cpus with 4-5 offline and 6-7 isolated
+    let cpu_dir = root.join("sys/devices/system/cpu");
+    std::fs::create_dir_all(&cpu_dir).unwrap();
+    std::fs::write(cpu_dir.join("online"), "0-3,6-7\n").unwrap();
+    std::fs::write(cpu_dir.join("isolated"), "6-7\n").unwrap();
+    // zone pinned to 2-3
+    let other = mock_cz("cz_test_validate_other", "2-3", 1024);
+    std::fs::create_dir_all(root.join("zones/other")).unwrap();
+    std::fs::write(
+        root.join("zones/other").join(CZ_CONFIG),
+        serde_yaml::to_string(&other).unwrap(),
+    )
+    .unwrap();
+
+    let validate = |cpuset: &str, emulator_cpuset: Option<&str>| {
+        let mut resource = mock_cz("cz_test_validate", cpuset, 1024).resource;
+        resource.emulator_cpuset = emulator_cpuset.map(String::from);
+        resource.validate("cz_test_validate", &root.join("zones"), &root.join("sys"))
+    };
+
+    assert_eq!(validate("6-7", None), Ok(()));
+    // not isolated is only warned
+    assert_eq!(validate("0-1", Some("0")), Ok(()));
+    assert!(matches!(
+        validate("3-1", None),
+        Err(ResourceError::Cpuset {
+            field: "cpuset",
+            source: CpusetError::Reversed(_),
+            ..
+        })
+    ));
+    assert!(matches!(
+        validate("6", Some("a-b")),
+        Err(ResourceError::Cpuset {
+            field: "emulator_cpuset",
+            ..
+        })
+    ));
+    assert_eq!(validate("", None), Err(ResourceError::NoCpus));
+    assert_eq!(
+        validate("5-6", Some("9")),
+        Err(ResourceError::Offline(vec![5, 9]))
+    );
+    assert_eq!(
+        validate("1-2", None),
+        Err(ResourceError::Pinned {
+            cpus: vec![2],
+            zone: String::from("cz_test_validate_other"),
+        })
+    );
+    // own pins do not conflict
+    let resource = mock_cz("cz_test_validate_other", "2-3", 1024).resource;
+    let validated = resource.validate(
+        "cz_test_validate_other",
+        &root.join("zones"),
+        &root.join("sys"),
+    );
+    assert_eq!(validated, Ok(()));
+    // resctrl group of zone must not replace an entry of resctrl root
+    let validated = resource.validate("mon_groups", &root.join("zones"), &root.join("sys"));
+    assert_eq!(
+        validated,
+        Err(ResourceError::Reserved(String::from("mon_groups")))
+    );
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_allocate() {
+    let resource: Resource =
+        serde_yaml::from_str("cpus: 4\nplacement: smt-spread\nmemory: 512").unwrap();
+    assert_eq!(resource.cpu_count, Some(4));
+    assert_eq!(resource.placement, Some(Placement::SmtSpread));
+    assert!(resource.cpuset.is_empty());
+    let cpus = BTreeSet::from_iter(vec![0, 1, 2, 4, 6, 7]);
+    assert_eq!(format_cpuset(&cpus), "0-2,4,6-7");
+    assert_eq!(parse_cpuset(&format_cpuset(&cpus)).unwrap(), cpus);
+
+    // fake host of 2 sockets, 2 cores per socket and 2 threads per core,
+    // siblings are cpu N and N+4, a numa node per socket
+    let root = std::env::temp_dir().join("cz_test_allocate");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    let sysfs = root.join("sys");
+    let cpu_dir = sysfs.join("devices/system/cpu");
+    for cpu in 0..8 {
+        let package = cpu % 4 / 2;
+        let topology_dir = cpu_dir.join(format!("cpu{cpu}/topology"));
+        std::fs::create_dir_all(&topology_dir).unwrap();
+        std::fs::create_dir(cpu_dir.join(format!("cpu{cpu}/node{package}"))).unwrap();
+        std::fs::write(
+            topology_dir.join("physical_package_id"),
+            package.to_string(),
+        )
+        .unwrap();
+        std::fs::write(topology_dir.join("core_id"), (cpu % 2).to_string()).unwrap();
+    }
+    std::fs::write(cpu_dir.join("online"), "0-7\n").unwrap();
+    let zones = root.join("zones");
+    std::fs::create_dir_all(&zones).unwrap();
+
+    let alloc = |count: usize, placement: Option<Placement>, reserved: &[u32]| {
+        let reserved = reserved.iter().copied().collect();
+        allocate(count, placement, "cz01", &reserved, &zones, &sysfs)
+            .map(|cpus| format_cpuset(&cpus))
+    };
+    assert_eq!(alloc(2, None, &[]).unwrap(), "0-1");
+    assert_eq!(alloc(2, None, &[0]).unwrap(), "1-2");
+    assert_eq!(alloc(4, Some(Placement::SmtPack), &[]).unwrap(), "0-1,4-5");
+    assert_eq!(alloc(4, Some(Placement::SmtSpread), &[]).unwrap(), "0-3");
+    assert_eq!(alloc(4, Some(Placement::SameNuma), &[]).unwrap(), "0-1,4-5");
+    assert!(alloc(9, None, &[]).is_err());
+
+    // cpus of other zones are skipped
+    let other = mock_cz("cz_test_allocate_other", "0", 1024);
+    std::fs::create_dir_all(zones.join("other")).unwrap();
+    std::fs::write(
+        zones.join("other").join(CZ_CONFIG),
+        serde_yaml::to_string(&other).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(alloc(2, None, &[]).unwrap(), "1-2");
+    assert_eq!(alloc(4, Some(Placement::SameNuma), &[]).unwrap(), "2-3,6-7");
+    assert!(alloc(5, Some(Placement::SameNuma), &[]).is_err());
+    assert!(alloc(8, None, &[]).is_err());
+
+    // isolated cpus first, housekeeping ones if not enough
+    std::fs::write(cpu_dir.join("isolated"), "4-7\n").unwrap();
+    assert_eq!(alloc(2, None, &[]).unwrap(), "4-5");
+    assert_eq!(alloc(5, None, &[]).unwrap(), "1,4-7");
+    assert!(alloc(8, None, &[]).is_err());
+
+    // emulator cpus of other zones are skipped as well
+    let mut other = other;
+    other.resource.emulator_cpuset = Some(String::from("4"));
+    std::fs::write(
+        zones.join("other").join(CZ_CONFIG),
+        serde_yaml::to_string(&other).unwrap(),
+    )
+    .unwrap();
+    assert_eq!(alloc(2, None, &[]).unwrap(), "5-6");
+
+    // cpus of current resource kept while count is the same
+    let mut cz = mock_cz("cz_test_allocate", "", 1024);
+    cz.resource.cpu_count = Some(2);
+    let mut current = mock_cz("cz_test_allocate", "6-7", 1024).resource;
+    current.cpu_count = Some(2);
+    cz.allocate_cpus(Some(&current)).unwrap();
+    assert_eq!(cz.resource.cpuset, "6-7");
+    assert_eq!(cz.resource.cpus.len(), 2);
+    // cpuset configured wins
+    cz.resource.cpuset = String::from("0");
+    cz.allocate_cpus(None).unwrap();
+    assert_eq!(cz.resource.cpuset, "0");
+
+    std::fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn test_lock() {
+    let dir = std::env::temp_dir().join("cz_test_lock");
+    std::fs::create_dir_all(&dir).unwrap();
+    assert_eq!(FileLock::holder(&dir, ".lock"), None);
+
+    let lock = FileLock::acquire(&dir, ".lock", LockWait::NoWait).unwrap();
+    let holder = FileLock::holder(&dir, ".lock").unwrap();
+    assert!(holder.starts_with(&format!("{} ", std::process::id())));
+
+    // flock conflicts between open files of a process as well
+    assert!(FileLock::acquire(&dir, ".lock", LockWait::NoWait).is_err());
+    let timeout = LockWait::Timeout(Duration::from_millis(200));
+    let begin = std::time::Instant::now();
+    let err = FileLock::acquire(&dir, ".lock", timeout).err().unwrap();
+    assert!(begin.elapsed() >= Duration::from_millis(200));
+    assert!(err.to_string().contains(&holder));
+
+    drop(lock);
+    assert_eq!(FileLock::holder(&dir, ".lock"), None);
+    let lock = FileLock::acquire(&dir, ".lock", timeout).unwrap();
+    drop(lock);
+
+    // record left by a process exited without release
+    std::fs::write(dir.join(".lock"), format!("{} czctrl start", u32::MAX)).unwrap();
+    assert_eq!(FileLock::holder(&dir, ".lock"), None);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
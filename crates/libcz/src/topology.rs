@@ -0,0 +1,191 @@
+//! Guest cpu topology of control zone, sockets, cores and threads of vcpus
+//! and guest numa cells, derived from the host cpus pinned if not configured.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::Path,
+};
+
+use anyhow::{bail, Ok};
+use serde::{Deserialize, Serialize};
+
+use super::util::parse_cpuset;
+
+// sysfs/devices/system/cpu/cpu<id>/
+const CPU_DIR: &str = "devices/system/cpu";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Topology {
+    pub sockets: u32,
+    /// cores per socket
+    pub cores: u32,
+    /// threads per core
+    pub threads: u32,
+    /// guest numa nodes, a single node if empty
+    #[serde(default)]
+    pub cells: Vec<NumaCell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NumaCell {
+    /// vcpus of cell, e.g. `0-3`
+    pub cpus: String,
+    /// MiB
+    pub memory: u32,
+}
+
+impl Topology {
+    pub fn vcpus(&self) -> u32 {
+        self.sockets * self.cores * self.threads
+    }
+
+    /// check topology against vcpus and memory in MiB of zone, memory
+    /// of each numa cell is a multiple of `align` MiB, e.g. hugepage size
+    pub fn validate(&self, vcpus: usize, memory: u32, align: u32) -> anyhow::Result<()> {
+        if self.sockets == 0 || self.cores == 0 || self.threads == 0 {
+            bail!("empty topology: {:?}", self)
+        }
+        if self.vcpus() as usize != vcpus {
+            bail!("topology of {} vcpus but {vcpus} cpus pinned", self.vcpus())
+        }
+        if self.cells.is_empty() {
+            return Ok(());
+        }
+
+        let mut covered = BTreeSet::new();
+        for (id, cell) in self.cells.iter().enumerate() {
+            let cpus = match parse_cpuset(&cell.cpus) {
+                Result::Ok(cpus) => cpus,
+                Err(e) => bail!("invalid vcpus of numa cell {id}: {e}"),
+            };
+            if cpus.is_empty() {
+                bail!("no vcpus in numa cell {id}: {:?}", cell.cpus)
+            }
+            if cell.memory == 0 || cell.memory % align != 0 {
+                bail!(
+                    "memory of numa cell {id} {}MiB is not a multiple of {align}MiB",
+                    cell.memory
+                )
+            }
+            for cpu in cpus {
+                if cpu as usize >= vcpus || !covered.insert(cpu) {
+                    bail!("vcpu {cpu} of numa cell {id} not exists or in another cell")
+                }
+            }
+        }
+        if covered.len() != vcpus {
+            bail!("{} of {vcpus} vcpus in numa cells", covered.len())
+        }
+
+        let cells_memory: u32 = self.cells.iter().map(|cell| cell.memory).sum();
+        if cells_memory != memory {
+            bail!("memory of numa cells {cells_memory}MiB but {memory}MiB of zone")
+        }
+        Ok(())
+    }
+
+    /// topology of host cpus under sysfs root, together with the cpus
+    /// ordered as vcpus, so that threads of a guest core are pinned to
+    /// siblings of a host core, none if host topology is not uniform.
+    /// memory of cells is rounded down to `align` MiB but the last one
+    pub fn from_host(
+        cpus: &[u32],
+        memory: u32,
+        align: u32,
+        sysfs_root: &Path,
+    ) -> Option<(Self, Vec<u32>)> {
+        if cpus.is_empty() {
+            return None;
+        }
+
+        // (package, node, core, cpu)
+        let mut host: Vec<(u32, u32, u32, u32)> = cpus
+            .iter()
+            .map(|cpu| {
+                let (package, node, core) = host_cpu(*cpu, sysfs_root)?;
+                Some((package, node, core, *cpu))
+            })
+            .collect::<Option<_>>()?;
+        host.sort();
+
+        let mut sockets: BTreeMap<u32, BTreeMap<(u32, u32), u32>> = BTreeMap::new();
+        let mut nodes: BTreeMap<u32, u32> = BTreeMap::new();
+        for (package, node, core, _) in &host {
+            *sockets
+                .entry(*package)
+                .or_default()
+                .entry((*node, *core))
+                .or_default() += 1;
+            *nodes.entry(*node).or_default() += 1;
+        }
+
+        // not uniform if only part of a core or of a socket is pinned
+        let core_counts: BTreeSet<usize> = sockets.values().map(|cores| cores.len()).collect();
+        let thread_counts: BTreeSet<u32> = sockets
+            .values()
+            .flat_map(|cores| cores.values().copied())
+            .collect();
+        if core_counts.len() != 1 || thread_counts.len() != 1 {
+            return None;
+        }
+        let cores = *core_counts.first()? as u32;
+        let threads = *thread_counts.first()?;
+
+        // vcpus of a node are contiguous as sorted by node
+        let mut cells = vec![];
+        if nodes.len() > 1 {
+            let (mut start, mut assigned) = (0, 0);
+            for (id, count) in nodes.values().enumerate() {
+                let cell_memory = if id == nodes.len() - 1 {
+                    memory - assigned
+                } else {
+                    memory / cpus.len() as u32 * count / align * align
+                };
+                cells.push(NumaCell {
+                    cpus: format!("{}-{}", start, start + count - 1),
+                    memory: cell_memory,
+                });
+                start += count;
+                assigned += cell_memory;
+            }
+        }
+        // too little memory to split, guest sees a single node
+        if cells.iter().any(|cell| cell.memory == 0) {
+            cells.clear();
+        }
+
+        let topology = Topology {
+            sockets: sockets.len() as u32,
+            cores,
+            threads,
+            cells,
+        };
+        Some((topology, host.iter().map(|(_, _, _, cpu)| *cpu).collect()))
+    }
+}
+
+/// package, numa node and core of host cpu under sysfs root
+pub(crate) fn host_cpu(cpu: u32, sysfs_root: &Path) -> Option<(u32, u32, u32)> {
+    let dir = sysfs_root.join(CPU_DIR).join(format!("cpu{cpu}"));
+    let read = |file: &str| {
+        fs::read_to_string(dir.join("topology").join(file))
+            .ok()?
+            .trim()
+            .parse::<u32>()
+            .ok()
+    };
+    // cpu dir has a link to its node, e.g. `node0`
+    let node = fs::read_dir(&dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .find_map(|entry| {
+            entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("node")?
+                .parse::<u32>()
+                .ok()
+        })
+        .unwrap_or(0);
+    Some((read("physical_package_id")?, node, read("core_id")?))
+}
@@ -1,21 +1,67 @@
 use std::collections::BTreeSet;
 
-pub fn parse_cpuset(cpuset_config: &str) -> BTreeSet<u32> {
-    let mut cpus = Vec::new();
-    for part in cpuset_config.split(',') {
-        if part.contains('-') {
-            let range: Vec<&str> = part.split('-').collect();
-            if range.len() == 2 {
-                if let (Result::Ok(start), Result::Ok(end)) =
-                    (range[0].parse::<u32>(), range[1].parse::<u32>())
-                {
-                    cpus.extend(start..=end);
-                }
+/// Errors of cpuset syntax.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum CpusetError {
+    /// Part neither a cpu nor a range of cpus.
+    #[error("malformed part {0:?}")]
+    Malformed(String),
+
+    /// Range with start after end, e.g. `3-1`.
+    #[error("reversed range {0:?}")]
+    Reversed(String),
+
+    /// Cpu given by more than one part.
+    #[error("cpu {0} given more than once")]
+    Duplicated(u32),
+}
+
+/// parse cpu list like `0-3,6`, empty for an empty list
+pub fn parse_cpuset(cpuset_config: &str) -> Result<BTreeSet<u32>, CpusetError> {
+    let mut cpus = BTreeSet::new();
+    if cpuset_config.trim().is_empty() {
+        return Ok(cpus);
+    }
+
+    for part in cpuset_config.split(',').map(|part| part.trim()) {
+        let (start, end) = match part.split_once('-') {
+            Some((start, end)) => (start.parse::<u32>(), end.parse::<u32>()),
+            None => (part.parse::<u32>(), part.parse::<u32>()),
+        };
+        let (Result::Ok(start), Result::Ok(end)) = (start, end) else {
+            return Err(CpusetError::Malformed(part.to_owned()));
+        };
+        if start > end {
+            return Err(CpusetError::Reversed(part.to_owned()));
+        }
+
+        for cpu in start..=end {
+            if !cpus.insert(cpu) {
+                return Err(CpusetError::Duplicated(cpu));
             }
-        } else if let Result::Ok(num) = part.parse::<u32>() {
-            cpus.push(num);
         }
     }
+    Ok(cpus)
+}
 
-    BTreeSet::from_iter(cpus.into_iter())
+/// fold cpus into a cpu list like `0-3,6`
+pub fn format_cpuset(cpus: &BTreeSet<u32>) -> String {
+    let mut ranges: Vec<(u32, u32)> = vec![];
+    for cpu in cpus {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == *cpu => *end = *cpu,
+            _ => ranges.push((*cpu, *cpu)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{start}-{end}")
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(",")
 }
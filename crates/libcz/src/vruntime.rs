@@ -1,15 +1,22 @@
-use std::{fs, path::PathBuf, sync::mpsc, time::Duration};
+use std::{
+    fs,
+    path::PathBuf,
+    str::FromStr,
+    sync::mpsc,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
-use anyhow::Ok;
+use anyhow::{anyhow, bail, Ok};
 use log::{debug, error};
-use notify::{
-    event::{AccessKind, AccessMode},
-    Watcher,
-};
+use notify::Watcher;
 
-use crate::{state::State, ControlZone, INFO_DIR, IP_FILE};
+use crate::{
+    ready::Ready, resource::Resource, state::State, ControlZone, UpdateMode, INFO_DIR, IP_FILE,
+};
 
-const WAIT_TIMEOUT: u64 = 10;
+const WAIT_POLL_INTERVAL: u64 = 500;
+const STOP_POLL_INTERVAL: u64 = 500;
 
 pub type InfoPer = Box<dyn Fn(&ControlZone) -> anyhow::Result<()>>;
 pub type DVRuntime = Box<dyn VRuntime>;
@@ -33,9 +40,50 @@ pub fn addition_info_per(cz: &ControlZone) -> anyhow::Result<()> {
 }
 
 pub trait VRuntime {
+    /// name of vruntime, recorded in workdir of started control zone
+    fn name(&self) -> &'static str;
+
     fn start(&self, cz: &mut ControlZone) -> anyhow::Result<()>;
+    /// destroy vm of control zone, vm already exited is not an error
     fn stop(&self, cz: &mut ControlZone) -> anyhow::Result<()>;
 
+    /// ask guest to shutdown gracefully, e.g. through ACPI
+    fn shutdown(&self, cz: &ControlZone) -> anyhow::Result<()>;
+
+    /// actual state of vm of control zone, one of
+    /// `Running`, `Paused`, `Stopped` and `Error`
+    fn status(&self, cz: &ControlZone) -> anyhow::Result<State>;
+
+    /// threads of vm of control zone, vcpus and emulator included
+    fn tasks(&self, cz: &ControlZone) -> anyhow::Result<Vec<u32>>;
+
+    /// check if vm of control zone still exists
+    fn is_alive(&self, cz: &ControlZone) -> anyhow::Result<bool> {
+        Ok(self.status(cz)? != State::Stopped)
+    }
+
+    /// freeze all vcpus of control zone, guest state is kept
+    fn pause(&self, cz: &ControlZone) -> anyhow::Result<()>;
+    fn resume(&self, cz: &ControlZone) -> anyhow::Result<()>;
+
+    /// snapshot rootfs and memory of a running control zone
+    fn snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()>;
+    /// snapshot only rootfs of a running control zone, used when memory
+    /// state could not be saved
+    fn snapshot_disk(&self, cz: &ControlZone, _name: &str) -> anyhow::Result<()> {
+        bail!(
+            "{} vruntime could not snapshot rootfs of running {}",
+            self.name(),
+            cz.meta.name
+        )
+    }
+    fn revert_snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()>;
+    fn delete_snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()>;
+
+    /// apply new resource to a running control zone,
+    /// return `UpdateMode::Reboot` if it can not be applied live
+    fn update(&self, cz: &ControlZone, new_resource: &Resource) -> anyhow::Result<UpdateMode>;
+
     fn addi_bar(&self) {
         addition_info_bar();
     }
@@ -43,34 +91,76 @@ pub trait VRuntime {
         addition_info_per(cz)
     }
 
-    fn wait(&self, cz: &mut ControlZone) -> anyhow::Result<()> {
+    /// wait until czdaemon wrote a valid ready record, boot may be
+    /// complete before watch began, so record is checked first
+    fn wait(&self, cz: &mut ControlZone, timeout: Duration) -> anyhow::Result<Ready> {
         let (tx, rx) = mpsc::channel();
         let mut watcher = notify::recommended_watcher(
-            move |res: std::prelude::v1::Result<notify::Event, notify::Error>| match res {
-                Result::Ok(event) => {
+            move |res: std::prelude::v1::Result<notify::Event, notify::Error>| {
+                if let Result::Ok(event) = res {
                     debug!("{:?}", event);
-                    match event.kind {
-                        notify::EventKind::Access(AccessKind::Close(AccessMode::Write)) => {
-                            debug!("control zone state modified",);
-                            if let Err(e) = tx.send({}) {
-                                error!("failed to notify file change: {e}");
-                            }
-                        }
-                        _ => {}
-                    }
+                    let _ = tx.send(());
                 }
-                Err(_) => {}
             },
         )?;
 
+        let ready_f = cz.ready_file();
+        let info_dir = ready_f.parent().ok_or(anyhow!("invalid ready file"))?;
+        watcher.watch(info_dir, notify::RecursiveMode::NonRecursive)?;
+
+        let deadline = Instant::now() + timeout;
+        let mut last_err = None;
+        let ready = loop {
+            match Ready::read(&ready_f) {
+                Result::Ok(Some(ready)) => break ready,
+                Result::Ok(None) => {}
+                // record may be written by an old czdaemon non-atomically
+                Err(e) => last_err = Some(e),
+            }
+
+            if !self.is_alive(cz)? {
+                bail!("vm of {} exited before ready", cz.meta.name)
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                match last_err {
+                    Some(e) => bail!("wait timeout after {:?}, bad ready record: {e}", timeout),
+                    None => bail!("wait timeout after {:?}, czdaemon not ready", timeout),
+                }
+            }
+
+            // events may be missed, poll as well
+            let _ =
+                rx.recv_timeout((deadline - now).min(Duration::from_millis(WAIT_POLL_INTERVAL)));
+        };
+        watcher.unwatch(info_dir)?;
+        debug!("stop watch ready record");
+
+        ready.validate()?;
+
+        // czdaemon synced state before ready record written
+        cz.state = State::from_str(&fs::read_to_string(cz.state_file())?)?;
+        Ok(ready)
+    }
+
+    /// wait until vm exited or czdaemon reported stopped,
+    /// return false if timeout
+    fn wait_stopped(&self, cz: &ControlZone, timeout: Duration) -> anyhow::Result<bool> {
+        let deadline = Instant::now() + timeout;
         let state_f = cz.state_file();
-        watcher.watch(&state_f, notify::RecursiveMode::NonRecursive)?;
-        rx.recv_timeout(Duration::from_secs(WAIT_TIMEOUT))?;
-        watcher.unwatch(&state_f)?;
-        debug!("stop watch state");
-
-        // TODO: check state and avoid race
-        cz.state = State::Running;
-        Ok(())
+        while Instant::now() < deadline {
+            if !self.is_alive(cz)? {
+                debug!("vm of {} exited", cz.meta.name);
+                return Ok(true);
+            }
+
+            if let Result::Ok(State::Stopped) = State::from_str(&fs::read_to_string(&state_f)?) {
+                debug!("czdaemon of {} reported stopped", cz.meta.name);
+                return Ok(true);
+            }
+            sleep(Duration::from_millis(STOP_POLL_INTERVAL));
+        }
+        Ok(false)
     }
 }
@@ -1,2 +1,3 @@
 pub mod kvm;
 pub mod process;
+pub mod resctrl;
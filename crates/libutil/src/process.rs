@@ -81,6 +81,66 @@ fn parse_libvirt_cmdline(cmdline: &str) -> Result<(u32, String)> {
     Ok((vm_id, vm_name))
 }
 
+pub struct QemuInfo {
+    pub vm_name: String,
+    pub pid_file: String,
+    pub vm_cgroup: String,
+}
+
+/// Read info of a qemu process launched without libvirt, the process
+/// must be the one recorded in its `-pidfile`
+pub fn qemu_info_of(pid: u32) -> Result<QemuInfo> {
+    let proc_root = Path::new(PROC_FS).join(PathBuf::from(pid.to_string()));
+
+    if !proc_root.exists() || !proc_root.is_dir() {
+        bail!("process {} not exist", pid)
+    }
+
+    let cmdline_str = fs::read_to_string(proc_root.join("cmdline"))?;
+    let cgroup_str = fs::read_to_string(proc_root.join("cgroup"))?;
+
+    let (vm_name, pid_file) = parse_qemu_cmdline(&cmdline_str)?;
+    let Result::Ok(pid_s) = fs::read_to_string(&pid_file) else {
+        bail!("pid file {pid_file} of qemu {pid} not found")
+    };
+    if pid_s.trim() != pid.to_string() {
+        bail!("pid file {pid_file} not owned by qemu {pid}")
+    }
+
+    Ok(QemuInfo {
+        vm_name,
+        pid_file,
+        vm_cgroup: parse_libvirt_cgroup(&cgroup_str)?,
+    })
+}
+
+/// name and pid file from nul separated qemu cmdline
+fn parse_qemu_cmdline(cmdline: &str) -> Result<(String, String)> {
+    let args: Vec<&str> = cmdline.split('\0').collect();
+    let value_of = |opt: &str| {
+        args.iter()
+            .position(|arg| *arg == opt)
+            .and_then(|i| args.get(i + 1))
+            .copied()
+    };
+
+    let Some(name) = value_of("-name") else {
+        bail!("qemu cmdline without -name")
+    };
+    let Some(pid_file) = value_of("-pidfile") else {
+        bail!("qemu cmdline without -pidfile")
+    };
+
+    // -name [guest=]name[,debug-threads=on]
+    let vm_name = name.split(',').next().unwrap_or_default();
+    let vm_name = vm_name.strip_prefix("guest=").unwrap_or(vm_name);
+    if vm_name.is_empty() {
+        bail!("qemu cmdline with empty -name")
+    }
+    Ok((vm_name.to_owned(), pid_file.to_owned()))
+}
+
+/// cgroup of vm, emulator and vcpu threads are placed in its children
 #[inline]
 fn parse_libvirt_cgroup(cgroup: &str) -> Result<String> {
     Ok(cgroup
@@ -93,7 +153,7 @@ fn parse_libvirt_cgroup(cgroup: &str) -> Result<String> {
 #[cfg(test)]
 
 mod test {
-    use super::parse_libvirt_cgroup;
+    use super::{parse_libvirt_cgroup, parse_qemu_cmdline};
 
     #[test]
     fn moke_parse_libvirt_cgroup() {
@@ -106,4 +166,23 @@ mod test {
             r"/machine.slice/machine-qemu\x2d32\x2dcontrolzonedefault.scope/libvirt"
         )
     }
+
+    #[test]
+    fn test_parse_qemu_cmdline() {
+        let cmdline = "qemu-system-x86_64\0-pidfile\0/tmp/controlzones/cz01/qpid\0-name\0cz01\0";
+        assert_eq!(
+            parse_qemu_cmdline(cmdline).unwrap(),
+            (
+                String::from("cz01"),
+                String::from("/tmp/controlzones/cz01/qpid")
+            )
+        );
+
+        let cmdline =
+            "qemu-system-x86_64\0-name\0guest=cz02,debug-threads=on\0-pidfile\0/run/cz02.pid\0";
+        assert_eq!(parse_qemu_cmdline(cmdline).unwrap().0, "cz02");
+
+        assert!(parse_qemu_cmdline("qemu-system-x86_64\0-name\0cz03\0").is_err());
+        assert!(parse_qemu_cmdline("qemu-system-x86_64\0-pidfile\0/run/x.pid\0-name").is_err());
+    }
 }
@@ -0,0 +1,159 @@
+//! Monitoring data of resctrl groups, counters are read per L3 domain.
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::{bail, Ok, Result};
+use serde::Serialize;
+
+const MON_DATA_DIR: &str = "mon_data";
+const MON_L3_PREFIX: &str = "mon_L3_";
+const LLC_OCCUPANCY: &str = "llc_occupancy";
+const MBM_TOTAL_BYTES: &str = "mbm_total_bytes";
+const MBM_LOCAL_BYTES: &str = "mbm_local_bytes";
+
+/// counters of one L3 domain, none if unsupported or unavailable
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonSample {
+    pub domain: u32,
+    /// bytes of l3 cache occupied
+    pub llc_occupancy: Option<u64>,
+    pub mbm_total_bytes: Option<u64>,
+    pub mbm_local_bytes: Option<u64>,
+}
+
+/// Read counters of a resctrl control or monitor group
+pub fn read_mon_data(group: &Path) -> Result<Vec<MonSample>> {
+    let mon_data = group.join(MON_DATA_DIR);
+    if !mon_data.is_dir() {
+        bail!("{:?} is not a resctrl group", group)
+    }
+
+    let mut samples: Vec<MonSample> = fs::read_dir(mon_data)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let domain = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix(MON_L3_PREFIX)?
+                .parse::<u32>()
+                .ok()?;
+
+            let dir = entry.path();
+            Some(MonSample {
+                domain,
+                llc_occupancy: read_counter(&dir.join(LLC_OCCUPANCY)),
+                mbm_total_bytes: read_counter(&dir.join(MBM_TOTAL_BYTES)),
+                mbm_local_bytes: read_counter(&dir.join(MBM_LOCAL_BYTES)),
+            })
+        })
+        .collect();
+
+    samples.sort_by_key(|sample| sample.domain);
+    Ok(samples)
+}
+
+/// kernel reports `Unavailable` or `Error` instead of a value sometimes
+fn read_counter(path: &Path) -> Option<u64> {
+    fs::read_to_string(path).ok()?.trim().parse::<u64>().ok()
+}
+
+/// occupancy and bandwidth of one L3 domain over an interval
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MonRate {
+    pub domain: u32,
+    /// bytes of l3 cache occupied at the end of interval
+    pub llc_occupancy: Option<u64>,
+    /// bytes per second
+    pub mbm_total_rate: Option<f64>,
+    /// bytes per second
+    pub mbm_local_rate: Option<f64>,
+}
+
+/// Compute rates between two samples of a group taken `elapsed` apart
+pub fn mon_rates(prev: &[MonSample], curr: &[MonSample], elapsed: Duration) -> Vec<MonRate> {
+    let secs = elapsed.as_secs_f64();
+    let rate = |prev: Option<u64>, curr: Option<u64>| match (prev, curr) {
+        // counter reset if group recreated
+        (Some(prev), Some(curr)) if curr >= prev && secs > 0.0 => Some((curr - prev) as f64 / secs),
+        _ => None,
+    };
+
+    curr.iter()
+        .map(|curr| {
+            let prev = prev.iter().find(|prev| prev.domain == curr.domain);
+            MonRate {
+                domain: curr.domain,
+                llc_occupancy: curr.llc_occupancy,
+                mbm_total_rate: rate(
+                    prev.and_then(|prev| prev.mbm_total_bytes),
+                    curr.mbm_total_bytes,
+                ),
+                mbm_local_rate: rate(
+                    prev.and_then(|prev| prev.mbm_local_bytes),
+                    curr.mbm_local_bytes,
+                ),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use super::{mon_rates, read_mon_data, MonSample};
+
+    #[test]
+    fn test_mon_data() {
+        let group = std::env::temp_dir().join("libutil_test_mon_data");
+        if group.exists() {
+            fs::remove_dir_all(&group).unwrap();
+        }
+        assert!(read_mon_data(&group).is_err());
+
+        let write = |domain: &str, file: &str, value: &str| {
+            let dir = group.join("mon_data").join(domain);
+            fs::create_dir_all(&dir).unwrap();
+            fs::write(dir.join(file), value).unwrap();
+        };
+        write("mon_L3_01", "llc_occupancy", "4096\n");
+        write("mon_L3_01", "mbm_total_bytes", "3000\n");
+        write("mon_L3_01", "mbm_local_bytes", "Unavailable\n");
+        write("mon_L3_00", "llc_occupancy", "1024\n");
+        write("mon_L3_00", "mbm_total_bytes", "1000\n");
+        write("mon_L3_00", "mbm_local_bytes", "500\n");
+
+        let prev = read_mon_data(&group).unwrap();
+        assert_eq!(
+            prev,
+            vec![
+                MonSample {
+                    domain: 0,
+                    llc_occupancy: Some(1024),
+                    mbm_total_bytes: Some(1000),
+                    mbm_local_bytes: Some(500),
+                },
+                MonSample {
+                    domain: 1,
+                    llc_occupancy: Some(4096),
+                    mbm_total_bytes: Some(3000),
+                    mbm_local_bytes: None,
+                },
+            ]
+        );
+
+        write("mon_L3_00", "mbm_total_bytes", "5000\n");
+        write("mon_L3_00", "mbm_local_bytes", "100\n");
+        write("mon_L3_01", "mbm_total_bytes", "3000\n");
+        let curr = read_mon_data(&group).unwrap();
+
+        let rates = mon_rates(&prev, &curr, Duration::from_secs(2));
+        assert_eq!(rates[0].mbm_total_rate, Some(2000.0));
+        // counter went backwards
+        assert_eq!(rates[0].mbm_local_rate, None);
+        assert_eq!(rates[1].llc_occupancy, Some(4096));
+        assert_eq!(rates[1].mbm_total_rate, Some(0.0));
+        assert_eq!(rates[1].mbm_local_rate, None);
+
+        fs::remove_dir_all(group).unwrap();
+    }
+}
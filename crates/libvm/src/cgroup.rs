@@ -0,0 +1,223 @@
+//! Cgroup v2 placement of qemu-runtime zones, in the layout libvirt gives
+//! its domains: emulator threads and each vcpu thread are put in threaded
+//! children of the zone cgroup under `controlzone.slice`.
+use std::{
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Ok};
+use libcz::resource::Resource;
+use log::{debug, warn};
+
+pub const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const ZONE_SLICE: &str = "controlzone.slice";
+const EMULATOR_CGROUP: &str = "emulator";
+const VCPU_CGROUP_PREFIX: &str = "vcpu";
+
+const CONTROLLERS_FILE: &str = "cgroup.controllers";
+const SUBTREE_CONTROL_FILE: &str = "cgroup.subtree_control";
+const TYPE_FILE: &str = "cgroup.type";
+const PROCS_FILE: &str = "cgroup.procs";
+const THREADS_FILE: &str = "cgroup.threads";
+
+/// microseconds
+const CPU_MAX_PERIOD: u64 = 100000;
+/// MiB allowed to qemu itself on top of guest memory
+const MEMORY_OVERHEAD: u64 = 256;
+
+/// cgroup of a zone under cgroupfs root
+pub struct ZoneCgroup {
+    root: PathBuf,
+    dir: PathBuf,
+}
+
+impl ZoneCgroup {
+    pub fn new(root: &Path, zone: &str) -> Self {
+        ZoneCgroup {
+            root: root.to_owned(),
+            dir: root.join(ZONE_SLICE).join(zone),
+        }
+    }
+
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// path relative to cgroupfs root, as shown in `/proc/<pid>/cgroup`
+    pub fn path(&self) -> String {
+        let relative = self.dir.strip_prefix(&self.root).unwrap_or(&self.dir);
+        format!("/{}", relative.display())
+    }
+
+    pub fn exists(&self) -> bool {
+        self.dir.is_dir()
+    }
+
+    /// cgroup v2 mounted at root
+    pub fn available(&self) -> bool {
+        self.root.join(CONTROLLERS_FILE).is_file()
+    }
+
+    #[inline]
+    fn emulator_dir(&self) -> PathBuf {
+        self.dir.join(EMULATOR_CGROUP)
+    }
+
+    #[inline]
+    fn vcpu_dir(&self, vcpu: usize) -> PathBuf {
+        self.dir.join(format!("{VCPU_CGROUP_PREFIX}{vcpu}"))
+    }
+
+    /// create zone cgroup and its threaded children, then apply limits,
+    /// an existing cgroup is reused
+    pub fn create(&self, resource: &Resource) -> anyhow::Result<()> {
+        if !self.available() {
+            bail!("cgroup v2 not mounted at {:?}", self.root)
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        // memory is not a threaded controller, it stops at zone
+        write_file(&self.root, SUBTREE_CONTROL_FILE, "+cpu +cpuset +memory")?;
+        write_file(
+            &self.root.join(ZONE_SLICE),
+            SUBTREE_CONTROL_FILE,
+            "+cpu +cpuset +memory",
+        )?;
+
+        self.remove_stale_vcpus(resource.cpus.len());
+        let children = (0..resource.cpus.len())
+            .map(|vcpu| self.vcpu_dir(vcpu))
+            .chain([self.emulator_dir()]);
+        for child in children {
+            if !child.is_dir() {
+                fs::create_dir(&child)?;
+            }
+            write_file(&child, TYPE_FILE, "threaded")?;
+        }
+        write_file(&self.dir, SUBTREE_CONTROL_FILE, "+cpu +cpuset")?;
+        debug!("cgroup {:?} created", self.dir);
+
+        self.apply(resource)
+    }
+
+    /// vcpus of a previous boot
+    fn remove_stale_vcpus(&self, vcpus: usize) {
+        let Result::Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let stale = entry
+                .file_name()
+                .to_str()
+                .and_then(|name| name.strip_prefix(VCPU_CGROUP_PREFIX))
+                .and_then(|vcpu| vcpu.parse::<usize>().ok())
+                .is_some_and(|vcpu| vcpu >= vcpus);
+            if stale {
+                if let Err(e) = fs::remove_dir(entry.path()) {
+                    warn!("remove stale cgroup {:?} failed: {e}", entry.path());
+                }
+            }
+        }
+    }
+
+    /// write limits derived from resource, could be applied again live
+    pub fn apply(&self, resource: &Resource) -> anyhow::Result<()> {
+        self.apply_memory(resource.memory)?;
+        self.apply_cpus(resource)
+    }
+
+    /// limit memory of zone to guest memory in MiB and overhead of qemu
+    pub fn apply_memory(&self, memory: u32) -> anyhow::Result<()> {
+        let memory = (memory as u64 + MEMORY_OVERHEAD) * 1024 * 1024;
+        write_file(&self.dir, "memory.max", &memory.to_string())
+    }
+
+    /// cpu quota and cpusets of zone, emulator and vcpus
+    pub fn apply_cpus(&self, resource: &Resource) -> anyhow::Result<()> {
+        // a floating emulator is given one cpu worth of time
+        let cpus = resource.cpus.len() + resource.emulator_cpus.len().max(1);
+        let quota = cpus as u64 * CPU_MAX_PERIOD;
+        write_file(&self.dir, "cpu.max", &format!("{quota} {CPU_MAX_PERIOD}"))?;
+
+        // empty cpuset inherits from parent
+        let emulator_cpuset = join_cpus(&resource.emulator_cpus);
+        let zone_cpuset = if resource.emulator_cpus.is_empty() {
+            String::new()
+        } else {
+            let mut cpus = [resource.cpus.as_slice(), &resource.emulator_cpus].concat();
+            cpus.sort();
+            cpus.dedup();
+            join_cpus(&cpus)
+        };
+        write_file(&self.dir, "cpuset.cpus", &zone_cpuset)?;
+        write_file(&self.emulator_dir(), "cpuset.cpus", &emulator_cpuset)?;
+        for (vcpu, cpu) in resource.cpus.iter().enumerate() {
+            write_file(&self.vcpu_dir(vcpu), "cpuset.cpus", &cpu.to_string())?;
+        }
+        Ok(())
+    }
+
+    /// move process into zone, vcpu threads into their own cgroups by
+    /// index and other threads into emulator
+    pub fn place(&self, pid: u32, vcpu_tids: &[u32], tasks: &[u32]) -> anyhow::Result<()> {
+        write_file(&self.dir, PROCS_FILE, &pid.to_string())?;
+
+        let emulator_tids: Vec<u32> = tasks
+            .iter()
+            .filter(|task| !vcpu_tids.contains(task))
+            .copied()
+            .collect();
+        append_tasks(&self.emulator_dir(), THREADS_FILE, &emulator_tids)?;
+        for (vcpu, tid) in vcpu_tids.iter().enumerate() {
+            append_tasks(&self.vcpu_dir(vcpu), THREADS_FILE, &[*tid])?;
+        }
+        debug!("{} threads placed in {:?}", tasks.len(), self.dir);
+        Ok(())
+    }
+
+    /// remove zone cgroup, it must have no threads left
+    pub fn remove(&self) -> anyhow::Result<()> {
+        if !self.exists() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&self.dir)?.filter_map(|entry| entry.ok()) {
+            if entry.path().is_dir() {
+                fs::remove_dir(entry.path())?;
+            }
+        }
+        fs::remove_dir(&self.dir)?;
+        debug!("cgroup {:?} removed", self.dir);
+        Ok(())
+    }
+}
+
+fn join_cpus(cpus: &[u32]) -> String {
+    let cpus: Vec<String> = cpus.iter().map(|cpu| cpu.to_string()).collect();
+    cpus.join(",")
+}
+
+fn write_file(dir: &Path, file: &str, value: &str) -> anyhow::Result<()> {
+    if let Err(e) = fs::write(dir.join(file), value) {
+        bail!("write {value:?} to {file} of {:?} failed: {e}", dir)
+    }
+    Ok(())
+}
+
+/// kernel takes one task per write
+fn append_tasks(dir: &Path, file: &str, tasks: &[u32]) -> anyhow::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(file))?;
+    for task in tasks {
+        // thread may exit meanwhile
+        if let Err(e) = file.write_all(format!("{task}\n").as_bytes()) {
+            warn!("move task {task} to {:?} failed: {e}", dir);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,834 @@
+//! Typed model of libvirt domain xml, only elements used by control zone
+//! are modelled, others in `XMLDesc` are ignored on parsing.
+use anyhow::{bail, Ok};
+use libcz::{util::parse_cpuset, ControlZone};
+use serde::{Deserialize, Serialize};
+
+const EMULATOR: &str = "/usr/bin/qemu-system-x86_64";
+pub(crate) const SHARE_TAG: &str = "hostshare";
+pub(crate) const BRIDGE: &str = "br0";
+pub(crate) const ROOTFS_DEV: &str = "vda";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename = "domain")]
+pub struct DomainDef {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    pub name: String,
+    pub memory: Memory,
+    /// memory after ballooned, only in live xml
+    #[serde(
+        rename = "currentMemory",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub current_memory: Option<Memory>,
+    #[serde(
+        rename = "memoryBacking",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub memory_backing: Option<MemoryBacking>,
+    pub vcpu: Vcpu,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cputune: Option<CpuTune>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub numatune: Option<NumaTune>,
+    pub os: Os,
+    #[serde(default)]
+    pub features: Features,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cpu: Option<Cpu>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock: Option<Clock>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_poweroff: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_reboot: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub on_crash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub perf: Option<Perf>,
+    pub devices: Devices,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Memory {
+    #[serde(rename = "@unit", default = "default_unit")]
+    pub unit: String,
+    #[serde(rename = "$text")]
+    pub value: u64,
+}
+
+#[inline]
+fn default_unit() -> String {
+    String::from("KiB")
+}
+
+impl Memory {
+    /// size in KiB, libvirt 'MB' is 10^6 bytes while 'MiB' is 2^20 bytes
+    pub fn to_kib(&self) -> anyhow::Result<u64> {
+        let bytes: u64 = match self.unit.as_str() {
+            "b" | "bytes" => 1,
+            "KB" => 1000,
+            "k" | "KiB" => 1 << 10,
+            "MB" => 1000 * 1000,
+            "M" | "MiB" => 1 << 20,
+            "GB" => 1000 * 1000 * 1000,
+            "G" | "GiB" => 1 << 30,
+            unit => bail!("unknown memory unit: {unit}"),
+        };
+        Ok(self.value * bytes / 1024)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryBacking {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hugepages: Option<HugePages>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub locked: Option<Flag>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HugePages {
+    #[serde(rename = "page", default)]
+    pub pages: Vec<Page>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Page {
+    #[serde(rename = "@size")]
+    pub size: u64,
+    #[serde(rename = "@unit", default = "default_unit")]
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NumaTune {
+    pub memory: NumaMemory,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NumaMemory {
+    #[serde(rename = "@mode")]
+    pub mode: String,
+    #[serde(rename = "@nodeset")]
+    pub nodeset: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vcpu {
+    #[serde(
+        rename = "@placement",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub placement: Option<String>,
+    /// vcpus online, only if less than maximum
+    #[serde(rename = "@current", default, skip_serializing_if = "Option::is_none")]
+    pub current: Option<u32>,
+    #[serde(rename = "$text")]
+    pub value: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CpuTune {
+    #[serde(rename = "vcpupin", default)]
+    pub vcpupins: Vec<VcpuPin>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emulatorpin: Option<EmulatorPin>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VcpuPin {
+    #[serde(rename = "@vcpu")]
+    pub vcpu: u32,
+    #[serde(rename = "@cpuset")]
+    pub cpuset: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EmulatorPin {
+    #[serde(rename = "@cpuset")]
+    pub cpuset: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Os {
+    #[serde(rename = "type")]
+    pub kind: OsType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kernel: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub initrd: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmdline: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub boot: Option<Boot>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bootmenu: Option<BootMenu>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OsType {
+    #[serde(rename = "@arch", default, skip_serializing_if = "Option::is_none")]
+    pub arch: Option<String>,
+    /// machine is chosen by libvirt if not set
+    #[serde(rename = "@machine", default, skip_serializing_if = "Option::is_none")]
+    pub machine: Option<String>,
+    #[serde(rename = "$text")]
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Boot {
+    #[serde(rename = "@dev")]
+    pub dev: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BootMenu {
+    #[serde(rename = "@enable")]
+    pub enable: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Features {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub acpi: Option<Flag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub apic: Option<Flag>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pae: Option<Flag>,
+}
+
+/// empty element
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Flag {}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cpu {
+    #[serde(rename = "@mode")]
+    pub mode: String,
+    #[serde(rename = "@check", default, skip_serializing_if = "Option::is_none")]
+    pub check: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topology: Option<CpuTopology>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub numa: Option<Numa>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CpuTopology {
+    #[serde(rename = "@sockets")]
+    pub sockets: u32,
+    #[serde(rename = "@cores")]
+    pub cores: u32,
+    #[serde(rename = "@threads")]
+    pub threads: u32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Numa {
+    #[serde(rename = "cell", default)]
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cell {
+    #[serde(rename = "@id")]
+    pub id: u32,
+    #[serde(rename = "@cpus")]
+    pub cpus: String,
+    #[serde(rename = "@memory")]
+    pub memory: u64,
+    #[serde(rename = "@unit", default = "default_unit")]
+    pub unit: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clock {
+    #[serde(rename = "@offset")]
+    pub offset: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Perf {
+    #[serde(rename = "event", default)]
+    pub events: Vec<PerfEvent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PerfEvent {
+    #[serde(rename = "@name")]
+    pub name: String,
+    #[serde(rename = "@enabled")]
+    pub enabled: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Devices {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub emulator: Option<String>,
+    #[serde(rename = "disk", default)]
+    pub disks: Vec<Disk>,
+    #[serde(rename = "interface", default)]
+    pub interfaces: Vec<Interface>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<Serial>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub console: Option<Console>,
+    #[serde(rename = "input", default)]
+    pub inputs: Vec<Input>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub memballoon: Option<MemBalloon>,
+    #[serde(rename = "filesystem", default)]
+    pub filesystems: Vec<Filesystem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsock: Option<Vsock>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Disk {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    #[serde(rename = "@device")]
+    pub device: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<Driver>,
+    pub source: Source,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<Alias>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Driver {
+    #[serde(rename = "@name", default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(rename = "@type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(rename = "@iommu", default, skip_serializing_if = "Option::is_none")]
+    pub iommu: Option<String>,
+}
+
+/// source of disk, interface or filesystem
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Source {
+    #[serde(rename = "@file", default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    #[serde(rename = "@network", default, skip_serializing_if = "Option::is_none")]
+    pub network: Option<String>,
+    #[serde(rename = "@bridge", default, skip_serializing_if = "Option::is_none")]
+    pub bridge: Option<String>,
+    #[serde(rename = "@dir", default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+}
+
+/// target of disk, serial, console or filesystem
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Target {
+    #[serde(rename = "@dev", default, skip_serializing_if = "Option::is_none")]
+    pub dev: Option<String>,
+    #[serde(rename = "@bus", default, skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+    #[serde(rename = "@type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(rename = "@port", default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u32>,
+    #[serde(rename = "@dir", default, skip_serializing_if = "Option::is_none")]
+    pub dir: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Model {
+    #[serde(rename = "@type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(rename = "@name", default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alias {
+    #[serde(rename = "@name")]
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Interface {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    pub source: Source,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<Model>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub driver: Option<Driver>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<Alias>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Serial {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Console {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<Target>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Input {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    #[serde(rename = "@bus", default, skip_serializing_if = "Option::is_none")]
+    pub bus: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MemBalloon {
+    #[serde(rename = "@model")]
+    pub model: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stats: Option<Stats>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Stats {
+    #[serde(rename = "@period")]
+    pub period: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Filesystem {
+    #[serde(rename = "@type")]
+    pub kind: String,
+    #[serde(
+        rename = "@accessmode",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub accessmode: Option<String>,
+    pub source: Source,
+    pub target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vsock {
+    #[serde(rename = "@model")]
+    pub model: String,
+    pub cid: Cid,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cid {
+    #[serde(rename = "@auto")]
+    pub auto: String,
+    #[serde(rename = "@address")]
+    pub address: u32,
+}
+
+/// field differs between config and live domain
+#[derive(Debug, PartialEq)]
+pub struct Diff {
+    pub field: String,
+    pub config: String,
+    pub runtime: String,
+}
+
+impl DomainDef {
+    pub fn from_cz(cz: &ControlZone) -> Self {
+        let observability = &cz.observability;
+        let cputune = CpuTune {
+            vcpupins: cz
+                .resource
+                .cpus
+                .iter()
+                .enumerate()
+                .map(|(vcpu, cpu)| VcpuPin {
+                    vcpu: vcpu as u32,
+                    cpuset: cpu.to_string(),
+                })
+                .collect(),
+            emulatorpin: (!cz.resource.emulator_cpus.is_empty()).then(|| EmulatorPin {
+                cpuset: cz
+                    .resource
+                    .emulator_cpus
+                    .iter()
+                    .map(|cpu| cpu.to_string())
+                    .collect::<Vec<String>>()
+                    .join(","),
+            }),
+        };
+
+        let backing = cz.resource.memory_backing.as_ref();
+        let memory_backing = backing
+            .filter(|backing| backing.hugepages.is_some() || backing.locked)
+            .map(|backing| MemoryBacking {
+                hugepages: backing.hugepages.map(|size| HugePages {
+                    pages: vec![Page {
+                        size: size.kib(),
+                        unit: String::from("KiB"),
+                    }],
+                }),
+                locked: backing.locked.then_some(Flag {}),
+            });
+        let numatune = backing
+            .and_then(|backing| backing.numa_node)
+            .map(|node| NumaTune {
+                memory: NumaMemory {
+                    mode: String::from("strict"),
+                    nodeset: node.to_string(),
+                },
+            });
+
+        // guest sees host cores as they are with a topology
+        let topology = cz.resource.guest_topology.as_ref();
+        let cpu = match topology {
+            Some(topology) => Cpu {
+                mode: String::from("host-passthrough"),
+                check: Some(String::from("none")),
+                topology: Some(CpuTopology {
+                    sockets: topology.sockets,
+                    cores: topology.cores,
+                    threads: topology.threads,
+                }),
+                numa: (!topology.cells.is_empty()).then(|| Numa {
+                    cells: topology
+                        .cells
+                        .iter()
+                        .enumerate()
+                        .map(|(id, cell)| Cell {
+                            id: id as u32,
+                            cpus: cell.cpus.clone(),
+                            memory: cell.memory as u64,
+                            unit: String::from("MiB"),
+                        })
+                        .collect(),
+                }),
+            },
+            None => Cpu {
+                mode: String::from("host-model"),
+                check: Some(String::from("partial")),
+                topology: None,
+                numa: None,
+            },
+        };
+
+        let virtio = Some(Model {
+            kind: Some(String::from("virtio")),
+            name: None,
+        });
+        let bridge = Interface {
+            kind: String::from("bridge"),
+            source: Source {
+                bridge: Some(String::from(BRIDGE)),
+                ..Default::default()
+            },
+            model: virtio.clone(),
+            driver: None,
+            alias: Some(Alias {
+                name: String::from("ua-net-1"),
+            }),
+        };
+
+        // if static ip configured, then only using bridge network
+        let interfaces = match &cz.resource.static_net {
+            Some(_) => vec![bridge],
+            None => vec![
+                Interface {
+                    kind: String::from("network"),
+                    source: Source {
+                        network: Some(String::from("default")),
+                        ..Default::default()
+                    },
+                    model: virtio.clone(),
+                    driver: Some(Driver {
+                        iommu: Some(String::from("off")),
+                        ..Default::default()
+                    }),
+                    alias: Some(Alias {
+                        name: String::from("ua-net-0"),
+                    }),
+                },
+                bridge,
+            ],
+        };
+
+        let perf = (!observability.perf_events.is_empty()).then(|| Perf {
+            events: observability
+                .perf_events
+                .iter()
+                .map(|event| PerfEvent {
+                    name: event.to_string(),
+                    enabled: String::from("yes"),
+                })
+                .collect(),
+        });
+
+        let devices = Devices {
+            emulator: Some(String::from(EMULATOR)),
+            disks: vec![Disk {
+                kind: String::from("file"),
+                device: String::from("disk"),
+                driver: Some(Driver {
+                    name: Some(String::from("qemu")),
+                    kind: Some(String::from("qcow2")),
+                    iommu: None,
+                }),
+                source: Source {
+                    file: Some(cz.os.rootfs.clone()),
+                    ..Default::default()
+                },
+                target: Some(Target {
+                    dev: Some(String::from(ROOTFS_DEV)),
+                    bus: Some(String::from("virtio")),
+                    ..Default::default()
+                }),
+                alias: Some(Alias {
+                    name: String::from("ua-box-volume-0"),
+                }),
+            }],
+            interfaces,
+            serial: Some(Serial {
+                kind: String::from("pty"),
+                target: Some(Target {
+                    kind: Some(String::from("isa-serial")),
+                    port: Some(0),
+                    model: Some(Model {
+                        kind: None,
+                        name: Some(String::from("isa-serial")),
+                    }),
+                    ..Default::default()
+                }),
+            }),
+            console: Some(Console {
+                kind: String::from("pty"),
+                target: Some(Target {
+                    kind: Some(String::from("serial")),
+                    port: Some(0),
+                    ..Default::default()
+                }),
+            }),
+            inputs: vec![Input {
+                kind: String::from("mouse"),
+                bus: Some(String::from("ps2")),
+            }],
+            memballoon: Some(MemBalloon {
+                model: String::from("virtio"),
+                stats: (observability.stats_period > 0).then_some(Stats {
+                    period: observability.stats_period,
+                }),
+            }),
+            filesystems: vec![Filesystem {
+                kind: String::from("mount"),
+                accessmode: Some(String::from("mapped")),
+                source: Source {
+                    dir: Some(cz.meta.share_folder.clone()),
+                    ..Default::default()
+                },
+                target: Target {
+                    dir: Some(String::from(SHARE_TAG)),
+                    ..Default::default()
+                },
+            }],
+            vsock: cz.vsock_cid().map(|cid| Vsock {
+                model: String::from("virtio"),
+                cid: Cid {
+                    auto: String::from("no"),
+                    address: cid,
+                },
+            }),
+        };
+
+        DomainDef {
+            kind: String::from("kvm"),
+            name: cz.meta.name.clone(),
+            // MiB as `-m` of qemu, 'MB' of libvirt is 10^6 bytes
+            memory: Memory {
+                unit: String::from("MiB"),
+                value: cz.resource.memory as u64,
+            },
+            current_memory: None,
+            memory_backing,
+            vcpu: Vcpu {
+                placement: Some(String::from("static")),
+                current: None,
+                value: cz.resource.cpus.len() as u32,
+            },
+            cputune: Some(cputune),
+            numatune,
+            os: Os {
+                kind: OsType {
+                    arch: Some(String::from("x86_64")),
+                    machine: None,
+                    value: String::from("hvm"),
+                },
+                kernel: Some(cz.os.kernel.clone()),
+                initrd: cz.os.initram_fs.clone(),
+                cmdline: Some(cz.os.kcmdline.clone()),
+                boot: Some(Boot {
+                    dev: String::from("hd"),
+                }),
+                bootmenu: Some(BootMenu {
+                    enable: String::from("no"),
+                }),
+            },
+            features: Features {
+                acpi: Some(Flag {}),
+                apic: Some(Flag {}),
+                pae: Some(Flag {}),
+            },
+            cpu: Some(cpu),
+            clock: Some(Clock {
+                offset: String::from("utc"),
+            }),
+            on_poweroff: Some(String::from("destroy")),
+            on_reboot: Some(String::from("restart")),
+            on_crash: Some(String::from("destroy")),
+            perf,
+            devices,
+        }
+    }
+
+    /// serialize without indent, text of elements with attributes
+    /// would be padded by whitespace otherwise
+    pub fn to_xml(&self) -> anyhow::Result<String> {
+        Ok(quick_xml::se::to_string(self)?)
+    }
+
+    /// parse domain xml, e.g. from `XMLDesc`
+    pub fn from_xml(xml: &str) -> anyhow::Result<Self> {
+        Ok(quick_xml::de::from_str(xml)?)
+    }
+
+    /// fields of live domain that differ from this one,
+    /// only fields derived from control zone config are compared
+    pub fn diff(&self, live: &DomainDef) -> anyhow::Result<Vec<Diff>> {
+        let mut diffs = vec![];
+        let mut check = |field: &str, config: String, runtime: String| {
+            if config != runtime {
+                diffs.push(Diff {
+                    field: field.to_owned(),
+                    config,
+                    runtime,
+                })
+            }
+        };
+
+        check("name", self.name.clone(), live.name.clone());
+
+        let memory = |def: &DomainDef| {
+            def.current_memory
+                .as_ref()
+                .unwrap_or(&def.memory)
+                .to_kib()
+                .map(|kib| format!("{kib}KiB"))
+        };
+        check("memory", memory(self)?, memory(live)?);
+
+        let vcpus = |def: &DomainDef| def.vcpu.current.unwrap_or(def.vcpu.value).to_string();
+        check("vcpus", vcpus(self), vcpus(live));
+
+        let topology = |def: &DomainDef| {
+            def.cpu
+                .as_ref()
+                .and_then(|cpu| cpu.topology.as_ref())
+                .map(|topology| {
+                    format!(
+                        "{}/{}/{}",
+                        topology.sockets, topology.cores, topology.threads
+                    )
+                })
+                .unwrap_or_default()
+        };
+        // libvirt may fill in a topology of its own if none configured
+        if self.cpu.as_ref().is_some_and(|cpu| cpu.topology.is_some()) {
+            check("topology", topology(self), topology(live));
+        }
+
+        let pins = |def: &DomainDef| {
+            def.cputune
+                .as_ref()
+                .map(|cputune| {
+                    cputune
+                        .vcpupins
+                        .iter()
+                        .map(|pin| format!("{}:{}", pin.vcpu, pin.cpuset))
+                        .collect::<Vec<String>>()
+                        .join(",")
+                })
+                .unwrap_or_default()
+        };
+        check("vcpupin", pins(self), pins(live));
+
+        // libvirt folds cpuset into ranges
+        let emulatorpin = |def: &DomainDef| {
+            def.cputune
+                .as_ref()
+                .and_then(|cputune| cputune.emulatorpin.as_ref())
+                .map(|pin| {
+                    parse_cpuset(&pin.cpuset)
+                        .unwrap_or_default()
+                        .iter()
+                        .map(|cpu| cpu.to_string())
+                        .collect::<Vec<String>>()
+                        .join(",")
+                })
+                .unwrap_or_default()
+        };
+        check("emulatorpin", emulatorpin(self), emulatorpin(live));
+
+        let numa_node = |def: &DomainDef| {
+            def.numatune
+                .as_ref()
+                .map(|numatune| numatune.memory.nodeset.clone())
+                .unwrap_or_default()
+        };
+        check("numa_node", numa_node(self), numa_node(live));
+
+        let opt = |s: &Option<String>| s.clone().unwrap_or_default();
+        check("kernel", opt(&self.os.kernel), opt(&live.os.kernel));
+        check("initrd", opt(&self.os.initrd), opt(&live.os.initrd));
+        check("cmdline", opt(&self.os.cmdline), opt(&live.os.cmdline));
+
+        let rootfs = |def: &DomainDef| {
+            def.devices
+                .disks
+                .first()
+                .and_then(|disk| disk.source.file.clone())
+                .unwrap_or_default()
+        };
+        check("rootfs", rootfs(self), rootfs(live));
+
+        let share_folder = |def: &DomainDef| {
+            def.devices
+                .filesystems
+                .iter()
+                .find(|fs| fs.target.dir.as_deref() == Some(SHARE_TAG))
+                .and_then(|fs| fs.source.dir.clone())
+                .unwrap_or_default()
+        };
+        check("share_folder", share_folder(self), share_folder(live));
+
+        let cid = |def: &DomainDef| {
+            def.devices
+                .vsock
+                .as_ref()
+                .map(|vsock| vsock.cid.address.to_string())
+                .unwrap_or_default()
+        };
+        check("vsock_cid", cid(self), cid(live));
+        Ok(diffs)
+    }
+}
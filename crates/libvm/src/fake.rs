@@ -0,0 +1,214 @@
+//! Fake vruntime without KVM, the guest is simulated by running czdaemon
+//! in process against the share folder, so that lifecycle of control zone
+//! could be tested end to end.
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+use anyhow::{anyhow, bail, Ok};
+use czdaemon::Daemon;
+use libcz::{
+    channel::Addr, resource::Resource, state::State, vruntime::VRuntime, ControlZone, UpdateMode,
+};
+use log::{debug, error, warn};
+
+/// container runtime of fake guest, pod events always succeed
+const FAKE_CRUNTIME: &str = "true";
+const FAKE_CHANNEL_SOCK: &str = "channel.sock";
+
+/// a booted fake guest
+struct Domain {
+    want_to_stop: Arc<AtomicBool>,
+    daemon: JoinHandle<()>,
+    paused: bool,
+    /// vcpus and memory at boot, which could not grow live
+    vcpus: usize,
+    memory: u32,
+    snapshots: Vec<String>,
+}
+
+impl Domain {
+    fn is_alive(&self) -> bool {
+        !self.daemon.is_finished()
+    }
+}
+
+/// domains are kept in memory of current process, keyed by workdir,
+/// so a fake guest lives no longer than the process started it
+static DOMAINS: Mutex<BTreeMap<String, Domain>> = Mutex::new(BTreeMap::new());
+
+pub struct Fake {}
+
+impl Fake {
+    fn with_domain<F, R>(&self, cz: &ControlZone, f: F) -> anyhow::Result<R>
+    where
+        F: FnOnce(&mut Domain) -> anyhow::Result<R>,
+    {
+        let mut domains = DOMAINS.lock().map_err(|e| anyhow!("{e}"))?;
+        match domains.get_mut(&cz.meta.workdir) {
+            Some(domain) if domain.is_alive() => f(domain),
+            _ => bail!("fake domain of {} not running", cz.meta.name),
+        }
+    }
+}
+
+impl VRuntime for Fake {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    fn start(&self, cz: &mut ControlZone) -> anyhow::Result<()> {
+        let mut domains = DOMAINS.lock().map_err(|e| anyhow!("{e}"))?;
+        if domains
+            .get(&cz.meta.workdir)
+            .is_some_and(|domain| domain.is_alive())
+        {
+            bail!("fake domain of {} already running", cz.meta.name)
+        }
+
+        // guest is local, serve control channel over unix socket
+        let listen = Addr::Unix(PathBuf::from(&cz.meta.workdir).join(FAKE_CHANNEL_SOCK));
+        fs::write(cz.channel_file(), listen.to_string())?;
+
+        let daemon = Daemon {
+            share_root: PathBuf::from(&cz.meta.share_folder),
+            cruntime: FAKE_CRUNTIME.to_owned(),
+            listen: Some(listen),
+            poweroff: None,
+        };
+        let want_to_stop = Arc::new(AtomicBool::new(false));
+        let flag = want_to_stop.clone();
+        let name = cz.meta.name.clone();
+        let daemon = thread::spawn(move || {
+            if let Err(e) = daemon.run(flag) {
+                error!("fake czdaemon of {name} exited: {e}");
+            }
+        });
+        debug!("fake domain of {} started", cz.meta.name);
+
+        domains.insert(
+            cz.meta.workdir.clone(),
+            Domain {
+                want_to_stop,
+                daemon,
+                paused: false,
+                vcpus: cz.resource.cpus.len(),
+                memory: cz.resource.memory,
+                snapshots: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    fn stop(&self, cz: &mut ControlZone) -> anyhow::Result<()> {
+        let domain = DOMAINS
+            .lock()
+            .map_err(|e| anyhow!("{e}"))?
+            .remove(&cz.meta.workdir);
+        let Some(domain) = domain else {
+            debug!("fake domain of {} already exited", cz.meta.name);
+            return Ok(());
+        };
+
+        domain.want_to_stop.store(true, Ordering::SeqCst);
+        if domain.daemon.join().is_err() {
+            bail!("fake czdaemon of {} panicked", cz.meta.name)
+        }
+
+        // nobody listens any more
+        let sock = PathBuf::from(&cz.meta.workdir).join(FAKE_CHANNEL_SOCK);
+        if sock.exists() {
+            fs::remove_file(sock)?;
+        }
+        Ok(())
+    }
+
+    fn shutdown(&self, cz: &ControlZone) -> anyhow::Result<()> {
+        self.with_domain(cz, |domain| {
+            domain.want_to_stop.store(true, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+
+    /// fake guest has no threads of its own
+    fn tasks(&self, cz: &ControlZone) -> anyhow::Result<Vec<u32>> {
+        self.with_domain(cz, |_| Ok(vec![]))
+    }
+
+    fn status(&self, cz: &ControlZone) -> anyhow::Result<State> {
+        let domains = DOMAINS.lock().map_err(|e| anyhow!("{e}"))?;
+        Ok(match domains.get(&cz.meta.workdir) {
+            Some(domain) if domain.is_alive() && domain.paused => State::Paused,
+            Some(domain) if domain.is_alive() => State::Running,
+            _ => State::Stopped,
+        })
+    }
+
+    fn pause(&self, cz: &ControlZone) -> anyhow::Result<()> {
+        self.with_domain(cz, |domain| {
+            domain.paused = true;
+            Ok(())
+        })
+    }
+
+    fn resume(&self, cz: &ControlZone) -> anyhow::Result<()> {
+        self.with_domain(cz, |domain| {
+            domain.paused = false;
+            Ok(())
+        })
+    }
+
+    fn snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        self.with_domain(cz, |domain| {
+            if domain.snapshots.iter().any(|s| s == name) {
+                bail!("snapshot {name} already exists")
+            }
+            domain.snapshots.push(name.to_owned());
+            Ok(())
+        })
+    }
+
+    fn snapshot_disk(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        self.snapshot(cz, name)
+    }
+
+    fn revert_snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        self.with_domain(cz, |domain| {
+            if !domain.snapshots.iter().any(|s| s == name) {
+                bail!("snapshot {name} not found")
+            }
+            Ok(())
+        })
+    }
+
+    fn delete_snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        self.with_domain(cz, |domain| {
+            let Some(i) = domain.snapshots.iter().position(|s| s == name) else {
+                bail!("snapshot {name} not found")
+            };
+            domain.snapshots.remove(i);
+            Ok(())
+        })
+    }
+
+    fn update(&self, cz: &ControlZone, new_resource: &Resource) -> anyhow::Result<UpdateMode> {
+        self.with_domain(cz, |domain| {
+            // behave like real hypervisors, resource could only shrink below boot values
+            if new_resource.cpus.len() > domain.vcpus || new_resource.memory > domain.memory {
+                warn!(
+                    "resource of {} grows past boot maximum, fallback to reboot",
+                    cz.meta.name
+                );
+                return Ok(UpdateMode::Reboot);
+            }
+            Ok(UpdateMode::Hot)
+        })
+    }
+}
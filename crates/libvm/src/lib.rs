@@ -1,12 +1,22 @@
+use std::path::Path;
+
+use cgroup::{ZoneCgroup, CGROUP_ROOT};
+#[cfg(feature = "fake")]
+use fake::Fake;
 use libcz::vruntime::DVRuntime;
-use libvirt::Libvirt;
 
+pub mod cgroup;
+pub mod domain;
+#[cfg(feature = "fake")]
+mod fake;
 mod libvirt;
 mod qemu;
+pub mod qmp;
+pub mod stats;
 #[cfg(test)]
 mod test;
 
-pub use libvirt::cz_to_xml;
+pub use libvirt::{cz_to_xml, live_domain, Libvirt};
 use qemu::Qemu;
 
 pub fn new_libvirt_vruntime(url: &str) -> DVRuntime {
@@ -16,3 +26,14 @@ pub fn new_libvirt_vruntime(url: &str) -> DVRuntime {
 pub fn new_qemu_vruntime() -> DVRuntime {
     Box::new(Qemu {})
 }
+
+/// cgroup of a zone started by qemu vruntime
+pub fn zone_cgroup(name: &str) -> ZoneCgroup {
+    ZoneCgroup::new(Path::new(CGROUP_ROOT), name)
+}
+
+/// vruntime simulating guest in process, for testing without KVM
+#[cfg(feature = "fake")]
+pub fn new_fake_vruntime() -> DVRuntime {
+    Box::new(Fake {})
+}
@@ -1,11 +1,25 @@
 //! An abstraction on top of the libvirt bindings.
+use std::{fs, path::PathBuf};
+
 use anyhow::{anyhow, bail, Ok};
-use libcz::{vruntime::VRuntime, ControlZone};
-use log::debug;
-use std::fmt::Write;
-use virt::{connect::Connect, domain::Domain, sys::VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_LEASE};
+use libcz::{resource::Resource, state::State, vruntime::VRuntime, ControlZone, UpdateMode};
+
+use crate::domain::{DomainDef, ROOTFS_DEV};
+use libutil::process::tasks_of;
+use log::{debug, warn};
+use virt::{
+    connect::Connect,
+    domain::Domain,
+    domain_snapshot::DomainSnapshot,
+    error::ErrorNumber,
+    sys::{
+        VIR_DOMAIN_AFFECT_LIVE, VIR_DOMAIN_CRASHED, VIR_DOMAIN_INTERFACE_ADDRESSES_SRC_LEASE,
+        VIR_DOMAIN_MEM_LIVE, VIR_DOMAIN_PAUSED, VIR_DOMAIN_PMSUSPENDED, VIR_DOMAIN_SHUTOFF,
+        VIR_DOMAIN_SNAPSHOT_REVERT_RUNNING, VIR_DOMAIN_VCPU_LIVE,
+    },
+};
 
-const DEFUAL_OBSERVE: bool = true;
+const LIBVIRT_PID_DIR: &str = "/run/libvirt/qemu";
 
 /// Errors from this module.
 #[derive(Debug, thiserror::Error)]
@@ -30,159 +44,27 @@ impl Libvirt {
         let conn = Connect::open(url).map_err(VirtError::Connect)?;
         Ok(Self { conn })
     }
-}
 
-pub fn cz_to_xml(cz: &ControlZone, observity: bool) -> anyhow::Result<String> {
-    let mut buf = String::from("<domain type='kvm'>\n");
-
-    // Init name
-    writeln!(&mut buf, "<name>{}</name>", cz.meta.name)?;
-
-    // Init memory
-    writeln!(
-        &mut buf,
-        "<memory unit='MB'>{}</memory>",
-        cz.resource.memory
-    )?;
-
-    // Init static CPU
-    writeln!(
-        &mut buf,
-        "<vcpu placement='static'>{}</vcpu>",
-        cz.resource.cpus.len()
-    )?;
-    writeln!(&mut buf, "<cputune>")?;
-    for (i, cpu) in cz.resource.cpus.iter().enumerate() {
-        writeln!(&mut buf, "<vcpupin vcpu='{}' cpuset='{}'/>", i, cpu)?;
-    }
-    writeln!(&mut buf, "</cputune>")?;
-
-    // Init Rootfs
-    let rootfs = format!(
-        "\
-        <disk type='file' device='disk'>\n\
-        <driver name='qemu' type='qcow2'/>\n\
-        <source file='{}'/>\n\
-        #<target dev='vda' bus='virtio'/>\n\
-        <alias name='ua-box-volume-0'/>\n\
-        <address type='pci' domain='0x0000' bus='0x00' slot='0x02' function='0x0'/>\n\
-        </disk>",
-        cz.os.rootfs
-    );
-
-    // Init Network
-    // if static ip configured, then only using bridge network
-    let network = match &cz.resource.static_net {
-        Some(_) => String::from(
-            "\
-            <interface type='bridge'>\n\
-            <source bridge='br0'/>\n\
-            <model type='virtio'/>\n\
-            <alias name='ua-net-1'/>\n\
-            <address type='pci' domain='0x0000' bus='0x00' slot='0x05' function='0x0'/>\n\
-            </interface>",
-        ),
-        None => format!(
-            "\
-                <interface type='network'>\n\
-                <domain name='{}'/>\n\
-                <source network='default'/>\n\
-                <model type='virtio'/>\n\
-                <driver iommu='off'/>\n\
-                <alias name='ua-net-0'/>\n\
-                <address type='pci' domain='0x0000' bus='0x00' slot='0x04' function='0x0'/>\n\
-                </interface>\n\
-                <interface type='bridge'>\n\
-                <source bridge='br0'/>\n\
-                <model type='virtio'/>\n\
-                <alias name='ua-net-1'/>\n\
-                <address type='pci' domain='0x0000' bus='0x00' slot='0x05' function='0x0'/>\n\
-                </interface>",
-            cz.meta.name
-        ),
-    };
+    /// lookup domain of control zone, none if not exists
+    pub(crate) fn lookup(&self, name: &str) -> anyhow::Result<Option<Domain>> {
+        match Domain::lookup_by_name(&self.conn, name) {
+            Result::Ok(domain) => Ok(Some(domain)),
+            Err(e) if e.code() == ErrorNumber::NoDomain => Ok(None),
+            Err(e) => bail!("lookup domain {name} failed: {e}"),
+        }
+    }
+}
 
-    // Init Sharefolder
-    let sharefolder = format!(
-        "\
-        <filesystem type='mount' accessmode='mapped'>\n\
-        <source dir='{}'/>\n\
-        <target dir='hostshare'/>\n\
-        <address type='pci' domain='0x0000' bus='0x00' slot='0x06' function='0x0'/>\n\
-        </filesystem>",
-        cz.meta.share_folder
-    );
-
-    // Init OS
-    writeln!(
-        &mut buf,
-        "<os>\n<type arch='x86_64' machine='pc-i440fx-jammy'>hvm</type>"
-    )?;
-    writeln!(&mut buf, "<kernel>{}</kernel>", cz.os.kernel)?;
-    if let Some(initrd) = &cz.os.initram_fs {
-        writeln!(&mut buf, "<initrd>{}</initrd>", initrd)?;
-    }
-    writeln!(&mut buf, "<cmdline>{}</cmdline>", cz.os.kcmdline)?;
-
-    // Init Perf
-    let perf = if observity {
-        format!(
-            "\n\
-        <perf>\n\
-        <event name='cpu_cycles' enabled='yes'/>\n\
-        <event name='instructions' enabled='yes'/>\n\
-        <event name='cache_misses' enabled='yes'/>\n\
-        <event name='branch_instructions' enabled='yes'/>\n\
-        <event name='branch_misses' enabled='yes'/>\n\
-        <event name='context_switches' enabled='yes'/>\n\
-        </perf>"
-        )
-    } else {
-        String::from("")
-    };
+pub fn cz_to_xml(cz: &ControlZone) -> anyhow::Result<String> {
+    DomainDef::from_cz(cz).to_xml()
+}
 
-    let mem_refresh = if observity {
-        format!("\n<stats period='4'/>")
-    } else {
-        String::from("")
+/// definition of running domain of control zone, none if not running
+pub fn live_domain(url: &str, name: &str) -> anyhow::Result<Option<DomainDef>> {
+    let Some(domain) = Libvirt::new(url)?.lookup(name)? else {
+        return Ok(None);
     };
-
-    write!(
-        &mut buf,
-        "<boot dev='hd'/>
-<bootmenu enable='no'/>
-</os>
-<features>
-<acpi/>
-<apic/>
-<pae/>
-</features>
-<cpu mode='host-model' check='partial'/>
-<clock offset='utc'/>
-<on_poweroff>destroy</on_poweroff>
-<on_reboot>restart</on_reboot>
-<on_crash>destroy</on_crash>{perf}
-<devices>
-<emulator>/usr/bin/qemu-system-x86_64</emulator>
-{rootfs}
-{network}
-<serial type='pty'>
-<target type='isa-serial' port='0'>
-<model name='isa-serial'/>
-</target>
-</serial>
-<console type='pty'>
-<target type='serial' port='0'/>
-</console>
-<input type='mouse' bus='ps2'/>
-<memballoon model='virtio'>{mem_refresh}
-<address type='pci' domain='0x0000' bus='0x00' slot='0x03' function='0x0'/>
-</memballoon>
-{sharefolder}
-</devices>
-</domain>",
-    )?;
-    Ok(buf)
+    Ok(Some(DomainDef::from_xml(&domain.get_xml_desc(0)?)?))
 }
 
 fn first_ip(domain: &Domain) -> anyhow::Result<String> {
@@ -201,21 +83,222 @@ fn first_ip(domain: &Domain) -> anyhow::Result<String> {
     Ok(addr[0].addr.clone())
 }
 
+/// memory of resource is in MiB, while memory apis use KiB
+#[inline]
+fn mib_to_kib(mib: u32) -> u64 {
+    (mib as u64) << 10
+}
+
+/// snapshot of rootfs only, memory state of vm is not saved
+pub(crate) fn disk_snapshot_xml(name: &str) -> String {
+    format!(
+        "<domainsnapshot><name>{name}</name><memory snapshot='no'/>\
+         <disks><disk name='{ROOTFS_DEV}' snapshot='internal'/></disks></domainsnapshot>"
+    )
+}
+
+/// bitmap of host cpus for vcpu pinning
+fn cpumap_of(cpus: &[u32]) -> Vec<u8> {
+    let max = cpus.iter().max().copied().unwrap_or_default();
+    let mut cpumap = vec![0u8; max as usize / 8 + 1];
+    for cpu in cpus {
+        cpumap[*cpu as usize / 8] |= 1 << (cpu % 8);
+    }
+    cpumap
+}
+
 impl VRuntime for Libvirt {
+    fn name(&self) -> &'static str {
+        "libvirt"
+    }
+
     fn start(&self, cz: &mut ControlZone) -> anyhow::Result<()> {
-        let config = cz_to_xml(cz, DEFUAL_OBSERVE)?;
+        let config = cz_to_xml(cz)?;
         Domain::create_xml(&self.conn, &config, 0)?;
         Ok(())
     }
 
     fn stop(&self, cz: &mut ControlZone) -> anyhow::Result<()> {
-        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        let Some(domain) = self.lookup(&cz.meta.name)? else {
+            debug!("domain {} already exited", cz.meta.name);
+            return Ok(());
+        };
+
         if let Err(e) = domain.destroy() {
             bail!("destroy control zone failed: {e}")
         }
         Ok(())
     }
 
+    fn shutdown(&self, cz: &ControlZone) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        if let Err(e) = domain.shutdown() {
+            bail!("shutdown control zone failed: {e}")
+        }
+        Ok(())
+    }
+
+    fn tasks(&self, cz: &ControlZone) -> anyhow::Result<Vec<u32>> {
+        let pid_file = PathBuf::from(LIBVIRT_PID_DIR).join(format!("{}.pid", cz.meta.name));
+        let pid = fs::read_to_string(pid_file)?.trim().parse::<u32>()?;
+        tasks_of(pid)
+    }
+
+    fn status(&self, cz: &ControlZone) -> anyhow::Result<State> {
+        let Some(domain) = self.lookup(&cz.meta.name)? else {
+            return Ok(State::Stopped);
+        };
+
+        let (state, _) = domain.get_state()?;
+        Ok(match state {
+            VIR_DOMAIN_PAUSED | VIR_DOMAIN_PMSUSPENDED => State::Paused,
+            VIR_DOMAIN_SHUTOFF => State::Stopped,
+            VIR_DOMAIN_CRASHED => State::Error,
+            _ => State::Running,
+        })
+    }
+
+    fn pause(&self, cz: &ControlZone) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        if let Err(e) = domain.suspend() {
+            bail!("suspend control zone failed: {e}")
+        }
+        Ok(())
+    }
+
+    fn resume(&self, cz: &ControlZone) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        if let Err(e) = domain.resume() {
+            bail!("resume control zone failed: {e}")
+        }
+        Ok(())
+    }
+
+    fn snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        let xml = format!("<domainsnapshot><name>{name}</name></domainsnapshot>");
+        if let Err(e) = DomainSnapshot::create_xml(&domain, &xml, 0) {
+            bail!("snapshot control zone failed: {e}")
+        }
+        Ok(())
+    }
+
+    fn snapshot_disk(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        // internal snapshots of a running vm may be refused without memory
+        if let Err(e) = DomainSnapshot::create_xml(&domain, &disk_snapshot_xml(name), 0) {
+            bail!(
+                "snapshot rootfs of {} failed: {e}, stop control zone first",
+                cz.meta.name
+            )
+        }
+        Ok(())
+    }
+
+    fn revert_snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        let snapshot = match DomainSnapshot::lookup_by_name(&domain, name, 0) {
+            Result::Ok(snapshot) => snapshot,
+            Err(e) if e.code() == ErrorNumber::NoDomainSnapshot => {
+                bail!("snapshot {name} not taken by libvirt, stop control zone first")
+            }
+            Err(e) => bail!(e),
+        };
+        if let Err(e) = snapshot.revert(VIR_DOMAIN_SNAPSHOT_REVERT_RUNNING) {
+            bail!("revert control zone failed: {e}")
+        }
+        Ok(())
+    }
+
+    fn delete_snapshot(&self, cz: &ControlZone, name: &str) -> anyhow::Result<()> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        let snapshot = match DomainSnapshot::lookup_by_name(&domain, name, 0) {
+            Result::Ok(snapshot) => snapshot,
+            Err(e) if e.code() == ErrorNumber::NoDomainSnapshot => {
+                bail!("snapshot {name} not taken by libvirt, stop control zone first")
+            }
+            Err(e) => bail!(e),
+        };
+
+        if let Err(e) = snapshot.delete(0) {
+            bail!("delete snapshot failed: {e}")
+        }
+        Ok(())
+    }
+
+    fn update(&self, cz: &ControlZone, new_resource: &Resource) -> anyhow::Result<UpdateMode> {
+        let domain = Domain::lookup_by_name(&self.conn, &cz.meta.name)?;
+        let info = domain.get_info()?;
+
+        let max_vcpus = domain.get_max_vcpus()?;
+        let vcpus = new_resource.cpus.len() as u32;
+        if vcpus as u64 > max_vcpus {
+            warn!(
+                "vcpu count of {} grows past boot maximum ({} > {}), fallback to reboot",
+                cz.meta.name, vcpus, max_vcpus
+            );
+            return Ok(UpdateMode::Reboot);
+        }
+
+        let memory = mib_to_kib(new_resource.memory);
+        if memory > info.max_mem {
+            warn!(
+                "memory of {} grows past boot maximum ({}KiB > {}KiB), fallback to reboot",
+                cz.meta.name, memory, info.max_mem
+            );
+            return Ok(UpdateMode::Reboot);
+        }
+
+        if vcpus != info.nr_virt_cpu {
+            debug!(
+                "set vcpus of {}: {} -> {}",
+                cz.meta.name, info.nr_virt_cpu, vcpus
+            );
+            if let Err(e) = domain.set_vcpus_flags(vcpus, VIR_DOMAIN_VCPU_LIVE) {
+                warn!(
+                    "vcpu count of {} can not be changed live ({e}), fallback to reboot",
+                    cz.meta.name
+                );
+                return Ok(UpdateMode::Reboot);
+            }
+        }
+
+        for (vcpu, cpu) in new_resource.cpus.iter().enumerate() {
+            debug!("pin vcpu {} of {} to {}", vcpu, cz.meta.name, cpu);
+            if let Err(e) =
+                domain.pin_vcpu_flags(vcpu as u32, &cpumap_of(&[*cpu]), VIR_DOMAIN_AFFECT_LIVE)
+            {
+                bail!("pin vcpu {vcpu} to cpu {cpu} failed: {e}")
+            }
+        }
+
+        // emulator pin is kept until next start if removed
+        if !new_resource.emulator_cpus.is_empty() {
+            debug!(
+                "pin emulator of {} to {:?}",
+                cz.meta.name, new_resource.emulator_cpus
+            );
+            if let Err(e) = domain.pin_emulator(
+                &cpumap_of(&new_resource.emulator_cpus),
+                VIR_DOMAIN_AFFECT_LIVE,
+            ) {
+                bail!("pin emulator failed: {e}")
+            }
+        }
+
+        if memory != info.memory {
+            debug!(
+                "balloon memory of {}: {}KiB -> {}KiB",
+                cz.meta.name, info.memory, memory
+            );
+            if let Err(e) = domain.set_memory_flags(memory, VIR_DOMAIN_MEM_LIVE) {
+                bail!("set memory by balloon failed: {e}")
+            }
+        }
+
+        Ok(UpdateMode::Hot)
+    }
+
     fn addi_bar(&self) {
         println!("{:6}{:16}", "ID", "IP");
     }
@@ -1,12 +1,38 @@
-use std::{fs, path::PathBuf, process::Command};
+use std::{
+    fs,
+    path::PathBuf,
+    process::Command,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
 use anyhow::{bail, Ok};
-use libcz::vruntime::VRuntime;
-use log::debug;
+use libcz::{
+    memory::MemoryBacking, resource::Resource, state::State, vruntime::VRuntime, UpdateMode,
+};
+use libutil::process::tasks_of;
+use log::{debug, warn};
+use serde_json::json;
 
+use crate::{
+    domain::{BRIDGE, SHARE_TAG},
+    qmp::QmpClient,
+    zone_cgroup,
+};
+
+const PROC_FS: &str = "/proc";
 const QEMU_BIN: &str = "qemu-system-x86_64";
 const QEMU_KILLER: &str = "kill";
 const QEMU_PID_FILE: &str = "qpid";
+const QEMU_QMP_SOCK: &str = "qmp.sock";
+const QEMU_CONSOLE_LOG: &str = "console.log";
+const QEMU_BALLOON_ID: &str = "balloon";
+const QEMU_MEMORY_ID: &str = "mem";
+const QEMU_ROOTFS_ID: &str = "hd";
+/// how long to wait for qemu to exit or balloon to deflate
+const QEMU_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+/// milliseconds
+const QEMU_POLL_INTERVAL: u64 = 100;
 
 pub struct Qemu {}
 
@@ -18,62 +44,336 @@ fn pid_file(workdir: &str) -> Option<String> {
         .and_then(|os_str| Some(os_str.to_owned()))
 }
 
-impl VRuntime for Qemu {
-    fn start(&self, cz: &mut libcz::ControlZone) -> anyhow::Result<()> {
-        let Some(pid_file) = pid_file(&cz.meta.workdir) else {
-            bail!("error gen qemu pid file")
-        };
+/// check if qemu process in pid file exists
+fn pid_alive(workdir: &str) -> anyhow::Result<bool> {
+    let Some(pid_file) = pid_file(workdir) else {
+        bail!("error gen qemu pid file")
+    };
 
-        let mut cmd = Command::new(QEMU_BIN);
-        cmd.args([
+    let Result::Ok(pid_s) = fs::read_to_string(pid_file) else {
+        return Ok(false);
+    };
+    let pid = pid_s.trim().parse::<u32>()?;
+    Ok(PathBuf::from(PROC_FS).join(pid.to_string()).exists())
+}
+
+fn qemu_pid(workdir: &str) -> anyhow::Result<u32> {
+    let Some(pid_file) = pid_file(workdir) else {
+        bail!("error gen qemu pid file")
+    };
+    Ok(fs::read_to_string(pid_file)?.trim().parse::<u32>()?)
+}
+
+/// poll until `done` holds, return false if timeout
+fn poll_until(mut done: impl FnMut() -> anyhow::Result<bool>) -> anyhow::Result<bool> {
+    let deadline = Instant::now() + QEMU_WAIT_TIMEOUT;
+    while !done()? {
+        if Instant::now() >= deadline {
+            return Ok(false);
+        }
+        sleep(Duration::from_millis(QEMU_POLL_INTERVAL));
+    }
+    Ok(true)
+}
+
+/// wait until guest memory ballooned down to `memory` bytes
+fn wait_balloon(qmp: &mut QmpClient, memory: u64) -> anyhow::Result<bool> {
+    poll_until(|| Ok(qmp.query_balloon()?.actual <= memory))
+}
+
+#[inline]
+fn qmp_sock(workdir: &str) -> PathBuf {
+    PathBuf::from(workdir).join(QEMU_QMP_SOCK)
+}
+
+/// apply observability profile, qemu counts no perf events of its own
+fn observe(qmp: &mut QmpClient, cz: &libcz::ControlZone) -> anyhow::Result<()> {
+    let observability = &cz.observability;
+    if !observability.perf_events.is_empty() {
+        debug!(
+            "perf events of {} are only counted by libvirt",
+            cz.meta.name
+        );
+    }
+
+    // balloon stats are polled by qemu once an interval is set
+    if observability.stats_period > 0 {
+        qmp.qom_set(
+            &format!("/machine/peripheral/{QEMU_BALLOON_ID}"),
+            "guest-stats-polling-interval",
+            json!(observability.stats_period),
+        )?;
+    }
+    Ok(())
+}
+
+/// run a human monitor command, which reports error by output
+fn hmp(cz: &libcz::ControlZone, cmd: &str) -> anyhow::Result<()> {
+    let output = QmpClient::connect(qmp_sock(&cz.meta.workdir))?.human_monitor_command(cmd)?;
+    if !output.trim().is_empty() {
+        bail!("{cmd} failed: {}", output.trim())
+    }
+    Ok(())
+}
+
+/// move threads of qemu into cgroup of zone, left in cgroup of caller
+/// if cgroup v2 not available
+fn place_cgroup(qmp: &mut QmpClient, cz: &libcz::ControlZone) -> anyhow::Result<()> {
+    let cgroup = zone_cgroup(&cz.meta.name);
+    if !cgroup.available() {
+        warn!("cgroup v2 not available, skip cgroup of {}", cz.meta.name);
+        return Ok(());
+    }
+
+    let pid = qemu_pid(&cz.meta.workdir)?;
+    let vcpu_tids: Vec<u32> = qmp
+        .query_cpus_fast()?
+        .iter()
+        .map(|vcpu| vcpu.thread_id)
+        .collect();
+    cgroup.create(&cz.resource)?;
+    cgroup.place(pid, &vcpu_tids, &tasks_of(pid)?)
+}
+
+/// pin thread to host cpus
+fn set_affinity(tid: u32, cpus: &[u32]) -> anyhow::Result<()> {
+    let mut cpu_set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    for cpu in cpus {
+        unsafe { libc::CPU_SET(*cpu as usize, &mut cpu_set) };
+    }
+
+    let ret = unsafe {
+        libc::sched_setaffinity(
+            tid as libc::pid_t,
+            std::mem::size_of::<libc::cpu_set_t>(),
+            &cpu_set,
+        )
+    };
+    if ret != 0 {
+        bail!(
+            "set affinity of thread {tid} failed: {}",
+            std::io::Error::last_os_error()
+        )
+    }
+    Ok(())
+}
+
+/// pin each vcpu thread to the host cpu at the same index
+fn pin_vcpus(qmp: &mut QmpClient, cz: &libcz::ControlZone, cpus: &[u32]) -> anyhow::Result<()> {
+    let vcpus = qmp.query_cpus_fast()?;
+    if vcpus.len() != cpus.len() {
+        bail!("{} vcpus but {} cpus to pin", vcpus.len(), cpus.len())
+    }
+
+    for (vcpu, cpu) in vcpus.iter().zip(cpus.iter()) {
+        debug!(
+            "pin vcpu {} (thread {}) of {} to {}",
+            vcpu.cpu_index, vcpu.thread_id, cz.meta.name, cpu
+        );
+        set_affinity(vcpu.thread_id, &[*cpu])?;
+    }
+    Ok(())
+}
+
+/// pin threads of qemu other than vcpus to `cpus`, threads created later
+/// inherit affinity from main loop
+fn pin_emulator(qmp: &mut QmpClient, cz: &libcz::ControlZone, cpus: &[u32]) -> anyhow::Result<()> {
+    let pid = qemu_pid(&cz.meta.workdir)?;
+    let vcpus: Vec<u32> = qmp
+        .query_cpus_fast()?
+        .iter()
+        .map(|vcpu| vcpu.thread_id)
+        .collect();
+
+    for tid in tasks_of(pid)?
+        .into_iter()
+        .filter(|tid| !vcpus.contains(tid))
+    {
+        debug!(
+            "pin emulator thread {} of {} to {:?}",
+            tid, cz.meta.name, cpus
+        );
+        set_affinity(tid, cpus)?;
+    }
+    Ok(())
+}
+
+/// `-object` of guest memory of `size` MiB, only if hugepages or numa
+/// bound unless `required`
+fn memory_backend(
+    backing: Option<&MemoryBacking>,
+    id: &str,
+    size: u32,
+    required: bool,
+) -> Option<String> {
+    let bind = backing
+        .and_then(|backing| backing.numa_node)
+        .map(|node| format!(",host-nodes={node},policy=bind"))
+        .unwrap_or_default();
+    match backing.and_then(|backing| backing.hugepages) {
+        Some(pages) => Some(format!(
+            "memory-backend-file,id={id},size={size}M,mem-path={},share=on,prealloc=on{bind}",
+            pages.mount_path()
+        )),
+        None if required || !bind.is_empty() => {
+            Some(format!("memory-backend-ram,id={id},size={size}M{bind}"))
+        }
+        None => None,
+    }
+}
+
+/// command line of qemu, kept in line with `DomainDef::from_cz`
+pub(crate) fn qemu_args(cz: &libcz::ControlZone) -> anyhow::Result<Vec<String>> {
+    let workdir = PathBuf::from(&cz.meta.workdir);
+    let Some(pid_file) = pid_file(&cz.meta.workdir) else {
+        bail!("error gen qemu pid file")
+    };
+    let Some(qmp_sock) = qmp_sock(&cz.meta.workdir).to_str().map(|s| s.to_owned()) else {
+        bail!("error gen qemu qmp socket")
+    };
+    let Some(console_log) = workdir
+        .join(QEMU_CONSOLE_LOG)
+        .to_str()
+        .map(|s| s.to_owned())
+    else {
+        bail!("error gen qemu console log")
+    };
+
+    // no default devices, every device is given below
+    let mut args: Vec<String> = Vec::from(
+        [
+            "-nodefaults",
+            "-no-user-config",
             "-enable-kvm",
-            "-display",
-            "none",
             "-daemonize",
-            "-machine",
-            "pc",
-            "-cpu",
-            "host",
-        ]);
-        cmd.args(["-pidfile", &pid_file]);
-
-        // Resource
-        cmd.args(["-smp", &format!("{}", cz.resource.cpus.len())]);
-        cmd.args(["-m", &format!("{}", cz.resource.memory)]);
-
-        if cz.resource.static_net.is_none() {
-            bail!("qemu vruntime currently not support dynamic IP")
+        ]
+        .map(String::from),
+    );
+    let mut push = |arg: &str, value: String| {
+        args.push(arg.to_owned());
+        args.push(value);
+    };
+
+    let backing = cz.resource.memory_backing.as_ref();
+    let topology = cz.resource.guest_topology.as_ref();
+    let cells = topology
+        .map(|topology| topology.cells.as_slice())
+        .unwrap_or_default();
+    // numa cells take memory from a backend each, machine from a single one
+    let machine_memory = if cells.is_empty() {
+        let id = format!("{QEMU_MEMORY_ID}0");
+        memory_backend(backing, &id, cz.resource.memory, false)
+    } else {
+        None
+    };
+
+    push("-display", String::from("none"));
+    // ksm merging pages of zones brings noise to isolation
+    let mut machine = String::from("pc,accel=kvm,mem-merge=off");
+    if machine_memory.is_some() {
+        machine.push_str(&format!(",memory-backend={QEMU_MEMORY_ID}0"));
+    }
+    push("-machine", machine);
+    push("-cpu", String::from("host"));
+    push("-rtc", String::from("base=utc"));
+    push("-pidfile", pid_file);
+    push("-qmp", format!("unix:{},server=on,wait=off", qmp_sock));
+
+    //   Console
+    push("-chardev", format!("file,id=console,path={console_log}"));
+    push("-serial", String::from("chardev:console"));
+
+    // Resource
+    let mut smp = format!("{}", cz.resource.cpus.len());
+    if let Some(topology) = topology {
+        smp.push_str(&format!(
+            ",sockets={},cores={},threads={}",
+            topology.sockets, topology.cores, topology.threads
+        ));
+    }
+    push("-smp", smp);
+    push("-m", format!("{}", cz.resource.memory));
+    if let Some(machine_memory) = machine_memory {
+        push("-object", machine_memory);
+    }
+    for (id, cell) in cells.iter().enumerate() {
+        let memdev = format!("{QEMU_MEMORY_ID}{id}");
+        if let Some(memory_backend) = memory_backend(backing, &memdev, cell.memory, true) {
+            push("-object", memory_backend);
         }
-        cmd.args([
-            "-device",
-            "virtio-net-pci,netdev=net",
-            "-netdev",
-            "bridge,br=br0,id=net",
-        ]);
-
-        // Meta
-        cmd.args(["-name", &cz.meta.name]);
-        //   ShareFolder
-        cmd.args([
-            "-device",
-            "virtio-9p-pci,fsdev=shared-folder,mount_tag=hostshare",
-        ]);
-        cmd.args([
-            "-fsdev",
-            &format!(
-                "local,id=shared-folder,path={},security_model=mapped",
-                cz.meta.share_folder
-            ),
-        ]);
-
-        // OS
-        //   Rootfs
-        cmd.args(["-device", "virtio-blk-pci,drive=hd"]);
-        cmd.args(["-drive", &format!("file={},if=none,id=hd", cz.os.rootfs)]);
-        cmd.args(["-kernel", &cz.os.kernel]);
-
-        //  TODO: initramfs
-        cmd.args(["-append", &cz.os.kcmdline]);
+        let cpus: Vec<String> = cell
+            .cpus
+            .split(',')
+            .map(|cpus| format!(",cpus={cpus}"))
+            .collect();
+        push(
+            "-numa",
+            format!("node,nodeid={id}{},memdev={memdev}", cpus.concat()),
+        );
+    }
+    if backing.is_some_and(|backing| backing.locked) {
+        push("-overcommit", String::from("mem-lock=on"));
+    }
+    push(
+        "-device",
+        format!("virtio-balloon-pci,id={QEMU_BALLOON_ID}"),
+    );
+
+    //   Network, if static ip configured, then only using bridge network
+    if cz.resource.static_net.is_none() {
+        push("-netdev", String::from("user,id=net0"));
+        push("-device", String::from("virtio-net-pci,netdev=net0"));
+    }
+    push("-netdev", format!("bridge,br={BRIDGE},id=net1"));
+    push("-device", String::from("virtio-net-pci,netdev=net1"));
+
+    // Meta
+    push("-name", cz.meta.name.clone());
+    //   ShareFolder
+    push(
+        "-device",
+        format!("virtio-9p-pci,fsdev=shared-folder,mount_tag={SHARE_TAG}"),
+    );
+    push(
+        "-fsdev",
+        format!(
+            "local,id=shared-folder,path={},security_model=mapped",
+            cz.meta.share_folder
+        ),
+    );
+
+    //   Control Channel
+    if let Some(cid) = cz.vsock_cid() {
+        push("-device", format!("vhost-vsock-pci,guest-cid={}", cid));
+    }
+
+    // OS
+    //   Rootfs
+    push("-device", format!("virtio-blk-pci,drive={QEMU_ROOTFS_ID}"));
+    push(
+        "-drive",
+        format!(
+            "file={},format=qcow2,if=none,id={QEMU_ROOTFS_ID}",
+            cz.os.rootfs
+        ),
+    );
+    push("-kernel", cz.os.kernel.clone());
+    if let Some(initrd) = &cz.os.initram_fs {
+        push("-initrd", initrd.clone());
+    }
+    push("-append", cz.os.kcmdline.clone());
+
+    Ok(args)
+}
+
+impl VRuntime for Qemu {
+    fn name(&self) -> &'static str {
+        "qemu"
+    }
+
+    fn start(&self, cz: &mut libcz::ControlZone) -> anyhow::Result<()> {
+        let mut cmd = Command::new(QEMU_BIN);
+        cmd.args(qemu_args(cz)?);
 
         debug!("{:?}", cmd);
         let mut childp = match cmd.spawn() {
@@ -90,6 +390,26 @@ impl VRuntime for Qemu {
             Err(e) => bail!("could not wait for command: {e}"),
         };
 
+        // vcpu threads exist once qemu daemonized,
+        // cgroup cpuset goes first as affinity is confined by it
+        let pinned = QmpClient::connect(qmp_sock(&cz.meta.workdir)).and_then(|mut qmp| {
+            place_cgroup(&mut qmp, cz)?;
+            pin_vcpus(&mut qmp, cz, &cz.resource.cpus)?;
+            if !cz.resource.emulator_cpus.is_empty() {
+                pin_emulator(&mut qmp, cz, &cz.resource.emulator_cpus)?;
+            }
+            Ok(())
+        });
+        if let Err(e) = pinned {
+            self.stop(cz)?;
+            bail!("pin threads of {} failed: {e}", cz.meta.name)
+        }
+
+        if let Err(e) =
+            QmpClient::connect(qmp_sock(&cz.meta.workdir)).and_then(|mut qmp| observe(&mut qmp, cz))
+        {
+            warn!("apply observability of {} failed: {e}", cz.meta.name);
+        }
         Ok(())
     }
 
@@ -98,9 +418,19 @@ impl VRuntime for Qemu {
         let Some(pid_file) = pid_file(&cz.meta.workdir) else {
             bail!("error gen qemu pid file")
         };
+
+        if !pid_alive(&cz.meta.workdir)? {
+            debug!("qemu of {} already exited", cz.meta.name);
+            if PathBuf::from(&pid_file).exists() {
+                fs::remove_file(pid_file)?;
+            }
+            if let Err(e) = zone_cgroup(&cz.meta.name).remove() {
+                warn!("remove cgroup of {} failed: {e}", cz.meta.name);
+            }
+            return Ok(());
+        }
         let pid_s = fs::read_to_string(&pid_file)?;
 
-        // TODO: Stop More gently
         let mut cmd = Command::new(QEMU_KILLER);
         cmd.arg(&pid_s.trim());
 
@@ -118,8 +448,131 @@ impl VRuntime for Qemu {
             Err(e) => bail!("could not wait for command: {e}"),
         };
 
+        // cgroup is busy until qemu exited
+        let proc_dir = PathBuf::from(PROC_FS).join(pid_s.trim());
+        if !poll_until(|| Ok(!proc_dir.exists()))? {
+            warn!(
+                "qemu of {} not exited in {:?}, cgroup kept",
+                cz.meta.name, QEMU_WAIT_TIMEOUT
+            );
+        } else if let Err(e) = zone_cgroup(&cz.meta.name).remove() {
+            warn!("remove cgroup of {} failed: {e}", cz.meta.name);
+        }
+
         // remove old pid file
         fs::remove_file(pid_file)?;
         Ok(())
     }
+
+    fn shutdown(&self, cz: &libcz::ControlZone) -> anyhow::Result<()> {
+        QmpClient::connect(qmp_sock(&cz.meta.workdir))?.system_powerdown()
+    }
+
+    fn tasks(&self, cz: &libcz::ControlZone) -> anyhow::Result<Vec<u32>> {
+        tasks_of(qemu_pid(&cz.meta.workdir)?)
+    }
+
+    fn status(&self, cz: &libcz::ControlZone) -> anyhow::Result<State> {
+        if !pid_alive(&cz.meta.workdir)? {
+            return Ok(State::Stopped);
+        }
+
+        // qemu alive but monitor not ready
+        let Result::Ok(mut qmp) = QmpClient::connect(qmp_sock(&cz.meta.workdir)) else {
+            return Ok(State::Running);
+        };
+
+        Ok(match qmp.query_status()?.status.as_str() {
+            "paused" | "suspended" => State::Paused,
+            "internal-error" | "io-error" | "guest-panicked" => State::Error,
+            _ => State::Running,
+        })
+    }
+
+    fn pause(&self, cz: &libcz::ControlZone) -> anyhow::Result<()> {
+        QmpClient::connect(qmp_sock(&cz.meta.workdir))?.stop()
+    }
+
+    fn resume(&self, cz: &libcz::ControlZone) -> anyhow::Result<()> {
+        QmpClient::connect(qmp_sock(&cz.meta.workdir))?.cont()
+    }
+
+    fn snapshot(&self, cz: &libcz::ControlZone, name: &str) -> anyhow::Result<()> {
+        hmp(cz, &format!("savevm {name}"))
+    }
+
+    fn snapshot_disk(&self, cz: &libcz::ControlZone, name: &str) -> anyhow::Result<()> {
+        QmpClient::connect(qmp_sock(&cz.meta.workdir))?
+            .blockdev_snapshot_internal_sync(QEMU_ROOTFS_ID, name)
+    }
+
+    fn revert_snapshot(&self, cz: &libcz::ControlZone, name: &str) -> anyhow::Result<()> {
+        hmp(cz, &format!("loadvm {name}"))
+    }
+
+    fn delete_snapshot(&self, cz: &libcz::ControlZone, name: &str) -> anyhow::Result<()> {
+        hmp(cz, &format!("delvm {name}"))
+    }
+
+    fn update(
+        &self,
+        cz: &libcz::ControlZone,
+        new_resource: &Resource,
+    ) -> anyhow::Result<UpdateMode> {
+        let mut qmp = QmpClient::connect(qmp_sock(&cz.meta.workdir))?;
+
+        // vcpus are fixed by `-smp` at boot
+        let vcpus = qmp.query_cpus_fast()?;
+        if new_resource.cpus.len() != vcpus.len() {
+            warn!(
+                "vcpu count of {} can not be changed live ({} -> {}), fallback to reboot",
+                cz.meta.name,
+                vcpus.len(),
+                new_resource.cpus.len()
+            );
+            return Ok(UpdateMode::Reboot);
+        }
+
+        // `-m` is in MiB
+        let memory = new_resource.memory as u64 * 1024 * 1024;
+        let max_memory = qmp.query_memory_size_summary()?.base_memory;
+        if memory > max_memory {
+            warn!(
+                "memory of {} grows past boot maximum ({}B > {}B), fallback to reboot",
+                cz.meta.name, memory, max_memory
+            );
+            return Ok(UpdateMode::Reboot);
+        }
+
+        // memory limit is raised before balloon grows, but lowered only
+        // after guest gave memory back, or qemu is reclaimed or killed
+        let cgroup = Some(zone_cgroup(&cz.meta.name)).filter(|cgroup| cgroup.exists());
+        let shrink = memory < qmp.query_balloon()?.actual;
+        if let Some(cgroup) = &cgroup {
+            cgroup.apply_cpus(new_resource)?;
+            if !shrink {
+                cgroup.apply_memory(new_resource.memory)?;
+            }
+        }
+
+        pin_vcpus(&mut qmp, cz, &new_resource.cpus)?;
+        // emulator pin is kept until next start if removed
+        if !new_resource.emulator_cpus.is_empty() {
+            pin_emulator(&mut qmp, cz, &new_resource.emulator_cpus)?;
+        }
+
+        debug!("balloon memory of {} to {}B", cz.meta.name, memory);
+        qmp.balloon(memory)?;
+        if let (Some(cgroup), true) = (&cgroup, shrink) {
+            if wait_balloon(&mut qmp, memory)? {
+                cgroup.apply_memory(new_resource.memory)?;
+            } else {
+                warn!(
+                    "balloon of {} not deflated in {:?}, memory limit kept",
+                    cz.meta.name, QEMU_WAIT_TIMEOUT
+                );
+            }
+        }
+        Ok(UpdateMode::Hot)
+    }
 }
@@ -0,0 +1,203 @@
+//! A minimal typed client of QEMU Machine Protocol.
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixStream,
+    path::Path,
+    time::Duration,
+};
+
+use anyhow::Ok;
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+const QMP_TIMEOUT: u64 = 5;
+
+/// Errors from this module.
+#[derive(Debug, thiserror::Error)]
+pub enum QmpError {
+    /// Error returned by qemu for a command.
+    #[error("qmp command {cmd} failed: {class}: {desc}")]
+    Command {
+        cmd: String,
+        class: String,
+        desc: String,
+    },
+
+    /// Qemu closed the monitor connection.
+    #[error("qmp connection closed")]
+    Closed,
+
+    /// Unexpected message from qemu.
+    #[error("unexpected qmp message: {0}")]
+    Unexpected(String),
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct StatusInfo {
+    pub running: bool,
+    pub status: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct CpuInfoFast {
+    pub cpu_index: u32,
+    pub thread_id: u32,
+    pub qom_path: String,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct BalloonInfo {
+    /// actual memory of guest in bytes
+    pub actual: u64,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct MemorySizeSummary {
+    /// boot memory of guest in bytes
+    pub base_memory: u64,
+}
+
+pub struct QmpClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl QmpClient {
+    /// connect to qmp socket and negotiate capabilities
+    pub fn connect<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let stream = UnixStream::connect(&path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(QMP_TIMEOUT)))?;
+
+        let mut client = QmpClient {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        let greeting = client.read_message()?;
+        if greeting.get("QMP").is_none() {
+            return Err(QmpError::Unexpected(greeting.to_string()).into());
+        }
+
+        client.execute::<Value>("qmp_capabilities", None)?;
+        debug!("qmp connected: {:?}", path.as_ref());
+        Ok(client)
+    }
+
+    fn read_message(&mut self) -> anyhow::Result<Value> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(QmpError::Closed.into());
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// execute a qmp command and wait for its return, events are skipped
+    pub fn execute<R: DeserializeOwned>(
+        &mut self,
+        cmd: &str,
+        args: Option<Value>,
+    ) -> anyhow::Result<R> {
+        let mut request = json!({ "execute": cmd });
+        if let Some(args) = args {
+            request["arguments"] = args;
+        }
+        writeln!(self.writer, "{}", request)?;
+        debug!("qmp send: {}", request);
+
+        loop {
+            let mut msg = self.read_message()?;
+            if let Some(ret) = msg.get_mut("return") {
+                return Ok(serde_json::from_value(ret.take())?);
+            }
+
+            if let Some(err) = msg.get("error") {
+                return Err(QmpError::Command {
+                    cmd: cmd.to_owned(),
+                    class: err["class"].as_str().unwrap_or_default().to_owned(),
+                    desc: err["desc"].as_str().unwrap_or_default().to_owned(),
+                }
+                .into());
+            }
+
+            if msg.get("event").is_some() {
+                debug!("qmp event skipped: {}", msg);
+                continue;
+            }
+
+            return Err(QmpError::Unexpected(msg.to_string()).into());
+        }
+    }
+
+    pub fn query_status(&mut self) -> anyhow::Result<StatusInfo> {
+        self.execute("query-status", None)
+    }
+
+    /// ask guest to power down through ACPI
+    pub fn system_powerdown(&mut self) -> anyhow::Result<()> {
+        self.execute::<Value>("system_powerdown", None)?;
+        Ok(())
+    }
+
+    /// pause all vcpus
+    pub fn stop(&mut self) -> anyhow::Result<()> {
+        self.execute::<Value>("stop", None)?;
+        Ok(())
+    }
+
+    /// resume all vcpus
+    pub fn cont(&mut self) -> anyhow::Result<()> {
+        self.execute::<Value>("cont", None)?;
+        Ok(())
+    }
+
+    pub fn query_cpus_fast(&mut self) -> anyhow::Result<Vec<CpuInfoFast>> {
+        self.execute("query-cpus-fast", None)
+    }
+
+    pub fn query_memory_size_summary(&mut self) -> anyhow::Result<MemorySizeSummary> {
+        self.execute("query-memory-size-summary", None)
+    }
+
+    pub fn query_balloon(&mut self) -> anyhow::Result<BalloonInfo> {
+        self.execute("query-balloon", None)
+    }
+
+    /// execute a human monitor command, return its output
+    pub fn human_monitor_command(&mut self, cmd: &str) -> anyhow::Result<String> {
+        self.execute(
+            "human-monitor-command",
+            Some(json!({ "command-line": cmd })),
+        )
+    }
+
+    /// internal snapshot of a drive in use, without memory state
+    pub fn blockdev_snapshot_internal_sync(
+        &mut self,
+        device: &str,
+        name: &str,
+    ) -> anyhow::Result<()> {
+        self.execute::<Value>(
+            "blockdev-snapshot-internal-sync",
+            Some(json!({ "device": device, "name": name })),
+        )?;
+        Ok(())
+    }
+
+    /// set guest memory to `value` bytes through balloon
+    pub fn balloon(&mut self, value: u64) -> anyhow::Result<()> {
+        self.execute::<Value>("balloon", Some(json!({ "value": value })))?;
+        Ok(())
+    }
+
+    /// set property of a qom object, e.g. a device under `/machine/peripheral`
+    pub fn qom_set(&mut self, path: &str, property: &str, value: Value) -> anyhow::Result<()> {
+        self.execute::<Value>(
+            "qom-set",
+            Some(json!({ "path": path, "property": property, "value": value })),
+        )?;
+        Ok(())
+    }
+}
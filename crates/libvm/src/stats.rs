@@ -0,0 +1,98 @@
+//! Bulk statistics of libvirt domains, e.g. `perf.cpu_cycles`,
+//! `balloon.current` or `vcpu.0.time`, flattened into numbers.
+use std::{collections::BTreeMap, ffi::CStr, ptr};
+
+use anyhow::{bail, Ok};
+use virt::sys::{
+    virDomainListGetStats, virDomainPtr, virDomainStatsRecordListFree, virDomainStatsRecordPtr,
+    virTypedParameter, VIR_DOMAIN_STATS_BALLOON, VIR_DOMAIN_STATS_BLOCK,
+    VIR_DOMAIN_STATS_INTERFACE, VIR_DOMAIN_STATS_PERF, VIR_DOMAIN_STATS_STATE,
+    VIR_DOMAIN_STATS_VCPU, VIR_TYPED_PARAM_BOOLEAN, VIR_TYPED_PARAM_DOUBLE, VIR_TYPED_PARAM_INT,
+    VIR_TYPED_PARAM_LLONG, VIR_TYPED_PARAM_UINT, VIR_TYPED_PARAM_ULLONG,
+};
+
+use crate::libvirt::Libvirt;
+
+/// groups of statistics, named after the prefix of their fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatsGroup {
+    State,
+    Balloon,
+    Vcpu,
+    Net,
+    Block,
+    Perf,
+}
+
+impl StatsGroup {
+    fn flag(&self) -> u32 {
+        match self {
+            StatsGroup::State => VIR_DOMAIN_STATS_STATE,
+            StatsGroup::Balloon => VIR_DOMAIN_STATS_BALLOON,
+            StatsGroup::Vcpu => VIR_DOMAIN_STATS_VCPU,
+            StatsGroup::Net => VIR_DOMAIN_STATS_INTERFACE,
+            StatsGroup::Block => VIR_DOMAIN_STATS_BLOCK,
+            StatsGroup::Perf => VIR_DOMAIN_STATS_PERF,
+        }
+    }
+}
+
+/// statistics of a running domain keyed by field, none if domain not exists
+pub fn domain_stats(
+    libvirt: &Libvirt,
+    name: &str,
+    groups: &[StatsGroup],
+) -> anyhow::Result<Option<BTreeMap<String, f64>>> {
+    let Some(domain) = libvirt.lookup(name)? else {
+        return Ok(None);
+    };
+
+    let stats = groups.iter().fold(0, |stats, group| stats | group.flag());
+    let mut doms: [virDomainPtr; 2] = [domain.as_ptr(), ptr::null_mut()];
+    let mut records: *mut virDomainStatsRecordPtr = ptr::null_mut();
+    let n = unsafe { virDomainListGetStats(doms.as_mut_ptr(), stats, &mut records, 0) };
+    if n < 0 {
+        bail!("get stats of domain {name} failed")
+    }
+
+    let mut fields = BTreeMap::new();
+    unsafe {
+        for i in 0..n as usize {
+            let record = *records.add(i);
+            if record.is_null() {
+                break;
+            }
+            let params = (*record).params;
+            let nparams = (*record).nparams.max(0) as usize;
+            if !params.is_null() {
+                fields.extend(typed_params(std::slice::from_raw_parts(params, nparams)));
+            }
+        }
+        virDomainStatsRecordListFree(records);
+    }
+    Ok(Some(fields))
+}
+
+/// numeric typed parameters, strings like `block.0.name` are skipped
+pub(crate) fn typed_params(params: &[virTypedParameter]) -> BTreeMap<String, f64> {
+    params
+        .iter()
+        .filter_map(|param| {
+            let field = unsafe { CStr::from_ptr(param.field.as_ptr()) }
+                .to_string_lossy()
+                .into_owned();
+            let value = unsafe {
+                match param.type_ as u32 {
+                    VIR_TYPED_PARAM_INT => param.value.i as f64,
+                    VIR_TYPED_PARAM_UINT => param.value.ui as f64,
+                    VIR_TYPED_PARAM_LLONG => param.value.l as f64,
+                    VIR_TYPED_PARAM_ULLONG => param.value.ul as f64,
+                    VIR_TYPED_PARAM_DOUBLE => param.value.d,
+                    VIR_TYPED_PARAM_BOOLEAN => param.value.b as f64,
+                    _ => return None,
+                }
+            };
+            Some((field, value))
+        })
+        .collect()
+}
@@ -1,10 +1,37 @@
-use libcz::{czos::CZOS, meta::Meta, resource::Resource, state::State, ControlZone};
+use std::{
+    io::{BufRead, BufReader, Write},
+    os::unix::net::UnixListener,
+    thread,
+};
 
-use crate::libvirt::cz_to_xml;
+use libcz::{
+    czos::CZOS,
+    memory::{HugePageSize, MemoryBacking},
+    meta::Meta,
+    observability::Observability,
+    resource::Resource,
+    state::State,
+    topology::{NumaCell, Topology},
+    ControlZone,
+};
+use serde_json::Value;
+use virt::sys::{
+    virTypedParameter, VIR_TYPED_PARAM_DOUBLE, VIR_TYPED_PARAM_STRING, VIR_TYPED_PARAM_UINT,
+    VIR_TYPED_PARAM_ULLONG,
+};
+
+use crate::{
+    cgroup::ZoneCgroup,
+    domain::{DomainDef, EmulatorPin},
+    libvirt::{cz_to_xml, disk_snapshot_xml},
+    qemu::qemu_args,
+    qmp::{CpuInfoFast, QmpClient, StatusInfo},
+    stats::typed_params,
+};
 
 const TARGET_XML: &str = "<domain type='kvm'>
 <name>controlzone01</name>
-<memory unit='MB'>4096</memory>
+<memory unit='MiB'>4096</memory>
 <vcpu placement='static'>4</vcpu>
 <cputune>
 <vcpupin vcpu='0' cpuset='130'/>
@@ -13,7 +40,7 @@ const TARGET_XML: &str = "<domain type='kvm'>
 <vcpupin vcpu='3' cpuset='133'/>
 </cputune>
 <os>
-<type arch='x86_64' machine='pc-i440fx-jammy'>hvm</type>
+<type arch='x86_64'>hvm</type>
 <kernel>/tmp/control_zone/kernels/cfs-virt</kernel>
 <initrd>/tmp/control_zone/initramfs-virt</initrd>
 <cmdline>vmlinuz-virt initrd=initramfs-virt root=LABEL=root rootfstype=ext4 modules=kms,scsi,virtio console=ttyS0</cmdline>
@@ -35,23 +62,19 @@ const TARGET_XML: &str = "<domain type='kvm'>
 <disk type='file' device='disk'>
 <driver name='qemu' type='qcow2'/>
 <source file='/tmp/control_zone/images/alpine-uefi.qcow2'/>
-#<target dev='vda' bus='virtio'/>
+<target dev='vda' bus='virtio'/>
 <alias name='ua-box-volume-0'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x02' function='0x0'/>
 </disk>
 <interface type='network'>
-<domain name='controlzone01'/>
 <source network='default'/>
 <model type='virtio'/>
 <driver iommu='off'/>
 <alias name='ua-net-0'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x04' function='0x0'/>
 </interface>
 <interface type='bridge'>
 <source bridge='br0'/>
 <model type='virtio'/>
 <alias name='ua-net-1'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x05' function='0x0'/>
 </interface>
 <serial type='pty'>
 <target type='isa-serial' port='0'>
@@ -62,21 +85,17 @@ const TARGET_XML: &str = "<domain type='kvm'>
 <target type='serial' port='0'/>
 </console>
 <input type='mouse' bus='ps2'/>
-<memballoon model='virtio'>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x03' function='0x0'/>
-</memballoon>
+<memballoon model='virtio'/>
 <filesystem type='mount' accessmode='mapped'>
 <source dir='/tmp/control_zone/controlzone'/>
 <target dir='hostshare'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x06' function='0x0'/>
 </filesystem>
 </devices>
 </domain>";
 
-
 const TARGET_PERF_XML: &str = "<domain type='kvm'>
 <name>controlzone01</name>
-<memory unit='MB'>4096</memory>
+<memory unit='MiB'>4096</memory>
 <vcpu placement='static'>4</vcpu>
 <cputune>
 <vcpupin vcpu='0' cpuset='130'/>
@@ -85,7 +104,7 @@ const TARGET_PERF_XML: &str = "<domain type='kvm'>
 <vcpupin vcpu='3' cpuset='133'/>
 </cputune>
 <os>
-<type arch='x86_64' machine='pc-i440fx-jammy'>hvm</type>
+<type arch='x86_64'>hvm</type>
 <kernel>/tmp/control_zone/kernels/cfs-virt</kernel>
 <initrd>/tmp/control_zone/initramfs-virt</initrd>
 <cmdline>vmlinuz-virt initrd=initramfs-virt root=LABEL=root rootfstype=ext4 modules=kms,scsi,virtio console=ttyS0</cmdline>
@@ -105,6 +124,7 @@ const TARGET_PERF_XML: &str = "<domain type='kvm'>
 <perf>
 <event name='cpu_cycles' enabled='yes'/>
 <event name='instructions' enabled='yes'/>
+<event name='cache_references' enabled='yes'/>
 <event name='cache_misses' enabled='yes'/>
 <event name='branch_instructions' enabled='yes'/>
 <event name='branch_misses' enabled='yes'/>
@@ -115,23 +135,19 @@ const TARGET_PERF_XML: &str = "<domain type='kvm'>
 <disk type='file' device='disk'>
 <driver name='qemu' type='qcow2'/>
 <source file='/tmp/control_zone/images/alpine-uefi.qcow2'/>
-#<target dev='vda' bus='virtio'/>
+<target dev='vda' bus='virtio'/>
 <alias name='ua-box-volume-0'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x02' function='0x0'/>
 </disk>
 <interface type='network'>
-<domain name='controlzone01'/>
 <source network='default'/>
 <model type='virtio'/>
 <driver iommu='off'/>
 <alias name='ua-net-0'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x04' function='0x0'/>
 </interface>
 <interface type='bridge'>
 <source bridge='br0'/>
 <model type='virtio'/>
 <alias name='ua-net-1'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x05' function='0x0'/>
 </interface>
 <serial type='pty'>
 <target type='isa-serial' port='0'>
@@ -144,50 +160,592 @@ const TARGET_PERF_XML: &str = "<domain type='kvm'>
 <input type='mouse' bus='ps2'/>
 <memballoon model='virtio'>
 <stats period='4'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x03' function='0x0'/>
 </memballoon>
 <filesystem type='mount' accessmode='mapped'>
 <source dir='/tmp/control_zone/controlzone'/>
 <target dir='hostshare'/>
-<address type='pci' domain='0x0000' bus='0x00' slot='0x06' function='0x0'/>
 </filesystem>
 </devices>
 </domain>";
 
-#[test]
-fn test_to_xml() {
-    let controlzone = ControlZone {
-        meta: Meta{
+/// `XMLDesc` of a running domain, with 2 vcpus unplugged and memory ballooned
+const LIVE_XML: &str = "<domain type='kvm' id='7'>
+  <name>controlzone01</name>
+  <uuid>4f6bb1b4-0c1e-4d1a-9d8e-2f0e4e3c8a11</uuid>
+  <memory unit='KiB'>4000000</memory>
+  <currentMemory unit='KiB'>2000000</currentMemory>
+  <vcpu placement='static' current='2'>4</vcpu>
+  <cputune>
+    <vcpupin vcpu='0' cpuset='130'/>
+    <vcpupin vcpu='1' cpuset='131'/>
+  </cputune>
+  <resource>
+    <partition>/machine</partition>
+  </resource>
+  <os>
+    <type arch='x86_64' machine='pc-i440fx-jammy'>hvm</type>
+    <kernel>/tmp/control_zone/kernels/cfs-virt</kernel>
+    <initrd>/tmp/control_zone/initramfs-virt</initrd>
+    <cmdline>vmlinuz-virt initrd=initramfs-virt root=LABEL=root rootfstype=ext4 modules=kms,scsi,virtio console=ttyS0</cmdline>
+    <boot dev='hd'/>
+  </os>
+  <features>
+    <acpi/>
+    <apic/>
+  </features>
+  <devices>
+    <emulator>/usr/bin/qemu-system-x86_64</emulator>
+    <disk type='file' device='disk'>
+      <driver name='qemu' type='qcow2'/>
+      <source file='/tmp/control_zone/images/alpine-uefi.qcow2' index='1'/>
+      <backingStore/>
+      <target dev='vda' bus='virtio'/>
+      <alias name='ua-box-volume-0'/>
+      <address type='pci' domain='0x0000' bus='0x00' slot='0x02' function='0x0'/>
+    </disk>
+    <controller type='usb' index='0' model='piix3-uhci'>
+      <alias name='usb'/>
+    </controller>
+    <filesystem type='mount' accessmode='mapped'>
+      <driver type='path' wrpolicy='immediate'/>
+      <source dir='/tmp/control_zone/controlzone'/>
+      <target dir='hostshare'/>
+      <alias name='fs0'/>
+    </filesystem>
+    <interface type='bridge'>
+      <mac address='52:54:00:6b:3c:58'/>
+      <source bridge='br0'/>
+      <target dev='vnet3'/>
+      <model type='virtio'/>
+      <alias name='ua-net-1'/>
+    </interface>
+    <serial type='pty'>
+      <source path='/dev/pts/3'/>
+      <target type='isa-serial' port='0'>
+        <model name='isa-serial'/>
+      </target>
+      <alias name='serial0'/>
+    </serial>
+    <input type='mouse' bus='ps2'>
+      <alias name='input0'/>
+    </input>
+    <input type='keyboard' bus='ps2'>
+      <alias name='input1'/>
+    </input>
+    <memballoon model='virtio'>
+      <stats period='4'/>
+      <alias name='balloon0'/>
+    </memballoon>
+  </devices>
+  <seclabel type='dynamic' model='apparmor' relabel='yes'/>
+</domain>";
+
+fn controlzone01() -> ControlZone {
+    ControlZone {
+        meta: Meta {
             name: String::from("controlzone01"),
             workdir: String::from("/tmp/control_zone/"),
             share_folder: String::from("/tmp/control_zone/controlzone"),
             full_config: String::from("nothing"),
         },
-        os: CZOS{
+        os: CZOS {
             kernel: String::from("/tmp/control_zone/kernels/cfs-virt"),
             initram_fs: Some(String::from("/tmp/control_zone/initramfs-virt")),
             rootfs: String::from("/tmp/control_zone/images/alpine-uefi.qcow2"),
             kcmdline: String::from("vmlinuz-virt initrd=initramfs-virt root=LABEL=root rootfstype=ext4 modules=kms,scsi,virtio console=ttyS0"),
         },
-        resource: Resource{
+        resource: Resource {
             cpus: vec![130, 131, 132, 133],
-            memory:4096,
+            memory: 4096,
             static_net: None,
+            emulator_cpuset: None,
+            resctrl: None,
+            memory_backing: None,
+            topology: None,
             cpuset: String::from("nothing"),
+            cpu_count: None,
+            placement: None,
+            emulator_cpus: vec![],
+            guest_topology: None,
         },
+        observability: Observability::disabled(),
         state: State::Created,
-        
-    };
+    }
+}
+
+#[test]
+fn test_to_xml() {
+    let controlzone = controlzone01();
+
+    let domain = DomainDef::from_cz(&controlzone);
+    assert_eq!(domain, DomainDef::from_xml(TARGET_XML).unwrap());
+    let xml = cz_to_xml(&controlzone).unwrap();
+    assert_eq!(DomainDef::from_xml(&xml).unwrap(), domain);
+    assert!(!xml.contains('#'));
+    assert!(!xml.contains("<address"));
+
+    let mut controlzone = controlzone;
+    controlzone.observability = Observability::default();
+    let perf_domain = DomainDef::from_cz(&controlzone);
+    assert_eq!(perf_domain, DomainDef::from_xml(TARGET_PERF_XML).unwrap());
+    let perf_xml = cz_to_xml(&controlzone).unwrap();
+    assert_eq!(DomainDef::from_xml(&perf_xml).unwrap(), perf_domain);
+}
+
+#[test]
+fn test_observability() {
+    let mut controlzone = controlzone01();
+    controlzone.observability.perf_events = vec![String::from("cpu_cycles")];
+    controlzone.observability.stats_period = 10;
+
+    let domain = DomainDef::from_cz(&controlzone);
+    let events = &domain.perf.as_ref().unwrap().events;
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].name, "cpu_cycles");
+    let memballoon = domain.devices.memballoon.as_ref().unwrap();
+    assert_eq!(memballoon.stats.as_ref().unwrap().period, 10);
+
+    // balloon stats only
+    controlzone.observability.perf_events.clear();
+    let domain = DomainDef::from_cz(&controlzone);
+    assert!(domain.perf.is_none());
+    assert!(domain.devices.memballoon.unwrap().stats.is_some());
+}
+
+#[test]
+fn test_memory_backing() {
+    let mut controlzone = controlzone01();
+    controlzone.resource.memory_backing = Some(MemoryBacking {
+        hugepages: Some(HugePageSize::Size1G),
+        locked: true,
+        numa_node: Some(1),
+    });
+
+    let xml = cz_to_xml(&controlzone).unwrap();
+    assert!(xml.contains("<memory unit=\"MiB\">4096</memory>"));
+    assert!(xml.contains(
+        "<memoryBacking><hugepages><page size=\"1048576\" unit=\"KiB\"/></hugepages><locked/></memoryBacking>"
+    ));
+    assert!(xml.contains("<numatune><memory mode=\"strict\" nodeset=\"1\"/></numatune>"));
+    let domain = DomainDef::from_cz(&controlzone);
+    assert_eq!(DomainDef::from_xml(&xml).unwrap(), domain);
+    // binding is part of drift
+    let mut live = domain.clone();
+    live.numatune = None;
+    assert_eq!(domain.diff(&live).unwrap()[0].field, "numa_node");
+
+    let args = qemu_args(&controlzone).unwrap();
+    assert_eq!(
+        arg_values(&args, "-machine"),
+        vec!["pc,accel=kvm,mem-merge=off,memory-backend=mem0"]
+    );
+    assert_eq!(
+        arg_values(&args, "-object"),
+        vec!["memory-backend-file,id=mem0,size=4096M,mem-path=/dev/hugepages1G,share=on,prealloc=on,host-nodes=1,policy=bind"]
+    );
+    assert_eq!(arg_values(&args, "-overcommit"), vec!["mem-lock=on"]);
+
+    // locking only keeps default memory
+    controlzone.resource.memory_backing = Some(MemoryBacking {
+        locked: true,
+        ..Default::default()
+    });
+    let domain = DomainDef::from_cz(&controlzone);
+    assert_eq!(domain.memory.unit, "MiB");
+    assert!(domain.numatune.is_none());
+    let args = qemu_args(&controlzone).unwrap();
+    assert!(arg_values(&args, "-object").is_empty());
+    assert_eq!(
+        arg_values(&args, "-machine"),
+        vec!["pc,accel=kvm,mem-merge=off"]
+    );
+}
+
+#[test]
+fn test_topology() {
+    let mut controlzone = controlzone01();
+    controlzone.resource.guest_topology = Some(Topology {
+        sockets: 1,
+        cores: 2,
+        threads: 2,
+        cells: vec![
+            NumaCell {
+                cpus: String::from("0-1"),
+                memory: 2048,
+            },
+            NumaCell {
+                cpus: String::from("2-3"),
+                memory: 2048,
+            },
+        ],
+    });
+
+    let xml = cz_to_xml(&controlzone).unwrap();
+    assert!(xml.contains("<memory unit=\"MiB\">4096</memory>"));
+    assert!(xml.contains("<cpu mode=\"host-passthrough\" check=\"none\"><topology sockets=\"1\" cores=\"2\" threads=\"2\"/>"));
+    assert!(xml.contains("<cell id=\"1\" cpus=\"2-3\" memory=\"2048\" unit=\"MiB\"/>"));
+    let domain = DomainDef::from_cz(&controlzone);
+    assert_eq!(DomainDef::from_xml(&xml).unwrap(), domain);
+    // a flat live domain drifts from topology
+    let live = DomainDef::from_cz(&controlzone01());
+    let diffs = domain.diff(&live).unwrap();
+    assert!(diffs.iter().any(|diff| diff.field == "topology"));
+
+    let args = qemu_args(&controlzone).unwrap();
+    assert_eq!(
+        arg_values(&args, "-smp"),
+        vec!["4,sockets=1,cores=2,threads=2"]
+    );
+    assert_eq!(
+        arg_values(&args, "-object"),
+        vec![
+            "memory-backend-ram,id=mem0,size=2048M",
+            "memory-backend-ram,id=mem1,size=2048M"
+        ]
+    );
+    assert_eq!(
+        arg_values(&args, "-numa"),
+        vec![
+            "node,nodeid=0,cpus=0-1,memdev=mem0",
+            "node,nodeid=1,cpus=2-3,memdev=mem1"
+        ]
+    );
+    assert_eq!(
+        arg_values(&args, "-machine"),
+        vec!["pc,accel=kvm,mem-merge=off"]
+    );
+
+    // hugepages of cells
+    controlzone.resource.memory_backing = Some(MemoryBacking {
+        hugepages: Some(HugePageSize::Size2M),
+        ..Default::default()
+    });
+    let args = qemu_args(&controlzone).unwrap();
+    assert_eq!(
+        arg_values(&args, "-object")[1],
+        "memory-backend-file,id=mem1,size=2048M,mem-path=/dev/hugepages,share=on,prealloc=on"
+    );
+}
+
+#[test]
+fn test_xml_escape() {
+    let mut controlzone = controlzone01();
+    controlzone.meta.name = String::from("cz<'&'>");
+    controlzone.os.kcmdline = String::from("console=ttyS0 quiet\" init=/bin/sh");
+
+    let xml = cz_to_xml(&controlzone).unwrap();
+    assert!(xml.contains("<name>cz&lt;&apos;&amp;&apos;&gt;</name>"));
+
+    let domain = DomainDef::from_xml(&xml).unwrap();
+    assert_eq!(domain.name, controlzone.meta.name);
+    assert_eq!(domain.os.cmdline.unwrap(), controlzone.os.kcmdline);
+}
+
+#[test]
+fn test_disk_snapshot_xml() {
+    let xml = disk_snapshot_xml("snap01");
+    assert!(xml.contains("<name>snap01</name>"));
+    assert!(xml.contains("<memory snapshot='no'/>"));
+
+    // only rootfs of domain is snapshotted, not the share folder
+    let domain = DomainDef::from_cz(&controlzone01());
+    let rootfs = domain.devices.disks[0].target.as_ref().unwrap();
+    let dev = rootfs.dev.as_deref().unwrap();
+    assert!(xml.contains(&format!(
+        "<disks><disk name='{dev}' snapshot='internal'/></disks>"
+    )));
+}
+
+#[test]
+fn test_live_diff() {
+    let controlzone = controlzone01();
+    let live = DomainDef::from_xml(LIVE_XML).unwrap();
+    assert_eq!(live.os.kind.machine.as_deref(), Some("pc-i440fx-jammy"));
+    assert_eq!(live.devices.inputs.len(), 2);
+
+    let diffs = DomainDef::from_cz(&controlzone).diff(&live).unwrap();
+    let fields: Vec<&str> = diffs.iter().map(|diff| diff.field.as_str()).collect();
+    assert_eq!(fields, vec!["memory", "vcpus", "vcpupin"]);
+    assert_eq!(diffs[0].config, "4194304KiB");
+    assert_eq!(diffs[0].runtime, "2000000KiB");
+
+    assert!(DomainDef::from_cz(&controlzone)
+        .diff(&DomainDef::from_xml(TARGET_XML).unwrap())
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_emulatorpin() {
+    let mut controlzone = controlzone01();
+    controlzone.resource.emulator_cpuset = Some(String::from("0-1,4"));
+    controlzone.resource.emulator_cpus = vec![0, 1, 4];
+
+    let domain = DomainDef::from_cz(&controlzone);
+    let xml = domain.to_xml().unwrap();
+    assert!(xml.contains("<emulatorpin cpuset=\"0,1,4\"/>"));
+    assert_eq!(DomainDef::from_xml(&xml).unwrap(), domain);
+
+    // live domain reports folded cpuset
+    let mut live = domain.clone();
+    live.cputune.as_mut().unwrap().emulatorpin = Some(EmulatorPin {
+        cpuset: String::from("0-1,4"),
+    });
+    assert!(domain.diff(&live).unwrap().is_empty());
+
+    live.cputune.as_mut().unwrap().emulatorpin = None;
+    let diffs = domain.diff(&live).unwrap();
+    assert_eq!(diffs.len(), 1);
+    assert_eq!(diffs[0].field, "emulatorpin");
+    assert_eq!(diffs[0].config, "0,1,4");
+    assert_eq!(diffs[0].runtime, "");
+}
+
+/// serve one qmp connection with canned replies
+fn fake_qmp_server(sock: &std::path::Path) -> thread::JoinHandle<Vec<String>> {
+    let _ = std::fs::remove_file(sock);
+    let listener = UnixListener::bind(sock).unwrap();
+
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let reader = BufReader::new(stream);
+        writeln!(
+            writer,
+            r#"{{"QMP": {{"version": {{"qemu": {{"micro": 0, "minor": 2, "major": 8}}}}, "capabilities": []}}}}"#
+        )
+        .unwrap();
+
+        let mut executed = vec![];
+        for line in reader.lines() {
+            let request: Value = serde_json::from_str(&line.unwrap()).unwrap();
+            let cmd = request["execute"].as_str().unwrap().to_owned();
+            let reply = match cmd.as_str() {
+                "query-status" => {
+                    writeln!(writer, r#"{{"event": "RESUME", "timestamp": {{"seconds": 0, "microseconds": 0}}}}"#).unwrap();
+                    r#"{"return": {"running": true, "singlestep": false, "status": "running"}}"#.to_owned()
+                }
+                "query-cpus-fast" => r#"{"return": [
+                    {"cpu-index": 0, "thread-id": 1001, "qom-path": "/machine/unattached/device[0]", "target": "x86_64"},
+                    {"cpu-index": 1, "thread-id": 1002, "qom-path": "/machine/unattached/device[1]", "target": "x86_64"}]}"#
+                    .replace('\n', ""),
+                "query-balloon" => r#"{"return": {"actual": 1073741824}}"#.to_owned(),
+                "balloon" => {
+                    assert_eq!(request["arguments"]["value"], 1 << 29);
+                    r#"{"return": {}}"#.to_owned()
+                }
+                "qom-set" => {
+                    assert_eq!(request["arguments"]["property"], "guest-stats-polling-interval");
+                    assert_eq!(request["arguments"]["value"], 4);
+                    r#"{"return": {}}"#.to_owned()
+                }
+                "blockdev-snapshot-internal-sync" => {
+                    assert_eq!(request["arguments"]["device"], "hd");
+                    r#"{"return": {}}"#.to_owned()
+                }
+                "qmp_capabilities" | "stop" | "cont" | "system_powerdown" => {
+                    r#"{"return": {}}"#.to_owned()
+                }
+                _ => r#"{"error": {"class": "CommandNotFound", "desc": "not found"}}"#.to_owned(),
+            };
+            writeln!(writer, "{}", reply).unwrap();
+            executed.push(cmd);
+        }
+        executed
+    })
+}
+
+/// value following `arg` in qemu command line
+fn arg_values<'a>(args: &'a [String], arg: &str) -> Vec<&'a str> {
+    args.windows(2)
+        .filter(|w| w[0] == arg)
+        .map(|w| w[1].as_str())
+        .collect()
+}
+
+#[test]
+fn test_qemu_args() {
+    let mut controlzone = controlzone01();
+    let args = qemu_args(&controlzone).unwrap();
+
+    assert_eq!(
+        arg_values(&args, "-initrd"),
+        vec!["/tmp/control_zone/initramfs-virt"]
+    );
+    assert_eq!(arg_values(&args, "-smp"), vec!["4"]);
+    assert_eq!(arg_values(&args, "-m"), vec!["4096"]);
+    assert_eq!(
+        arg_values(&args, "-chardev"),
+        vec!["file,id=console,path=/tmp/control_zone/console.log"]
+    );
+    assert_eq!(arg_values(&args, "-serial"), vec!["chardev:console"]);
+
+    // dynamic ip through user-mode nat, bridge is kept as libvirt does
+    assert_eq!(
+        arg_values(&args, "-netdev"),
+        vec!["user,id=net0", "bridge,br=br0,id=net1"]
+    );
+
+    controlzone.os.initram_fs = None;
+    controlzone.resource.static_net = Some(libcz::resource::StaticNet {
+        address: String::from("192.168.1.10"),
+        netmask: String::from("255.255.255.0"),
+        gateway: String::from("192.168.1.2"),
+    });
+    let args = qemu_args(&controlzone).unwrap();
+    assert!(arg_values(&args, "-initrd").is_empty());
+    assert_eq!(arg_values(&args, "-netdev"), vec!["bridge,br=br0,id=net1"]);
+}
+
+#[test]
+fn test_qmp_client() {
+    let sock = std::env::temp_dir().join("cz_test_qmp.sock");
+    let server = fake_qmp_server(&sock);
+
+    let mut qmp = QmpClient::connect(&sock).unwrap();
+    assert_eq!(
+        qmp.query_status().unwrap(),
+        StatusInfo {
+            running: true,
+            status: String::from("running"),
+        }
+    );
+
+    let cpus = qmp.query_cpus_fast().unwrap();
+    assert_eq!(
+        cpus[1],
+        CpuInfoFast {
+            cpu_index: 1,
+            thread_id: 1002,
+            qom_path: String::from("/machine/unattached/device[1]"),
+        }
+    );
+
+    assert_eq!(qmp.query_balloon().unwrap().actual, 1 << 30);
+    qmp.balloon(1 << 29).unwrap();
+    qmp.qom_set(
+        "/machine/peripheral/balloon",
+        "guest-stats-polling-interval",
+        serde_json::json!(4),
+    )
+    .unwrap();
+    qmp.stop().unwrap();
+    qmp.cont().unwrap();
+    qmp.system_powerdown().unwrap();
+    qmp.blockdev_snapshot_internal_sync("hd", "base").unwrap();
+    assert!(qmp.query_memory_size_summary().is_err());
+
+    drop(qmp);
+    assert_eq!(
+        server.join().unwrap(),
+        vec![
+            "qmp_capabilities",
+            "query-status",
+            "query-cpus-fast",
+            "query-balloon",
+            "balloon",
+            "qom-set",
+            "stop",
+            "cont",
+            "system_powerdown",
+            "blockdev-snapshot-internal-sync",
+            "query-memory-size-summary",
+        ]
+    );
+    std::fs::remove_file(sock).unwrap();
+}
+
+fn typed_param(field: &str, type_: u32) -> virTypedParameter {
+    let mut param: virTypedParameter = unsafe { std::mem::zeroed() };
+    for (i, c) in field.bytes().enumerate() {
+        param.field[i] = c as libc::c_char;
+    }
+    param.type_ = type_ as libc::c_int;
+    param
+}
+
+#[test]
+fn test_typed_params() {
+    let mut cycles = typed_param("perf.cpu_cycles", VIR_TYPED_PARAM_ULLONG);
+    cycles.value.ul = 123456789;
+    let mut current = typed_param("balloon.current", VIR_TYPED_PARAM_ULLONG);
+    current.value.ul = 4194304;
+    let mut vcpus = typed_param("vcpu.current", VIR_TYPED_PARAM_UINT);
+    vcpus.value.ui = 4;
+    let mut rate = typed_param("dirtyrate.calc_rate", VIR_TYPED_PARAM_DOUBLE);
+    rate.value.d = 0.5;
+    let name = typed_param("block.0.name", VIR_TYPED_PARAM_STRING);
+
+    let fields = typed_params(&[cycles, current, vcpus, rate, name]);
+    assert_eq!(fields.len(), 4);
+    assert_eq!(fields["perf.cpu_cycles"], 123456789.0);
+    assert_eq!(fields["balloon.current"], 4194304.0);
+    assert_eq!(fields["vcpu.current"], 4.0);
+    assert_eq!(fields["dirtyrate.calc_rate"], 0.5);
+    assert!(!fields.contains_key("block.0.name"));
+}
+
+#[test]
+fn test_zone_cgroup() {
+    let root = std::env::temp_dir().join("cz_test_cgroupfs");
+    if root.exists() {
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+    std::fs::create_dir_all(&root).unwrap();
+
+    let mut resource = controlzone01().resource;
+    resource.cpus = vec![2, 3];
+    resource.memory = 512;
+
+    // cgroup v2 not mounted
+    let cgroup = ZoneCgroup::new(&root, "cz01");
+    assert!(!cgroup.available());
+    assert!(cgroup.create(&resource).is_err());
+    assert!(!cgroup.exists());
 
-    let yaml = serde_yaml::to_string(&controlzone).unwrap();
-    println!("{}", yaml);
+    std::fs::write(root.join("cgroup.controllers"), "cpuset cpu memory").unwrap();
+    cgroup.create(&resource).unwrap();
+    assert_eq!(cgroup.path(), "/controlzone.slice/cz01");
 
+    let read = |file: &str| std::fs::read_to_string(cgroup.dir().join(file)).unwrap();
+    assert_eq!(read("cgroup.subtree_control"), "+cpu +cpuset");
+    assert_eq!(read("memory.max"), ((512 + 256) << 20).to_string());
+    // two vcpus and a floating emulator
+    assert_eq!(read("cpu.max"), "300000 100000");
+    assert_eq!(read("cpuset.cpus"), "");
+    assert_eq!(read("emulator/cgroup.type"), "threaded");
+    assert_eq!(read("emulator/cpuset.cpus"), "");
+    assert_eq!(read("vcpu0/cgroup.type"), "threaded");
+    assert_eq!(read("vcpu0/cpuset.cpus"), "2");
+    assert_eq!(read("vcpu1/cpuset.cpus"), "3");
+    assert_eq!(
+        std::fs::read_to_string(root.join("controlzone.slice/cgroup.subtree_control")).unwrap(),
+        "+cpu +cpuset +memory"
+    );
 
+    cgroup
+        .place(100, &[101, 102], &[100, 101, 102, 103])
+        .unwrap();
+    assert_eq!(read("cgroup.procs"), "100");
+    assert_eq!(read("emulator/cgroup.threads"), "100\n103\n");
+    assert_eq!(read("vcpu0/cgroup.threads"), "101\n");
+    assert_eq!(read("vcpu1/cgroup.threads"), "102\n");
 
-    let xml = cz_to_xml(&controlzone, false).unwrap();
-    assert_eq!(xml, TARGET_XML);
+    // pinned emulator is included in cpuset of zone
+    resource.emulator_cpus = vec![0, 3];
+    cgroup.apply(&resource).unwrap();
+    assert_eq!(read("cpu.max"), "400000 100000");
+    assert_eq!(read("cpuset.cpus"), "0,2,3");
+    assert_eq!(read("emulator/cpuset.cpus"), "0,3");
 
-    let perf_xml = cz_to_xml(&controlzone, true).unwrap();
-    assert_eq!(perf_xml, TARGET_PERF_XML);
+    // memory is lowered apart from cpus, after balloon deflated
+    resource.memory = 256;
+    resource.emulator_cpus = vec![];
+    cgroup.apply_cpus(&resource).unwrap();
+    assert_eq!(read("memory.max"), ((512 + 256) << 20).to_string());
+    assert_eq!(read("cpu.max"), "300000 100000");
+    cgroup.apply_memory(resource.memory).unwrap();
+    assert_eq!(read("memory.max"), ((256 + 256) << 20).to_string());
 
+    std::fs::remove_dir_all(root).unwrap();
 }
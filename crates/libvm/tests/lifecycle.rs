@@ -0,0 +1,144 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use libcz::{lock::LockWait, state::State, ControlZone, UpdateMode, POD_APPLY_DIR, POD_DIR};
+
+const WAIT: Duration = Duration::from_secs(10);
+
+/// write config of a control zone under `root`, rootfs is a dummy file
+fn config(root: &PathBuf, name: &str, cpuset: &str, memory: u32) -> PathBuf {
+    fs::create_dir_all(root).unwrap();
+    let rootfs = root.join("rootfs.img");
+    fs::write(&rootfs, "rootfs").unwrap();
+
+    let config = root.join(format!("{name}.yaml"));
+    fs::write(
+        &config,
+        format!(
+            "meta:
+  name: {name}
+  workdir: {}
+os:
+  kernel: /boot/vmlinuz
+  rootfs: {}
+  kcmdline: console=ttyS0
+resource:
+  cpuset: \"{cpuset}\"
+  memory: {memory}
+",
+            root.join(name).display(),
+            rootfs.display()
+        ),
+    )
+    .unwrap();
+    config
+}
+
+/// config of a created control zone, with rootfs already copied
+fn new_config(cz: &ControlZone, root: &PathBuf, cpuset: &str, memory: u32) -> ControlZone {
+    let mut new_cz =
+        ControlZone::new_from_config(&config(root, &cz.meta.name, cpuset, memory)).unwrap();
+    new_cz.os.rootfs = cz.os.rootfs.clone();
+    new_cz
+}
+
+fn fresh_root(name: &str) -> PathBuf {
+    let root = std::env::temp_dir().join(name);
+    if root.exists() {
+        fs::remove_dir_all(&root).unwrap();
+    }
+    root
+}
+
+#[test]
+fn test_fake_lifecycle() {
+    let root = fresh_root("cz_fake_lifecycle");
+    let vruntime = libvm::new_fake_vruntime();
+
+    // apply
+    let mut cz = ControlZone::new_from_config(&config(&root, "fake01", "0", 512)).unwrap();
+    assert_eq!(cz.state, State::Pending);
+    cz.create().unwrap();
+    assert_eq!(cz.state, State::Created);
+    cz.start(Some(WAIT), LockWait::NoWait, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Running);
+    assert_eq!(vruntime.status(&cz).unwrap(), State::Running);
+
+    // persisted state survives reload
+    let full_config = PathBuf::from(&cz.meta.full_config);
+    let mut cz = ControlZone::new_from_full_config(&full_config).unwrap();
+    assert_eq!(cz.state, State::Running);
+    assert_eq!(cz.reconcile(&vruntime).unwrap(), None);
+
+    // pod add and delete through control channel
+    let mut client = cz.channel(WAIT).unwrap();
+    assert!(client.ping().is_ok());
+    assert!(client.info().unwrap().validate().is_ok());
+    client
+        .pod_apply("nginx.yaml", String::from("kind: Pod"))
+        .unwrap();
+    assert_eq!(client.pod_list().unwrap(), vec![String::from("nginx.yaml")]);
+    assert!(PathBuf::from(&cz.meta.share_folder)
+        .join(POD_DIR)
+        .join(POD_APPLY_DIR)
+        .join("nginx.yaml")
+        .exists());
+    assert!(client.pod_apply("nginx.yaml", String::new()).is_err());
+    client.pod_down("nginx.yaml").unwrap();
+    assert!(client.pod_list().unwrap().is_empty());
+    drop(client);
+
+    // shrinking resource is applied live
+    let new_cz = new_config(&cz, &root, "0", 256);
+    assert_eq!(cz.update(new_cz, &vruntime).unwrap(), UpdateMode::Hot);
+    assert_eq!(cz.resource.memory, 256);
+    assert_eq!(cz.state, State::Running);
+
+    // growing vcpus needs a reboot
+    let new_cz = new_config(&cz, &root, "0-1", 256);
+    assert_eq!(cz.update(new_cz, &vruntime).unwrap(), UpdateMode::Reboot);
+    cz.stop(WAIT, false, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Stopped);
+    cz.start(Some(WAIT), LockWait::NoWait, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Running);
+    assert_eq!(cz.resource.cpus, vec![0, 1]);
+
+    // pause and resume
+    cz.pause(&vruntime).unwrap();
+    assert_eq!(vruntime.status(&cz).unwrap(), State::Paused);
+    cz.resume(&vruntime).unwrap();
+    assert_eq!(vruntime.status(&cz).unwrap(), State::Running);
+
+    // graceful stop through control channel
+    cz.stop(WAIT, false, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Stopped);
+    assert_eq!(vruntime.status(&cz).unwrap(), State::Stopped);
+    assert!(cz.channel(WAIT).is_none());
+
+    // remove
+    cz.remove().unwrap();
+    assert_eq!(cz.state, State::Zombied);
+    assert!(!PathBuf::from(&cz.meta.workdir).exists());
+
+    fs::remove_dir_all(root).unwrap();
+}
+
+#[test]
+fn test_fake_killed() {
+    let root = fresh_root("cz_fake_killed");
+    let vruntime = libvm::new_fake_vruntime();
+
+    let mut cz = ControlZone::new_from_config(&config(&root, "fake02", "0", 512)).unwrap();
+    cz.create().unwrap();
+    cz.start(Some(WAIT), LockWait::NoWait, &vruntime).unwrap();
+
+    // fake domain of a zone could only be started once
+    assert!(vruntime.start(&mut cz).is_err());
+
+    // stopped by force
+    cz.stop(WAIT, true, &vruntime).unwrap();
+    assert_eq!(cz.state, State::Killed);
+    assert_eq!(vruntime.status(&cz).unwrap(), State::Stopped);
+
+    cz.remove().unwrap();
+    fs::remove_dir_all(root).unwrap();
+}